@@ -150,6 +150,19 @@ impl RingBuffer {
         start == end
     }
 
+    /// Return the number of bytes in the buffer.
+    pub fn occupied(&self) -> usize {
+        let len = self.len.load(Ordering::Relaxed);
+        let start = self.start.load(Ordering::Relaxed);
+        let end = self.end.load(Ordering::Relaxed);
+
+        if end >= start {
+            end - start
+        } else {
+            end + len * 2 - start
+        }
+    }
+
     fn wrap(&self, mut n: usize) -> usize {
         let len = self.len.load(Ordering::Relaxed);
 
@@ -407,6 +420,7 @@ mod tests {
 
             assert_eq!(rb.is_empty(), false);
             assert_eq!(rb.is_full(), true);
+            assert_eq!(rb.occupied(), 4);
 
             rb.writer().push(|buf| {
                 // If it's full, we can push 0 bytes.
@@ -425,6 +439,7 @@ mod tests {
 
             assert_eq!(rb.is_empty(), false);
             assert_eq!(rb.is_full(), false);
+            assert_eq!(rb.occupied(), 3);
 
             rb.reader().pop(|buf| {
                 assert_eq!(3, buf.len());
@@ -478,6 +493,7 @@ mod tests {
 
             assert_eq!(rb.is_empty(), false);
             assert_eq!(rb.is_full(), true);
+            assert_eq!(rb.occupied(), 4);
         }
     }
 
@@ -743,6 +743,57 @@ pub fn clk_sys_freq() -> u32 {
     CLOCKS.sys.load(Ordering::Relaxed)
 }
 
+/// The SYS clock's divider: an integer part and a fraction, of 256 on RP2040
+/// and of 65536 on RP235x.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SysClkDiv {
+    /// Integer part, never 0.
+    pub int: u32,
+    /// Fractional part.
+    pub frac: u16,
+}
+
+impl SysClkDiv {
+    /// A whole number divider.
+    pub const fn new(int: u32) -> Self {
+        Self { int, frac: 0 }
+    }
+
+    fn fixed(self) -> u64 {
+        #[cfg(feature = "rp2040")]
+        let shift = 8;
+        #[cfg(feature = "_rp235x")]
+        let shift = 16;
+        (self.int as u64) << shift | self.frac as u64
+    }
+}
+
+/// Sets the SYS clock's divider, e.g. to slow SYS down while a core waits
+/// for interrupts, and returns the one it replaces, to be put back after.
+/// Peripherals clocked from SYS slow down with it: run PERI from
+/// [`PeriClkSrc::PllSys`] for UART and SPI to keep their rates. The timer
+/// runs from REF and keeps time.
+///
+/// Panics if `div.int` is 0.
+pub fn set_sys_clk_div(div: SysClkDiv) -> SysClkDiv {
+    assert!(div.int != 0, "SYS clock divider of 0");
+    let c = pac::CLOCKS;
+    let old = c.clk_sys_div().read();
+    let old = SysClkDiv {
+        int: old.int() as u32,
+        frac: old.frac() as u16,
+    };
+    let src_freq = CLOCKS.sys.load(Ordering::Relaxed) as u64 * old.fixed();
+
+    c.clk_sys_div().write(|w| {
+        w.set_int(div.int as _);
+        w.set_frac(div.frac as _);
+    });
+    CLOCKS.sys.store((src_freq / div.fixed()) as u32, Ordering::Relaxed);
+    old
+}
+
 /// REF clock frequency.
 pub fn clk_ref_freq() -> u32 {
     CLOCKS.reference.load(Ordering::Relaxed)
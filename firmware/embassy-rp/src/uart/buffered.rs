@@ -2,7 +2,7 @@
 use core::future::Future;
 use core::slice;
 
-use atomic_polyfill::AtomicU8;
+use atomic_polyfill::{AtomicBool, AtomicU8, AtomicUsize};
 use embassy_hal_internal::atomic_ring_buffer::RingBuffer;
 
 use super::*;
@@ -13,6 +13,16 @@ pub struct State {
     rx_waker: AtomicWaker,
     rx_buf: RingBuffer,
     rx_error: AtomicU8,
+    // FLOW_* bits, and the RX buffer levels of `FlowControl`
+    flow: AtomicU8,
+    rx_high: AtomicUsize,
+    rx_low: AtomicUsize,
+    // the peer has sent XOFF
+    tx_held: AtomicBool,
+    // the peer has been asked to stop
+    rx_held: AtomicBool,
+    // XON or XOFF to go out ahead of the TX buffer, or 0
+    tx_flow: AtomicU8,
 }
 
 // these must match bits 8..11 in UARTDR
@@ -21,6 +31,14 @@ const RXE_BREAK: u8 = 4;
 const RXE_PARITY: u8 = 2;
 const RXE_FRAMING: u8 = 1;
 
+const FLOW_XON_XOFF: u8 = 1;
+const FLOW_RTS: u8 = 2;
+
+/// Resume transmission (DC1, Ctrl+Q).
+pub const XON: u8 = 0x11;
+/// Pause transmission (DC3, Ctrl+S).
+pub const XOFF: u8 = 0x13;
+
 impl State {
     pub const fn new() -> Self {
         Self {
@@ -29,10 +47,55 @@ impl State {
             rx_waker: AtomicWaker::new(),
             tx_waker: AtomicWaker::new(),
             rx_error: AtomicU8::new(0),
+            flow: AtomicU8::new(0),
+            rx_high: AtomicUsize::new(0),
+            rx_low: AtomicUsize::new(0),
+            tx_held: AtomicBool::new(false),
+            rx_held: AtomicBool::new(false),
+            tx_flow: AtomicU8::new(0),
         }
     }
 }
 
+/// Flow control by the fill level of the RX buffer, set with
+/// [`BufferedUart::set_flow_control`].
+///
+/// The hardware RTS only follows the 32 byte RX FIFO, so a reader that
+/// falls behind still loses data once the buffer behind it is full. Here the
+/// peer is asked to stop once `high_water` bytes wait in the RX buffer, and
+/// to go on once the reader has taken it down to `low_water`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlowControl {
+    /// Send [`XOFF`] and [`XON`] in-band, and hold transmission from an
+    /// XOFF received until an XON. Received XON and XOFF are not buffered.
+    pub xon_xoff: bool,
+    /// Drive RTS by the RX buffer instead of the hardware. Needs an RTS pin.
+    pub rts: bool,
+    /// RX buffer level, in bytes, at which the peer is asked to stop.
+    pub high_water: usize,
+    /// RX buffer level, in bytes, at which the peer is asked to go on.
+    pub low_water: usize,
+}
+
+/// Flow control config error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlowControlError {
+    /// `low_water` has to be below `high_water`
+    Watermarks,
+}
+
+impl FlowControl {
+    /// No flow control beyond the hardware's.
+    pub const NONE: Self = Self {
+        xon_xoff: false,
+        rts: false,
+        high_water: usize::MAX,
+        low_water: 0,
+    };
+}
+
 /// Buffered UART driver.
 pub struct BufferedUart<'d, T: Instance> {
     pub(crate) rx: BufferedUartRx<'d, T>,
@@ -66,6 +129,11 @@ pub(crate) fn init_buffers<'d, T: Instance + 'd>(
         unsafe { state.rx_buf.init(rx_buffer.as_mut_ptr(), len) };
     }
 
+    state.flow.store(0, Ordering::Relaxed);
+    state.tx_held.store(false, Ordering::Relaxed);
+    state.rx_held.store(false, Ordering::Relaxed);
+    state.tx_flow.store(0, Ordering::Relaxed);
+
     // From the datasheet:
     // "The transmit interrupt is based on a transition through a level, rather
     // than on the level itself. When the interrupt and the UART is enabled
@@ -168,6 +236,57 @@ impl<'d, T: Instance> BufferedUart<'d, T> {
         super::Uart::<'d, T, Async>::set_baudrate_inner(baudrate);
     }
 
+    /// Sets baudrate, word length, stop bits and parity on runtime. The pin
+    /// inversions are left as they were set up.
+    pub fn set_config(&mut self, config: &Config) {
+        super::Uart::<'d, T, Async>::set_config_inner(config);
+    }
+
+    /// Turns RTS/CTS flow control on or off on runtime, for an instance
+    /// created with [`new_with_rtscts`](Self::new_with_rtscts).
+    pub fn set_rtscts(&mut self, enabled: bool) {
+        T::regs().uartcr().modify(|w| {
+            w.set_ctsen(enabled);
+            w.set_rtsen(enabled);
+        });
+    }
+
+    /// Sets flow control by the RX buffer on runtime. A peer that was asked
+    /// to stop is let go on. RTS driven by the buffer takes over from
+    /// [`set_rtscts`](Self::set_rtscts), so call this after it.
+    pub fn set_flow_control(&mut self, flow: FlowControl) -> Result<(), FlowControlError> {
+        if flow.low_water >= flow.high_water {
+            return Err(FlowControlError::Watermarks);
+        }
+
+        let state = T::buffered_state();
+        let bits = if flow.xon_xoff { FLOW_XON_XOFF } else { 0 } | if flow.rts { FLOW_RTS } else { 0 };
+        // The RX interrupt updates the same state and UARTCR.
+        critical_section::with(|_| {
+            let old = state.flow.swap(0, Ordering::Relaxed);
+
+            state.rx_high.store(flow.high_water, Ordering::Relaxed);
+            state.rx_low.store(flow.low_water, Ordering::Relaxed);
+            state.tx_held.store(false, Ordering::Relaxed);
+            if state.rx_held.swap(false, Ordering::Relaxed) && old & FLOW_XON_XOFF != 0 {
+                state.tx_flow.store(XON, Ordering::Relaxed);
+            }
+            if (old | bits) & FLOW_RTS != 0 {
+                T::regs().uartcr().modify(|w| {
+                    if flow.rts {
+                        w.set_rtsen(false);
+                    }
+                    w.set_rts(true);
+                });
+            }
+            state.flow.store(bits, Ordering::Relaxed);
+        });
+
+        // Send a pending XON, and anything held by an XOFF.
+        T::Interrupt::pend();
+        Ok(())
+    }
+
     /// Split into separate RX and TX handles.
     pub fn split(self) -> (BufferedUartTx<'d, T>, BufferedUartRx<'d, T>) {
         (self.tx, self.rx)
@@ -265,6 +384,7 @@ impl<'d, T: Instance> BufferedUartRx<'d, T> {
                 Some(e) => Err(e),
             }
         } else {
+            Self::resume_peer();
             Ok(n)
         };
 
@@ -318,6 +438,7 @@ impl<'d, T: Instance> BufferedUartRx<'d, T> {
         let state = T::buffered_state();
         let mut rx_reader = unsafe { state.rx_buf.reader() };
         rx_reader.pop_done(amt);
+        Self::resume_peer();
 
         // (Re-)Enable the interrupt to receive more data in case it was
         // disabled because the buffer was full or errors were detected.
@@ -328,6 +449,32 @@ impl<'d, T: Instance> BufferedUartRx<'d, T> {
         });
     }
 
+    // Lets a peer that was asked to stop go on, once the buffer has drained
+    // to the low water mark.
+    fn resume_peer() {
+        let state = T::buffered_state();
+        // Checked and released with the RX interrupt held off, or it could
+        // ask the peer to stop in between and be undone.
+        let resumed = critical_section::with(|_| {
+            if !state.rx_held.load(Ordering::Relaxed) || state.rx_buf.occupied() > state.rx_low.load(Ordering::Relaxed)
+            {
+                return false;
+            }
+            state.rx_held.store(false, Ordering::Relaxed);
+            let flow = state.flow.load(Ordering::Relaxed);
+            if flow & FLOW_RTS != 0 {
+                T::regs().uartcr().modify(|w| w.set_rts(true));
+            }
+            if flow & FLOW_XON_XOFF != 0 {
+                state.tx_flow.store(XON, Ordering::Relaxed);
+            }
+            flow & FLOW_XON_XOFF != 0
+        });
+        if resumed {
+            T::Interrupt::pend();
+        }
+    }
+
     /// we are ready to read if there is data in the buffer
     fn read_ready() -> Result<bool, Error> {
         let state = T::buffered_state();
@@ -543,13 +690,15 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for BufferedInterr
             warn!("Overrun error");
         }
 
+        let flow = s.flow.load(Ordering::Relaxed);
+
         // RX
         if s.rx_buf.is_available() {
             let mut rx_writer = unsafe { s.rx_buf.writer() };
             let rx_buf = rx_writer.push_slice();
             let mut n_read = 0;
             let mut error = false;
-            for rx_byte in rx_buf {
+            while n_read < rx_buf.len() {
                 if r.uartfr().read().rxfe() {
                     break;
                 }
@@ -564,7 +713,12 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for BufferedInterr
                     // this is consistent with blocking uart error reporting.
                     break;
                 }
-                *rx_byte = dr.data();
+                let byte = dr.data();
+                if flow & FLOW_XON_XOFF != 0 && (byte == XON || byte == XOFF) {
+                    s.tx_held.store(byte == XOFF, Ordering::Relaxed);
+                    continue;
+                }
+                rx_buf[n_read] = byte;
                 n_read += 1;
             }
             if n_read > 0 {
@@ -573,6 +727,19 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for BufferedInterr
             } else if error {
                 s.rx_waker.wake();
             }
+            // Ask the peer to stop before the buffer overflows.
+            if flow != 0
+                && !s.rx_held.load(Ordering::Relaxed)
+                && s.rx_buf.occupied() >= s.rx_high.load(Ordering::Relaxed)
+            {
+                s.rx_held.store(true, Ordering::Relaxed);
+                if flow & FLOW_RTS != 0 {
+                    r.uartcr().modify(|w| w.set_rts(false));
+                }
+                if flow & FLOW_XON_XOFF != 0 {
+                    s.tx_flow.store(XOFF, Ordering::Relaxed);
+                }
+            }
             // Disable any further RX interrupts when the buffer becomes full or
             // errors have occurred. This lets us buffer additional errors in the
             // fifo without needing more error storage locations, and most applications
@@ -586,8 +753,18 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for BufferedInterr
             }
         }
 
+        // XON or XOFF goes out ahead of the TX buffer, even while it's held.
+        let tx_flow = s.tx_flow.swap(0, Ordering::Relaxed);
+        if tx_flow != 0 {
+            if r.uartfr().read().txff() {
+                s.tx_flow.store(tx_flow, Ordering::Relaxed);
+            } else {
+                r.uartdr().write(|w| w.set_data(tx_flow));
+            }
+        }
+
         // TX
-        if s.tx_buf.is_available() {
+        if s.tx_buf.is_available() && !s.tx_held.load(Ordering::Relaxed) {
             let mut tx_reader = unsafe { s.tx_buf.reader() };
             let tx_buf = tx_reader.pop_slice();
             let mut n_written = 0;
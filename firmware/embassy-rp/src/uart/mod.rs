@@ -18,7 +18,9 @@ use crate::pac::io::vals::{Inover, Outover};
 use crate::{interrupt, pac, peripherals, Peripheral, RegExt};
 
 mod buffered;
-pub use buffered::{BufferedInterruptHandler, BufferedUart, BufferedUartRx, BufferedUartTx};
+pub use buffered::{
+    BufferedInterruptHandler, BufferedUart, BufferedUartRx, BufferedUartTx, FlowControl, FlowControlError, XOFF, XON,
+};
 
 /// Word length.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -1030,6 +1032,22 @@ impl<'d, T: Instance + 'd, M: Mode> Uart<'d, T, M> {
 
         Self::lcr_modify(|_| {});
     }
+
+    fn set_config_inner(config: &Config) {
+        Self::set_baudrate_inner(config.baudrate);
+
+        let (pen, eps) = match config.parity {
+            Parity::ParityNone => (false, false),
+            Parity::ParityOdd => (true, false),
+            Parity::ParityEven => (true, true),
+        };
+        Self::lcr_modify(|w| {
+            w.set_wlen(config.data_bits.bits());
+            w.set_stp2(config.stop_bits == StopBits::STOP2);
+            w.set_pen(pen);
+            w.set_eps(eps);
+        });
+    }
 }
 
 impl<'d, T: Instance, M: Mode> Uart<'d, T, M> {
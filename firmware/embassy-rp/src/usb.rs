@@ -14,6 +14,8 @@ use embassy_usb_driver::{
 use crate::interrupt::typelevel::{Binding, Interrupt};
 use crate::{interrupt, pac, peripherals, Peripheral, RegExt};
 
+pub mod host;
+
 trait SealedInstance {
     fn regs() -> crate::pac::usb::Usb;
     fn dpram() -> crate::pac::usb_dpram::UsbDpram;
@@ -243,7 +245,7 @@ impl<'d, T: Instance> Driver<'d, T> {
     }
 }
 
-/// USB interrupt handler.
+/// USB interrupt handler, for both the device [`Driver`] and [`host::Host`].
 pub struct InterruptHandler<T: Instance> {
     _uart: PhantomData<T>,
 }
@@ -254,6 +256,11 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
         //let x = regs.istr().read().0;
         //trace!("USB IRQ: {:08x}", x);
 
+        if regs.main_ctrl().read().host_ndevice() {
+            host::on_interrupt::<T>();
+            return;
+        }
+
         let ints = regs.ints().read();
 
         if ints.bus_reset() {
@@ -486,7 +493,10 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     async fn disable(&mut self) {}
 
     async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
-        Err(Unsupported)
+        // Self-clearing: the SIE drives resume signalling on the bus, the
+        // host then takes over and resumes the device.
+        T::regs().sie_ctrl().write_set(|w| w.set_resume(true));
+        Ok(())
     }
 }
 
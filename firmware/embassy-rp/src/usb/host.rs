@@ -0,0 +1,718 @@
+//! USB host driver.
+//!
+//! Drives one full or low speed device plugged straight into the port, with
+//! no hubs: enumeration, control transfers on the controller's shared EPX
+//! endpoint, and interrupt IN endpoints that the controller polls by itself.
+//! [`BootKeyboard`] reads keyboards, and barcode scanners posing as one,
+//! through the boot protocol.
+//!
+//! The controller is either a device or a host. Use [`Host`] in place of the
+//! device [`Driver`](super::Driver), bound to the same
+//! [`InterruptHandler`](super::InterruptHandler).
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::Timer;
+
+use super::{EndpointBuffer, Instance, InterruptHandler, EP_MEMORY};
+use crate::interrupt::typelevel::{Binding, Interrupt};
+use crate::{pac, Peripheral, RegExt};
+
+// In host mode the DPRAM holds the SETUP packet at 0, the interrupt
+// endpoints' control registers where device endpoints 1 to 15 IN keep theirs,
+// EPX's buffer control where EP0 IN keeps its, and EPX's control register
+// where device mode has the EP0 buffer.
+const EPX_CONTROL: usize = 0x100;
+const EPX_BUFFER: u16 = 0x180;
+// 64 bytes each for interrupt endpoints 1 to 15
+const INTERRUPT_BUFFERS: u16 = 0x1c0;
+const INTERRUPT_COUNT: usize = 16;
+
+// the one device is always given this address
+const ADDRESS: u8 = 1;
+
+static BUS_WAKER: AtomicWaker = AtomicWaker::new();
+static EPX_WAKER: AtomicWaker = AtomicWaker::new();
+static INTERRUPT_WAKERS: [AtomicWaker; INTERRUPT_COUNT] = [const { AtomicWaker::new() }; INTERRUPT_COUNT];
+
+// standard requests and descriptor types
+const GET_DESCRIPTOR: u8 = 6;
+const SET_ADDRESS: u8 = 5;
+const SET_CONFIGURATION: u8 = 9;
+const DESCRIPTOR_DEVICE: u8 = 1;
+const DESCRIPTOR_CONFIGURATION: u8 = 2;
+const DESCRIPTOR_INTERFACE: u8 = 4;
+const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+// HID class requests
+const HID_SET_IDLE: u8 = 0x0a;
+const HID_SET_PROTOCOL: u8 = 0x0b;
+
+/// Host errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The device was unplugged.
+    Disconnected,
+    /// The device stalled the request.
+    Stall,
+    /// The device did not answer.
+    Timeout,
+    /// A packet came with the wrong data toggle.
+    DataSequence,
+    /// The data did not fit the buffer given.
+    BufferOverflow,
+    /// A descriptor was short or malformed.
+    InvalidDescriptor,
+    /// The device has no interface of the kind asked for.
+    NotFound,
+    /// All interrupt endpoints are in use, or one asked for more than 64 bytes.
+    EndpointAlloc,
+}
+
+/// Speed of the device attached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// 1.5 Mbit/s, as most keyboards.
+    Low,
+    /// 12 Mbit/s.
+    Full,
+}
+
+/// A control request, as sent in the SETUP packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Setup {
+    /// Direction, type and recipient.
+    pub request_type: u8,
+    /// Request code.
+    pub request: u8,
+    /// Request value.
+    pub value: u16,
+    /// Request index, often an interface or endpoint.
+    pub index: u16,
+    /// Length of the data stage.
+    pub length: u16,
+}
+
+impl Setup {
+    fn get_descriptor(kind: u8, length: u16) -> Self {
+        Self {
+            request_type: 0x80,
+            request: GET_DESCRIPTOR,
+            value: (kind as u16) << 8,
+            index: 0,
+            length,
+        }
+    }
+
+    fn standard(request: u8, value: u16) -> Self {
+        Self {
+            request_type: 0x00,
+            request,
+            value,
+            index: 0,
+            length: 0,
+        }
+    }
+
+    fn hid(request: u8, value: u16, interface: u8) -> Self {
+        Self {
+            request_type: 0x21,
+            request,
+            value,
+            index: interface as u16,
+            length: 0,
+        }
+    }
+
+    fn to_bytes(&self) -> [u8; 8] {
+        let [value_lo, value_hi] = self.value.to_le_bytes();
+        let [index_lo, index_hi] = self.index.to_le_bytes();
+        let [length_lo, length_hi] = self.length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value_lo,
+            value_hi,
+            index_lo,
+            index_hi,
+            length_lo,
+            length_hi,
+        ]
+    }
+}
+
+/// The device found by [`Host::enumerate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Device {
+    /// Address given to it.
+    pub address: u8,
+    /// Speed it runs at.
+    pub speed: Speed,
+    /// Vendor ID.
+    pub vendor_id: u16,
+    /// Product ID.
+    pub product_id: u16,
+    /// Value of its first configuration, for SET_CONFIGURATION.
+    pub configuration: u8,
+}
+
+// Host-mode interrupts, from `super::InterruptHandler` while the controller
+// is a host.  Transaction events are disabled until the transfer waiting on
+// them has looked at the status.
+pub(super) unsafe fn on_interrupt<T: Instance>() {
+    let regs = T::regs();
+    let ints = regs.ints().read();
+
+    if ints.host_conn_dis() {
+        regs.sie_status().write(|w| w.set_speed(0b11));
+        BUS_WAKER.wake();
+        EPX_WAKER.wake();
+        for w in &INTERRUPT_WAKERS {
+            w.wake()
+        }
+    }
+    if ints.trans_complete() || ints.stall() || ints.error_rx_timeout() || ints.error_data_seq() {
+        regs.inte().write_clear(|w| {
+            w.set_trans_complete(true);
+            w.set_stall(true);
+            w.set_error_rx_timeout(true);
+            w.set_error_data_seq(true);
+        });
+        EPX_WAKER.wake();
+    }
+    if ints.buff_status() {
+        let s = regs.buff_status().read();
+        regs.buff_status().write_value(s);
+
+        for i in 1..INTERRUPT_COUNT {
+            if s.ep_in(i) {
+                INTERRUPT_WAKERS[i].wake();
+            }
+        }
+    }
+}
+
+// What SIE_CTRL holds between transactions: SOFs to full speed devices,
+// keep-alives to low speed ones, and the pull-downs a host has
+fn base(w: &mut pac::usb::regs::SieCtrl) {
+    w.set_sof_en(true);
+    w.set_keep_alive_en(true);
+    w.set_pulldown_en(true);
+    w.set_ep0_int_1buf(true);
+}
+
+/// RP USB host driver handle.
+pub struct Host<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    max_packet_size_0: u16,
+    // next interrupt endpoint to hand out
+    next_interrupt: usize,
+}
+
+impl<'d, T: Instance> Host<'d, T> {
+    /// Create a new USB host driver.
+    pub fn new(_usb: impl Peripheral<P = T> + 'd, _irq: impl Binding<T::Interrupt, InterruptHandler<T>>) -> Self {
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        let regs = T::regs();
+        unsafe {
+            // zero fill regs
+            let p = regs.as_ptr() as *mut u32;
+            for i in 0..0x9c / 4 {
+                p.add(i).write_volatile(0)
+            }
+
+            // zero fill the SETUP packet and control registers
+            let p = EP_MEMORY as *mut u32;
+            for i in 0..0x180 / 4 {
+                p.add(i).write_volatile(0)
+            }
+        }
+
+        regs.usb_muxing().write(|w| {
+            w.set_to_phy(true);
+            w.set_softcon(true);
+        });
+        regs.usb_pwr().write(|w| {
+            w.set_vbus_detect(true);
+            w.set_vbus_detect_override_en(true);
+        });
+        regs.main_ctrl().write(|w| {
+            w.set_controller_en(true);
+            w.set_host_ndevice(true);
+        });
+        regs.sie_ctrl().write(base);
+
+        // EPX stays a control endpoint with one buffer
+        let mut epx = pac::usb_dpram::regs::EpControl(0);
+        epx.set_enable(true);
+        epx.set_interrupt_per_buff(true);
+        epx.set_endpoint_type(pac::usb_dpram::vals::EpControlEndpointType::CONTROL);
+        epx.set_buffer_address(EPX_BUFFER);
+        unsafe { (EP_MEMORY.add(EPX_CONTROL) as *mut u32).write_volatile(epx.0) };
+
+        regs.inte().write(|w| {
+            w.set_host_conn_dis(true);
+            w.set_buff_status(true);
+        });
+
+        Self {
+            phantom: PhantomData,
+            max_packet_size_0: 8,
+            next_interrupt: 1,
+        }
+    }
+
+    /// Speed of the device attached, if there is one.
+    pub fn speed(&self) -> Option<Speed> {
+        match T::regs().sie_status().read().speed() {
+            1 => Some(Speed::Low),
+            2 => Some(Speed::Full),
+            _ => None,
+        }
+    }
+
+    /// Wait for a device to be plugged in.
+    pub async fn wait_connect(&mut self) -> Speed {
+        poll_fn(|cx| {
+            BUS_WAKER.register(cx.waker());
+            match self.speed() {
+                Some(speed) => Poll::Ready(speed),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait for the device to be unplugged.
+    pub async fn wait_disconnect(&mut self) {
+        poll_fn(|cx| {
+            BUS_WAKER.register(cx.waker());
+            match self.speed() {
+                Some(_) => Poll::Pending,
+                None => Poll::Ready(()),
+            }
+        })
+        .await
+    }
+
+    /// Reset the bus, leaving the device at address 0 with no interrupt
+    /// endpoints polled.
+    pub async fn reset(&mut self) {
+        let regs = T::regs();
+        regs.int_ep_ctrl().write(|w| w.set_int_ep_active(0));
+        for i in 1..INTERRUPT_COUNT {
+            T::dpram().ep_in_control(i - 1).write(|w| w.set_enable(false));
+        }
+        self.next_interrupt = 1;
+        self.max_packet_size_0 = 8;
+
+        regs.sie_ctrl().write(|w| {
+            base(w);
+            w.set_reset_bus(true);
+        });
+        Timer::after_millis(50).await;
+        // the device has 10ms to recover
+        Timer::after_millis(10).await;
+    }
+
+    /// Wait for a device, reset it and give it an address, then read its
+    /// configuration descriptor, with its interfaces and endpoints, into
+    /// `buf`.
+    pub async fn enumerate<'b>(&mut self, buf: &'b mut [u8]) -> Result<(Device, &'b [u8]), Error> {
+        let speed = self.wait_connect().await;
+        self.reset().await;
+
+        // the first 8 bytes of the device descriptor say how long packets
+        // on the control pipe may be
+        let mut descriptor = [0; 18];
+        self.control_in(0, &Setup::get_descriptor(DESCRIPTOR_DEVICE, 8), &mut descriptor[..8])
+            .await?;
+        self.max_packet_size_0 = match descriptor[7] {
+            size @ (8 | 16 | 32 | 64) => size as u16,
+            _ => return Err(Error::InvalidDescriptor),
+        };
+        self.control_out(0, &Setup::standard(SET_ADDRESS, ADDRESS as u16), &[])
+            .await?;
+        Timer::after_millis(2).await;
+
+        let n = self
+            .control_in(ADDRESS, &Setup::get_descriptor(DESCRIPTOR_DEVICE, 18), &mut descriptor)
+            .await?;
+        if n < 18 || descriptor[1] != DESCRIPTOR_DEVICE {
+            return Err(Error::InvalidDescriptor);
+        }
+
+        let mut header = [0; 9];
+        let n = self
+            .control_in(
+                ADDRESS,
+                &Setup::get_descriptor(DESCRIPTOR_CONFIGURATION, 9),
+                &mut header,
+            )
+            .await?;
+        if n < 9 || header[1] != DESCRIPTOR_CONFIGURATION {
+            return Err(Error::InvalidDescriptor);
+        }
+        let total = u16::from_le_bytes([header[2], header[3]]);
+        if total as usize > buf.len() {
+            return Err(Error::BufferOverflow);
+        }
+        let n = self
+            .control_in(
+                ADDRESS,
+                &Setup::get_descriptor(DESCRIPTOR_CONFIGURATION, total),
+                &mut buf[..total as usize],
+            )
+            .await?;
+
+        let device = Device {
+            address: ADDRESS,
+            speed,
+            vendor_id: u16::from_le_bytes([descriptor[8], descriptor[9]]),
+            product_id: u16::from_le_bytes([descriptor[10], descriptor[11]]),
+            configuration: header[5],
+        };
+        Ok((device, &buf[..n]))
+    }
+
+    /// Make a control request with an IN data stage, returning how much
+    /// was read into `buf`.
+    pub async fn control_in(&mut self, address: u8, setup: &Setup, buf: &mut [u8]) -> Result<usize, Error> {
+        self.setup(address, setup).await?;
+
+        let mut len = 0;
+        let mut pid = true;
+        loop {
+            let n = self.packet_in(pid, &mut buf[len..]).await?;
+            len += n;
+            pid = !pid;
+            if n < self.max_packet_size_0 as usize || len == buf.len() {
+                break;
+            }
+        }
+
+        // status stage
+        self.packet_out(true, &[]).await?;
+        Ok(len)
+    }
+
+    /// Make a control request, with `data` as its OUT data stage.
+    pub async fn control_out(&mut self, address: u8, setup: &Setup, data: &[u8]) -> Result<(), Error> {
+        self.setup(address, setup).await?;
+
+        let mut pid = true;
+        for chunk in data.chunks(self.max_packet_size_0 as usize) {
+            self.packet_out(pid, chunk).await?;
+            pid = !pid;
+        }
+
+        // status stage
+        let mut status = [0; 0];
+        self.packet_in(true, &mut status).await?;
+        Ok(())
+    }
+
+    /// Have the controller poll an interrupt IN endpoint of the device,
+    /// every `interval_ms` frames. Endpoints are freed again by
+    /// [`reset`](Self::reset).
+    pub fn interrupt_in(
+        &mut self,
+        address: u8,
+        endpoint: u8,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<InterruptIn<'d, T>, Error> {
+        let index = self.next_interrupt;
+        if index == INTERRUPT_COUNT || max_packet_size > 64 {
+            return Err(Error::EndpointAlloc);
+        }
+        self.next_interrupt += 1;
+
+        let addr = INTERRUPT_BUFFERS + (index as u16 - 1) * 64;
+        trace!(
+            "interrupt in {}: address={} endpoint={} mps={} interval_ms={}",
+            index,
+            address,
+            endpoint,
+            max_packet_size,
+            interval_ms
+        );
+
+        T::dpram().ep_in_control(index - 1).write(|w| {
+            w.set_enable(true);
+            w.set_interrupt_per_buff(true);
+            w.set_endpoint_type(pac::usb_dpram::vals::EpControlEndpointType::INTERRUPT);
+            w.set_buffer_address(addr);
+            // polling interval, in frames less one
+            w.0 |= (interval_ms.max(1) as u32 - 1) << 16;
+        });
+        let regs = T::regs();
+        regs.addr_endpx(index - 1).write(|w| {
+            w.set_address(address);
+            w.set_endpoint(endpoint);
+            w.set_intep_dir(true);
+        });
+        regs.int_ep_ctrl().write_set(|w| w.set_int_ep_active(1 << index));
+
+        Ok(InterruptIn {
+            phantom: PhantomData,
+            index,
+            max_packet_size,
+            buf: EndpointBuffer::new(addr, 64),
+            pid: false,
+        })
+    }
+
+    async fn setup(&mut self, address: u8, setup: &Setup) -> Result<(), Error> {
+        trace!("setup: address={} {:?}", address, setup);
+        T::regs().addr_endp().write(|w| {
+            w.set_address(address);
+            w.set_endpoint(0);
+        });
+        EndpointBuffer::<T>::new(0, 8).write(&setup.to_bytes());
+        self.transaction(|w| w.set_send_setup(true)).await
+    }
+
+    async fn packet_in(&mut self, pid: bool, buf: &mut [u8]) -> Result<usize, Error> {
+        let bufcontrol = T::dpram().ep_in_buffer_control(0);
+        bufcontrol.write(|w| {
+            w.set_pid(0, pid);
+            w.set_length(0, self.max_packet_size_0);
+            w.set_last(0, true);
+        });
+        cortex_m::asm::delay(12);
+        bufcontrol.write(|w| {
+            w.set_pid(0, pid);
+            w.set_length(0, self.max_packet_size_0);
+            w.set_last(0, true);
+            w.set_available(0, true);
+        });
+        self.transaction(|w| w.set_receive_data(true)).await?;
+
+        let rx_len = bufcontrol.read().length(0) as usize;
+        if rx_len > buf.len() {
+            return Err(Error::BufferOverflow);
+        }
+        EndpointBuffer::<T>::new(EPX_BUFFER, 64).read(&mut buf[..rx_len]);
+        Ok(rx_len)
+    }
+
+    async fn packet_out(&mut self, pid: bool, data: &[u8]) -> Result<(), Error> {
+        EndpointBuffer::<T>::new(EPX_BUFFER, 64).write(data);
+        let bufcontrol = T::dpram().ep_in_buffer_control(0);
+        bufcontrol.write(|w| {
+            w.set_pid(0, pid);
+            w.set_length(0, data.len() as _);
+            w.set_full(0, true);
+            w.set_last(0, true);
+        });
+        cortex_m::asm::delay(12);
+        bufcontrol.write(|w| {
+            w.set_pid(0, pid);
+            w.set_length(0, data.len() as _);
+            w.set_full(0, true);
+            w.set_last(0, true);
+            w.set_available(0, true);
+        });
+        self.transaction(|w| w.set_send_data(true)).await
+    }
+
+    // Starts one packet on EPX and waits for it to be acknowledged, stalled
+    // or lost.  NAKs the controller retries by itself.
+    async fn transaction(&mut self, kind: impl Fn(&mut pac::usb::regs::SieCtrl)) -> Result<(), Error> {
+        let regs = T::regs();
+        let clear = |w: &mut pac::usb::regs::SieStatus| {
+            w.set_trans_complete(true);
+            w.set_stall_rec(true);
+            w.set_rx_timeout(true);
+            w.set_data_seq_error(true);
+        };
+        regs.sie_status().write(clear);
+        regs.inte().write_set(|w| {
+            w.set_trans_complete(true);
+            w.set_stall(true);
+            w.set_error_rx_timeout(true);
+            w.set_error_data_seq(true);
+        });
+
+        regs.sie_ctrl().write(|w| {
+            base(w);
+            kind(w);
+        });
+        cortex_m::asm::delay(12);
+        regs.sie_ctrl().write(|w| {
+            base(w);
+            kind(w);
+            w.set_start_trans(true);
+        });
+
+        let result = poll_fn(|cx| {
+            EPX_WAKER.register(cx.waker());
+            let status = regs.sie_status().read();
+            if status.speed() == 0 {
+                Poll::Ready(Err(Error::Disconnected))
+            } else if status.stall_rec() {
+                Poll::Ready(Err(Error::Stall))
+            } else if status.rx_timeout() {
+                Poll::Ready(Err(Error::Timeout))
+            } else if status.data_seq_error() {
+                Poll::Ready(Err(Error::DataSequence))
+            } else if status.trans_complete() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        if result.is_err() {
+            regs.sie_ctrl().write(|w| {
+                base(w);
+                w.set_stop_trans(true);
+            });
+        }
+        regs.sie_status().write(clear);
+        result
+    }
+}
+
+/// Interrupt IN endpoint, polled by the controller.
+pub struct InterruptIn<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    index: usize,
+    max_packet_size: u16,
+    buf: EndpointBuffer<T>,
+    pid: bool,
+}
+
+impl<'d, T: Instance> InterruptIn<'d, T> {
+    /// Wait for the next packet the device sends, returning its length.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let bufcontrol = T::dpram().ep_in_buffer_control(self.index);
+        bufcontrol.write(|w| {
+            w.set_pid(0, self.pid);
+            w.set_length(0, self.max_packet_size);
+            w.set_last(0, true);
+        });
+        cortex_m::asm::delay(12);
+        bufcontrol.write(|w| {
+            w.set_pid(0, self.pid);
+            w.set_length(0, self.max_packet_size);
+            w.set_last(0, true);
+            w.set_available(0, true);
+        });
+
+        let index = self.index;
+        let val = poll_fn(|cx| {
+            INTERRUPT_WAKERS[index].register(cx.waker());
+            if T::regs().sie_status().read().speed() == 0 {
+                return Poll::Ready(Err(Error::Disconnected));
+            }
+            let val = bufcontrol.read();
+            if val.available(0) {
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(val))
+            }
+        })
+        .await?;
+
+        let rx_len = val.length(0) as usize;
+        if rx_len > buf.len() {
+            return Err(Error::BufferOverflow);
+        }
+        self.buf.read(&mut buf[..rx_len]);
+        self.pid = !self.pid;
+        Ok(rx_len)
+    }
+}
+
+/// HID keyboard in the boot protocol, as BIOSes read keyboards: whatever its
+/// report descriptor says, it sends 8 byte reports of the modifier bits, a
+/// reserved byte and up to six keycodes. Barcode scanners usually have such
+/// an interface too.
+pub struct BootKeyboard<'d, T: Instance> {
+    endpoint: InterruptIn<'d, T>,
+}
+
+impl<'d, T: Instance> BootKeyboard<'d, T> {
+    /// Configure the device and switch its first boot keyboard interface in
+    /// `config`, as returned by [`Host::enumerate`], to the boot protocol.
+    pub async fn new(host: &mut Host<'d, T>, device: &Device, config: &[u8]) -> Result<Self, Error> {
+        let (interface, endpoint, max_packet_size, interval) = find_boot_keyboard(config)?;
+        debug!(
+            "boot keyboard {:04x}:{:04x}, interface {} endpoint {}",
+            device.vendor_id, device.product_id, interface, endpoint
+        );
+
+        host.control_out(
+            device.address,
+            &Setup::standard(SET_CONFIGURATION, device.configuration as u16),
+            &[],
+        )
+        .await?;
+        host.control_out(device.address, &Setup::hid(HID_SET_PROTOCOL, 0, interface), &[])
+            .await?;
+        // reports only on change; not every keyboard takes this
+        match host
+            .control_out(device.address, &Setup::hid(HID_SET_IDLE, 0, interface), &[])
+            .await
+        {
+            Ok(()) | Err(Error::Stall) => {}
+            Err(e) => return Err(e),
+        }
+
+        let endpoint = host.interrupt_in(device.address, endpoint, max_packet_size, interval)?;
+        Ok(Self { endpoint })
+    }
+
+    /// Wait for the next report. A short one is padded with zeros.
+    pub async fn read(&mut self) -> Result<[u8; 8], Error> {
+        let mut buf = [0; 64];
+        let n = self.endpoint.read(&mut buf).await?;
+        let mut report = [0; 8];
+        let n = n.min(report.len());
+        report[..n].copy_from_slice(&buf[..n]);
+        Ok(report)
+    }
+}
+
+// The interface number, endpoint number, packet size and interval of the
+// first interface of class HID, subclass boot, protocol keyboard
+fn find_boot_keyboard(config: &[u8]) -> Result<(u8, u8, u16, u8), Error> {
+    let mut interface = None;
+    let mut rest = config;
+    while rest.len() >= 2 {
+        let len = rest[0] as usize;
+        if len < 2 || len > rest.len() {
+            return Err(Error::InvalidDescriptor);
+        }
+        let descriptor = &rest[..len];
+        rest = &rest[len..];
+
+        match descriptor[1] {
+            DESCRIPTOR_INTERFACE if len >= 9 => {
+                let boot_keyboard = descriptor[5..8] == [0x03, 0x01, 0x01];
+                interface = boot_keyboard.then_some(descriptor[2]);
+            }
+            DESCRIPTOR_ENDPOINT if len >= 7 => {
+                let Some(interface) = interface else { continue };
+                // an interrupt endpoint, IN
+                if descriptor[2] & 0x80 != 0 && descriptor[3] & 0x03 == 0x03 {
+                    let max_packet_size = u16::from_le_bytes([descriptor[4], descriptor[5]]) & 0x7ff;
+                    return Ok((interface, descriptor[2] & 0x0f, max_packet_size, descriptor[6]));
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(Error::NotFound)
+}
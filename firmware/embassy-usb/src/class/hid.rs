@@ -15,8 +15,6 @@ use crate::types::InterfaceNumber;
 use crate::{Builder, Handler};
 
 const USB_CLASS_HID: u8 = 0x03;
-const USB_SUBCLASS_NONE: u8 = 0x00;
-const USB_PROTOCOL_NONE: u8 = 0x00;
 
 // HID
 const HID_DESC_DESCTYPE_HID: u8 = 0x21;
@@ -31,6 +29,41 @@ const HID_REQ_SET_REPORT: u8 = 0x09;
 const HID_REQ_GET_PROTOCOL: u8 = 0x03;
 const HID_REQ_SET_PROTOCOL: u8 = 0x0b;
 
+/// HID interface subclass, see HID 1.11 section 4.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum HidSubclass {
+    /// No subclass, the host only uses the report descriptor.
+    No = 0x00,
+    /// Boot interface subclass, the device supports the boot protocol.
+    Boot = 0x01,
+}
+
+/// HID interface protocol for boot interfaces, see HID 1.11 section 4.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum HidBootProtocol {
+    /// Not a boot device.
+    None = 0x00,
+    /// Boot keyboard.
+    Keyboard = 0x01,
+    /// Boot mouse.
+    Mouse = 0x02,
+}
+
+/// Protocol selected by the host with SET_PROTOCOL, see HID 1.11 section 7.2.6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum HidProtocolMode {
+    /// Boot protocol: fixed reports as described in HID 1.11 appendix B.
+    Boot = 0x00,
+    /// Report protocol: reports as described by the report descriptor.
+    Report = 0x01,
+}
+
 /// Configuration for the HID class.
 pub struct Config<'d> {
     /// HID report descriptor.
@@ -48,6 +81,13 @@ pub struct Config<'d> {
 
     /// Max packet size for both the IN and OUT endpoints.
     pub max_packet_size: u16,
+
+    /// Interface subclass. Use [`HidSubclass::Boot`] together with a boot
+    /// protocol to let BIOSes and other simple hosts switch to boot reports.
+    pub hid_subclass: HidSubclass,
+
+    /// Interface protocol, only meaningful for [`HidSubclass::Boot`].
+    pub hid_boot_protocol: HidBootProtocol,
 }
 
 /// Report ID
@@ -109,10 +149,12 @@ fn build<'d, D: Driver<'d>>(
 ) -> (Option<D::EndpointOut>, D::EndpointIn, &'d AtomicUsize) {
     let len = config.report_descriptor.len();
 
-    let mut func = builder.function(USB_CLASS_HID, USB_SUBCLASS_NONE, USB_PROTOCOL_NONE);
+    let subclass = config.hid_subclass as u8;
+    let protocol = config.hid_boot_protocol as u8;
+    let mut func = builder.function(USB_CLASS_HID, subclass, protocol);
     let mut iface = func.interface();
     let if_num = iface.interface_number();
-    let mut alt = iface.alt_setting(USB_CLASS_HID, USB_SUBCLASS_NONE, USB_PROTOCOL_NONE, None);
+    let mut alt = iface.alt_setting(USB_CLASS_HID, subclass, protocol, None);
 
     // HID descriptor
     alt.descriptor(
@@ -147,6 +189,7 @@ fn build<'d, D: Driver<'d>>(
         config.report_descriptor,
         config.request_handler,
         &state.out_report_offset,
+        config.hid_subclass,
     ));
     builder.handler(control);
 
@@ -406,6 +449,20 @@ pub trait RequestHandler {
     fn set_idle_ms(&mut self, id: Option<ReportId>, duration_ms: u32) {
         let _ = (id, duration_ms);
     }
+
+    /// Get the protocol in use, only asked of [`HidSubclass::Boot`] interfaces.
+    fn get_protocol(&self) -> HidProtocolMode {
+        HidProtocolMode::Report
+    }
+
+    /// Switch to `protocol`, only asked of [`HidSubclass::Boot`] interfaces.
+    ///
+    /// Devices start out in report protocol, and are switched back to it
+    /// by a bus reset without this being called.
+    fn set_protocol(&mut self, protocol: HidProtocolMode) -> OutResponse {
+        let _ = protocol;
+        OutResponse::Rejected
+    }
 }
 
 struct Control<'d> {
@@ -413,6 +470,7 @@ struct Control<'d> {
     report_descriptor: &'d [u8],
     request_handler: Option<&'d mut dyn RequestHandler>,
     out_report_offset: &'d AtomicUsize,
+    hid_subclass: HidSubclass,
     hid_descriptor: [u8; 9],
 }
 
@@ -422,12 +480,14 @@ impl<'d> Control<'d> {
         report_descriptor: &'d [u8],
         request_handler: Option<&'d mut dyn RequestHandler>,
         out_report_offset: &'d AtomicUsize,
+        hid_subclass: HidSubclass,
     ) -> Self {
         Control {
             if_num,
             report_descriptor,
             request_handler,
             out_report_offset,
+            hid_subclass,
             hid_descriptor: [
                 // Length of buf inclusive of size prefix
                 9,
@@ -453,6 +513,11 @@ impl<'d> Control<'d> {
 impl<'d> Handler for Control<'d> {
     fn reset(&mut self) {
         self.out_report_offset.store(0, Ordering::Release);
+        if self.hid_subclass == HidSubclass::Boot {
+            if let Some(handler) = self.request_handler.as_mut() {
+                handler.set_protocol(HidProtocolMode::Report);
+            }
+        }
     }
 
     fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
@@ -482,11 +547,18 @@ impl<'d> Handler for Control<'d> {
                 _ => Some(OutResponse::Rejected),
             },
             HID_REQ_SET_PROTOCOL => {
-                if req.value == 1 {
-                    Some(OutResponse::Accepted)
-                } else {
-                    warn!("HID Boot Protocol is unsupported.");
-                    Some(OutResponse::Rejected) // UNSUPPORTED: Boot Protocol
+                let protocol = match req.value {
+                    0 => HidProtocolMode::Boot,
+                    1 => HidProtocolMode::Report,
+                    _ => return Some(OutResponse::Rejected),
+                };
+                match (self.hid_subclass, self.request_handler.as_mut()) {
+                    (HidSubclass::Boot, Some(handler)) => Some(handler.set_protocol(protocol)),
+                    _ if protocol == HidProtocolMode::Report => Some(OutResponse::Accepted),
+                    _ => {
+                        warn!("HID Boot Protocol is unsupported.");
+                        Some(OutResponse::Rejected)
+                    }
                 }
             }
             _ => Some(OutResponse::Rejected),
@@ -539,8 +611,10 @@ impl<'d> Handler for Control<'d> {
                         }
                     }
                     HID_REQ_GET_PROTOCOL => {
-                        // UNSUPPORTED: Boot Protocol
-                        buf[0] = 1;
+                        buf[0] = match (self.hid_subclass, self.request_handler.as_ref()) {
+                            (HidSubclass::Boot, Some(handler)) => handler.get_protocol() as u8,
+                            _ => HidProtocolMode::Report as u8,
+                        };
                         Some(InResponse::Accepted(&buf[0..1]))
                     }
                     _ => Some(InResponse::Rejected),
@@ -5,20 +5,31 @@ use embassy_executor::Spawner;
 use embassy_rp::{
     bind_interrupts,
     block::ImageDef,
-    gpio::{Flex, Pull},
+    clocks::{self, PeriClkSrc, SysClkDiv},
+    gpio::{AnyPin, Flex, Pull},
     peripherals::UART1,
-    uart::{BufferedInterruptHandler, BufferedUart, Config as UartConfig, StopBits, Parity},
+    uart::{BufferedInterruptHandler, BufferedUart, BufferedUartTx, Config as UartConfig, StopBits, Parity},
 };
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::channel;
-use embedded_io_async::Write;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Delay, Duration, Instant, Ticker};
+use embedded_io_async::{Read, Write};
+use heapless::Deque;
+use keyboard::combo::{Combo, Combos};
+use keyboard::debounce::{Debouncer, Eager};
+use keyboard::diagnostics::{self, Diagnostics, Findings};
+use keyboard::extrakey::Extras;
+use keyboard::idle::Idle;
+use keyboard::keymap::Action;
+use keyboard::layer::{Key, Layers, Output, TapHold};
+use keyboard::layouts::board::{self, COLUMNS, LAYERS, ROWS};
+use keyboard::matrix::{Matrix, PinMatrix, Scan};
+use keyboard::report::Keys;
+use keyboard::state::KeyState;
+use link::frame::{self, Decoder, Frame};
+use link::message::{Health, KeyStats, Message, Version, MATRIX_LEN};
+use link::session::{Error as LinkError, Link};
 use static_cell::StaticCell;
-use usbd_hid::descriptor::KeyboardReport;
-use usbd_hid::descriptor::KeyboardUsage;
 use {defmt_rtt as _, panic_probe as _};
-use 
-
-use ssmarshal::serialize;
 
 #[link_section = ".start_block"]
 #[used]
@@ -28,11 +39,24 @@ bind_interrupts!(struct Irqs {
     UART1_IRQ => BufferedInterruptHandler<UART1>;
 });
 
-static EVENT_CHANNEL: channel::Channel<CriticalSectionRawMutex, KeyboardUsage, 10> = channel::Channel::new();
+const COMBOS: &[Combo] = &[];
+
+// SYS while idle: 150 MHz down to 9.4 MHz, enough for the link's bytes
+const IDLE_SYS_DIV: SysClkDiv = SysClkDiv::new(16);
+
+type TeebLayers<'a> = Layers<'a, ROWS, COLUMNS, LAYERS>;
+type TeebDiagnostics = Diagnostics<ROWS, COLUMNS>;
+
+// the switches have to fit in a matrix message
+const _: () = assert!(ROWS * COLUMNS <= 8 * MATRIX_LEN);
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let p = embassy_rp::init(Default::default());
+    let mut config = embassy_rp::config::Config::default();
+    // PERI from the PLL, so the link's UART keeps its rate while SYS is
+    // slowed down, idle
+    config.clocks.peri_clk_src = Some(PeriClkSrc::PllSys);
+    let p = embassy_rp::init(config);
     let (tx_pin, rx_pin, uart) = (p.PIN_4, p.PIN_5, p.UART1);
 
 
@@ -44,276 +68,350 @@ async fn main(_spawner: Spawner) {
     config.parity = Parity::ParityEven;
     config.stop_bits = StopBits::STOP2;
     let uart = BufferedUart::new(uart, Irqs, tx_pin, rx_pin, tx_buf, rx_buf, config);
-    let (mut tx, rx) = uart.split();
-
-    let mut outputs = [
-        Flex::new(p.PIN_8),
-        Flex::new(p.PIN_9),
-        Flex::new(p.PIN_10),
-        Flex::new(p.PIN_11),
-        Flex::new(p.PIN_12),
-        Flex::new(p.PIN_13),
-        Flex::new(p.PIN_14),
-        Flex::new(p.PIN_15),
-        Flex::new(p.PIN_16),
-        Flex::new(p.PIN_17),
-        Flex::new(p.PIN_18),
-        Flex::new(p.PIN_19),
-        Flex::new(p.PIN_20),
-        Flex::new(p.PIN_21),
-    ];
+    let (tx, mut rx) = uart.split();
 
-    let mut inputs = [
-        Flex::new(p.PIN_3),
-        Flex::new(p.PIN_26),
-        Flex::new(p.PIN_22),
-        Flex::new(p.PIN_6),
-        Flex::new(p.PIN_7),
-    ];
+    // wired as the board's file in keyboard/layouts says.  The keyboard
+    // crate's build checks each pin is used once, is on the MCU and isn't
+    // one taken from `p` here: the link's UART on 4 and 5.
+    let mut columns = board::COLUMN_PINS.map(|pin| Flex::new(unsafe { AnyPin::steal(pin) }));
+    let mut rows = board::ROW_PINS.map(|pin| Flex::new(unsafe { AnyPin::steal(pin) }));
 
-    for input in &mut inputs {
-        input.set_low();
-        input.set_as_input();
-        input.set_schmitt(true);
-        input.set_pull(Pull::Down);
+    for row in &mut rows {
+        row.set_low();
+        row.set_as_input();
+        row.set_schmitt(true);
+        row.set_pull(Pull::Down);
     }
 
-    for output in &mut outputs {
-        output.set_as_output();
-        output.set_low();
+    for column in &mut columns {
+        column.set_as_output();
+        column.set_low();
     }
 
-    // you can maybe use variant_count
-    let mut state: [bool; 256] = [false; 256];
+    let mut matrix = PinMatrix::new(rows, columns, Delay, 5);
+    let mut debouncer: Debouncer<Eager, ROWS, COLUMNS> = Debouncer::new(Duration::from_millis(5));
+    // held for 10s is stuck, for a key test
+    let mut diagnostics: TeebDiagnostics = Diagnostics::new(Duration::from_millis(5), Duration::from_secs(10));
+    let mut state: KeyState<ROWS, COLUMNS> = KeyState::new();
+    let mut combos = Combos::new(COMBOS, Duration::from_millis(50));
+    let mut layers = Layers::new(board::KEYMAP);
+    let mut keys = Keys::new();
+    let mut ticker = Ticker::every(Duration::from_millis(1));
+    let mut idle = Idle::new(Duration::from_millis(50), Instant::now());
+    let mut split = Split::new(tx);
+    let mut read_buf = [0; 32];
 
     loop {
-        // should just be used for sleep?
-        // for mut output in &mut outputs {
-        //     output.set_high();
-        // }
-        // embassy_futures::select::select_array(inputs.map(|i| i.wait_for_any_edge())).await;
+        if idle.is_idle(Instant::now()) && split.link.is_idle() {
+            // nothing held: park on the row edge interrupts with SYS slowed
+            // down, the executor sleeping the core (WFE) until a key goes
+            // down.  Requests from the top half wake it too.  DORMANT would
+            // stop the UART's clock and miss them.
+            defmt::debug!("idle");
+            let div = clocks::set_sys_clk_div(IDLE_SYS_DIV);
+            let woken = select(matrix.wait_for_press(), rx.read(&mut read_buf)).await;
+            clocks::set_sys_clk_div(div);
+            match woken {
+                Either::First(result) => {
+                    result.unwrap();
+                    idle.wake(Instant::now());
+                    ticker.reset();
+                }
+                Either::Second(result) => {
+                    matrix.release().unwrap();
+                    match result {
+                        Ok(n) => split.receive(&read_buf[..n], &mut layers, &mut combos, &mut diagnostics).await,
+                        Err(e) => defmt::warn!("{:?}", e),
+                    }
+                    continue;
+                }
+            }
+        }
 
-        for output in &mut outputs {
-            output.set_low();
+        // acknowledgements and requests come in between scans
+        match select(ticker.next(), rx.read(&mut read_buf)).await {
+            Either::First(()) => {}
+            Either::Second(Ok(n)) => {
+                split.receive(&read_buf[..n], &mut layers, &mut combos, &mut diagnostics).await;
+                continue;
+            }
+            Either::Second(Err(e)) => {
+                defmt::warn!("{:?}", e);
+                continue;
+            }
         }
+        let raw = matrix.scan().await.unwrap();
+        let now = Instant::now();
+        let scan = debouncer.debounce(&raw, now);
+        let findings = diagnostics.update(&raw, &scan, now);
+        // undecided combos and tap-holds keep the scan going too
+        let active = raw.iter().flatten().any(|closed| *closed)
+            || state.any_pressed()
+            || combos.deadline().is_some()
+            || layers.deadline().is_some();
 
-        for input in &mut inputs {
-            let mut last: Option<&mut Flex<'_>> = None;
-            for output in &mut outputs {
-                if let Some(last) = &mut last {
-                    last.set_low();
-                }
-                output.set_high();
-                let id = output.bit();
-                last = Some(output);
+        split.retransmit(now, &keys).await;
+        split.send_findings(&diagnostics, findings, now).await;
+        for (input, at) in combos.tick(now) {
+            split.send_keys(&mut keys, layers.update(input, at)).await;
+        }
+        split.send_keys(&mut keys, layers.tick(now)).await;
+        for event in state.update(&scan) {
+            for (input, at) in combos.update(event, now) {
+                split.send_keys(&mut keys, layers.update(input, at)).await;
+            }
+        }
 
-                let mapped = mapping(input.bit().ilog2(), id.ilog2()) as usize;
-                cortex_m::asm::delay(1_000_000);
+        idle.update(active, now);
+    }
+}
 
-                if state[mapped] && input.is_low() {
-                    state[mapped] = false;
+// The bottom end of the link, and what the top half can ask of it
+struct Split<'d> {
+    tx: BufferedUartTx<'d, UART1>,
+    link: Link,
+    decoder: Decoder,
+    bad_frames: u32,
+    // a set of keys the link had no room for yet
+    pending: bool,
+    extras: Extras,
+    extras_pending: bool,
+    // macro keys the link had no room for yet, in the order pressed
+    macros_pending: Deque<u16, 8>,
+    // testing keys, and the switches the top half may not have yet
+    testing: bool,
+    matrix: [u8; MATRIX_LEN],
+    matrix_pending: bool,
+    leds: u8,
+}
 
-                    let mut buf: [u8; 9] = [0; 9];
-                    let report = KeyboardReport {
-                        keycodes: [0, 0, 0, 0, 0, 0],
-                        leds: 0,
-                        modifier: 0,
-                        reserved: 0,
-                    };
-                    if let Ok(size) = serialize(&mut buf, &report) {
-                        let mut send_buf: [u8; 11] = [2; 11];
-                        send_buf[10] = 3;
-                        send_buf[1..10].clone_from_slice(&buf);
-                        defmt::info!("{:?}", send_buf);
-                        tx.write_all(&send_buf).await.unwrap();
-                    }
-                } else if !state[mapped] && input.is_high() {
-                    state[mapped] = true;
-                    let mut buf: [u8; 9] = [0; 9];
-                    let report = KeyboardReport {
-                        keycodes: [mapped as u8, 0, 0, 0, 0, 0],
-                        leds: 0,
-                        modifier: 0,
-                        reserved: 0,
-                    };
-                    if let Ok(size) = serialize(&mut buf, &report) {
-                        let mut send_buf: [u8; 11] = [2; 11];
-                        send_buf[10] = 3;
-                        send_buf[1..10].clone_from_slice(&buf);
-                        defmt::info!("{:?}", send_buf);
-                        tx.write_all(&send_buf).await.unwrap();
+impl<'d> Split<'d> {
+    fn new(tx: BufferedUartTx<'d, UART1>) -> Self {
+        Self {
+            tx,
+            link: Link::new(Duration::from_millis(20)),
+            decoder: Decoder::new(),
+            bad_frames: 0,
+            pending: false,
+            extras: Extras::new(),
+            extras_pending: false,
+            macros_pending: Deque::new(),
+            testing: false,
+            matrix: [0; MATRIX_LEN],
+            matrix_pending: false,
+            leds: 0,
+        }
+    }
+
+    async fn receive(
+        &mut self,
+        bytes: &[u8],
+        layers: &mut TeebLayers<'_>,
+        combos: &mut Combos<'_>,
+        diagnostics: &mut TeebDiagnostics,
+    ) {
+        for byte in bytes {
+            let packet = match self.decoder.push(*byte) {
+                Some(Ok(packet)) => packet,
+                Some(Err(e)) => {
+                    defmt::warn!("bad frame {:?}", defmt::Debug2Format(&e));
+                    self.bad_frames = self.bad_frames.wrapping_add(1);
+                    continue;
+                }
+                None => continue,
+            };
+            let received = self.link.receive(&packet, Instant::now());
+            self.write(received.reply).await;
+            match received.message {
+                Some(Ok(message)) => {
+                    if let Some(reply) = self.request(message, layers, combos, diagnostics) {
+                        self.send(reply).await;
                     }
                 }
+                Some(Err(e)) => defmt::warn!("bad message {:?}", defmt::Debug2Format(&e)),
+                None => {}
+            }
+        }
+    }
+
+    fn request(
+        &mut self,
+        message: Message,
+        layers: &mut TeebLayers<'_>,
+        combos: &mut Combos<'_>,
+        diagnostics: &mut TeebDiagnostics,
+    ) -> Option<Message> {
+        match message {
+            Message::Leds(leds) => {
+                // the per-key LEDs aren't driven yet, only remembered
+                defmt::debug!("leds {:08b}", leds);
+                self.leds = leds;
+            }
+            // nothing here keeps time by the top half's clock, but the log
+            // lines up with the top half's by the offset
+            Message::Time(us) => {
+                defmt::debug!("clock offset {}us", us as i64 - Instant::now().as_micros() as i64);
+            }
+            Message::SetAction { layer, row, column, code } => {
+                let action = Action::from_code(code);
+                let set = action.is_some_and(|action| {
+                    layers.keymap_mut().set(layer as usize, row as usize, column as usize, action)
+                });
+                if !set {
+                    defmt::warn!("can't set {}/{}/{} to {:04x}", layer, row, column, code);
+                }
+            }
+            Message::Config(config) => {
+                layers.set_tap_hold(TapHold {
+                    tapping_term: Duration::from_millis(config.tapping_term_ms.into()),
+                    permissive_hold: config.permissive_hold,
+                    hold_on_other_key_press: config.hold_on_other_key_press,
+                });
+                combos.set_term(Duration::from_millis(config.combo_term_ms.into()));
+            }
+            Message::Bootloader => {
+                defmt::info!("rebooting to the bootloader");
+                // BOOTSEL, not returning, after 10ms for the acknowledgement
+                // to get out
+                embassy_rp::rom_data::reboot(0x0002 | 0x0100, 10, 0, 0);
+            }
+            Message::GetVersion => {
+                return Some(Message::Version(Version {
+                    protocol: frame::VERSION,
+                    major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+                    minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+                    patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+                }));
+            }
+            Message::GetHealth => {
+                return Some(Message::Health(Health {
+                    uptime_ms: Instant::now().as_millis() as u32,
+                    retransmits: self.link.retransmits(),
+                    bad_frames: self.bad_frames,
+                }));
+            }
+            Message::Diagnostics(on) => {
+                defmt::info!("key test {}", if on { "started" } else { "stopped" });
+                self.testing = on;
+                self.matrix_pending = false;
+                if on {
+                    // counts from here, and the switches as they are now
+                    diagnostics.reset();
+                    self.matrix = pack(diagnostics.raw());
+                    return Some(Message::Matrix(self.matrix));
+                }
             }
-            if let Some(last) = &mut last {
-                last.set_low();
+            Message::Keys(_)
+            | Message::Version(_)
+            | Message::Health(_)
+            | Message::Macro(_)
+            | Message::Extras(_)
+            | Message::Matrix(_)
+            | Message::KeyStats(_) => {
+                defmt::warn!("unexpected {:?}", defmt::Debug2Format(&message));
             }
         }
+        None
     }
-}
 
-fn mapping(input: u32, output: u32) -> KeyboardUsage {
-    match input {
-        // 1
-        3 => match output {
-            // A
-            8 => KeyboardUsage::KeyboardBacktickTilde,
-            // B
-            9 => KeyboardUsage::Keyboard1Exclamation,
-            // C
-            10 => KeyboardUsage::Keyboard2At,
-            // D
-            11 => KeyboardUsage::Keyboard3Hash,
-            // E
-            12 => KeyboardUsage::Keyboard4Dollar,
-            // F
-            13 => KeyboardUsage::Keyboard5Percent,
-            // G
-            14 => KeyboardUsage::Keyboard6Caret,
-            // H
-            15 => KeyboardUsage::Keyboard7Ampersand,
-            // I
-            16 => KeyboardUsage::Keyboard8Asterisk,
-            // J
-            17 => KeyboardUsage::Keyboard9OpenParens,
-            // K
-            18 => KeyboardUsage::Keyboard0CloseParens,
-            // L
-            19 => KeyboardUsage::KeyboardBackspace,
-            // M
-            20 => KeyboardUsage::KeyboardCc,
-            // N
-            21 => KeyboardUsage::KeyboardCc,
-            _ => KeyboardUsage::KeyboardBb,
-        },
-        // 2
-        26 => match output {
-            // A
-            8 => KeyboardUsage::KeyboardTab,
-            // B
-            9 => KeyboardUsage::KeyboardQq,
-            // C
-            10 => KeyboardUsage::KeyboardWw,
-            // D
-            11 => KeyboardUsage::KeyboardEe,
-            // E
-            12 => KeyboardUsage::KeyboardRr,
-            // F
-            13 => KeyboardUsage::KeyboardTt,
-            // G
-            14 => KeyboardUsage::KeyboardYy,
-            // H
-            15 => KeyboardUsage::KeyboardUu,
-            // I
-            16 => KeyboardUsage::KeyboardIi,
-            // J
-            17 => KeyboardUsage::KeyboardOo,
-            // K
-            18 => KeyboardUsage::KeyboardPp,
-            // L
-            19 => KeyboardUsage::KeyboardOpenBracketBrace,
-            // M
-            20 => KeyboardUsage::KeyboardCloseBracketBrace,
-            // N
-            21 => KeyboardUsage::KeyboardBackslashBar,
-            _ => KeyboardUsage::KeyboardBb,
-        },
-        // 3
-        22 => match output {
-            // A
-            8 => KeyboardUsage::KeyboardCapsLock,
-            // B
-            9 => KeyboardUsage::KeyboardAa,
-            // C
-            10 => KeyboardUsage::KeyboardSs,
-            // D
-            11 => KeyboardUsage::KeyboardDd,
-            // E
-            12 => KeyboardUsage::KeyboardFf,
-            // F
-            13 => KeyboardUsage::KeyboardGg,
-            // G
-            14 => KeyboardUsage::KeyboardHh,
-            // H
-            15 => KeyboardUsage::KeyboardJj,
-            // I
-            16 => KeyboardUsage::KeyboardKk,
-            // J
-            17 => KeyboardUsage::KeyboardLl,
-            // K
-            18 => KeyboardUsage::KeyboardSemiColon,
-            // L
-            19 => KeyboardUsage::KeyboardSingleDoubleQuote,
-            // M
-            20 => KeyboardUsage::KeyboardCc,
-            // N
-            21 => KeyboardUsage::KeyboardCc,
-            _ => KeyboardUsage::KeyboardBb,
-        },
-        // 4
-        6 => match output {
-            // A
-            8 => KeyboardUsage::KeyboardLeftShift,
-            // B
-            9 => KeyboardUsage::KeyboardZz,
-            // C
-            10 => KeyboardUsage::KeyboardXx,
-            // D
-            11 => KeyboardUsage::KeyboardCc,
-            // E
-            12 => KeyboardUsage::KeyboardVv,
-            // F
-            13 => KeyboardUsage::KeyboardBb,
-            // G
-            14 => KeyboardUsage::KeyboardNn,
-            // H
-            15 => KeyboardUsage::KeyboardMm,
-            // I
-            16 => KeyboardUsage::KeyboardCommaLess,
-            // J
-            17 => KeyboardUsage::KeyboardPeriodGreater,
-            // K
-            18 => KeyboardUsage::KeyboardSlashQuestion,
-            // L
-            19 => KeyboardUsage::KeyboardCc,
-            // M
-            20 => KeyboardUsage::KeyboardCc,
-            // N
-            21 => KeyboardUsage::KeyboardCc,
-            _ => KeyboardUsage::KeyboardBb,
-        },
-        // 5
-        7 => match output {
-            // A
-            8 => KeyboardUsage::KeyboardLeftControl,
-            // B
-            9 => KeyboardUsage::KeyboardLeftAlt,
-            // C
-            10 => KeyboardUsage::KeyboardCc,
-            // D
-            11 => KeyboardUsage::KeyboardCc,
-            // E
-            12 => KeyboardUsage::KeyboardSpacebar,
-            // F
-            13 => KeyboardUsage::KeyboardEscape,
-            // G
-            14 => KeyboardUsage::KeyboardRightControl,
-            // H
-            15 => KeyboardUsage::KeyboardRightAlt,
-            // I
-            16 => KeyboardUsage::KeyboardCc,
-            // J
-            17 => KeyboardUsage::KeyboardCc,
-            // K
-            18 => KeyboardUsage::KeyboardCc,
-            // L
-            19 => KeyboardUsage::KeyboardCc,
-            // M
-            20 => KeyboardUsage::KeyboardCc,
-            // N
-            21 => KeyboardUsage::KeyboardCc,
-            _ => KeyboardUsage::KeyboardBb,
-        },
-        _ => KeyboardUsage::KeyboardAa,
+    // The whole set of held keys goes over after every change, the top half
+    // picks boot or report protocol for the host.  A tap is two sets.  The
+    // consumer, system and mouse keys held go the same way, in a set of
+    // their own.  Macro keys go as they are, the top half plays them, and
+    // wait their turn behind any the link had no room for.
+    async fn send_keys(&mut self, keys: &mut Keys, output: Output) {
+        for key in output {
+            if let Key::Macro(key) = key {
+                if self.macros_pending.push_back(Action::Macro(key).code()).is_err() {
+                    defmt::warn!("macro dropped");
+                }
+                self.send_macros().await;
+            } else if keys.apply(key) {
+                self.pending = !self.send(Message::Keys(keys.nkro())).await;
+            } else if self.extras.apply(key) {
+                self.extras_pending = !self.send(Message::Extras(self.extras.to_bytes())).await;
+            }
+        }
+    }
+
+    async fn retransmit(&mut self, now: Instant, keys: &Keys) {
+        let frames = self.link.retransmit(now);
+        self.write(frames).await;
+        if self.pending {
+            self.pending = !self.send(Message::Keys(keys.nkro())).await;
+        }
+        if self.extras_pending {
+            self.extras_pending = !self.send(Message::Extras(self.extras.to_bytes())).await;
+        }
+        if self.matrix_pending {
+            self.matrix_pending = !self.send(Message::Matrix(self.matrix)).await;
+        }
+        self.send_macros().await;
+    }
+
+    async fn send_macros(&mut self) {
+        while let Some(&code) = self.macros_pending.front() {
+            if !self.send(Message::Macro(code)).await {
+                break;
+            }
+            self.macros_pending.pop_front();
+        }
     }
+
+    // While testing keys the switches go over after every change, like the
+    // keys held.  Stats go as a key is released, chatters, ghosts or gets
+    // stuck, and are dropped if there's no room: its next ones carry the
+    // counts on.
+    async fn send_findings(&mut self, diagnostics: &TeebDiagnostics, findings: Findings, now: Instant) {
+        if !self.testing {
+            return;
+        }
+        if findings.matrix {
+            self.matrix = pack(diagnostics.raw());
+            self.matrix_pending = !self.send(Message::Matrix(self.matrix)).await;
+        }
+        for (row, column) in findings.keys {
+            let stats = diagnostics.stats(row, column, now).unwrap();
+            if stats.stuck {
+                defmt::warn!("{}/{} stuck", row, column);
+            }
+            self.send(Message::KeyStats(KeyStats {
+                row: row as u8,
+                column: column as u8,
+                presses: stats.presses,
+                chatter: stats.chatter,
+                ghosts: stats.ghosts,
+                held_ms: stats.held.as_millis() as u32,
+                stuck: stats.stuck,
+            }))
+            .await;
+        }
+    }
+
+    // Returns false if the link had no room for it
+    async fn send(&mut self, message: Message) -> bool {
+        match self.link.send(message, Instant::now()) {
+            Ok(frame) => {
+                self.write(frame).await;
+                true
+            }
+            Err(LinkError::Full) => {
+                defmt::warn!("link full");
+                false
+            }
+            Err(LinkError::Message(_)) => unreachable!(),
+        }
+    }
+
+    async fn write(&mut self, frames: impl IntoIterator<Item = Frame>) {
+        for frame in frames {
+            self.tx.write_all(&frame).await.unwrap();
+        }
+    }
+}
+
+fn pack(scan: &Scan<ROWS, COLUMNS>) -> [u8; MATRIX_LEN] {
+    let mut matrix = [0; MATRIX_LEN];
+    diagnostics::pack(scan, &mut matrix);
+    matrix
 }
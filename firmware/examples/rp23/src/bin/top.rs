@@ -14,26 +14,61 @@ use assign_resources::assign_resources;
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_futures::join::join;
+use embassy_futures::select::{select, select3, select4, Either, Either3, Either4};
+use embassy_embedded_hal::flash::partition::{BlockingPartition, Error as PartitionError};
 use embassy_rp::{
     bind_interrupts,
     block::ImageDef,
+    flash::{Blocking, Flash, ERASE_SIZE},
     gpio::{Flex, Input, Level, Output, Pull},
+    peripherals::FLASH,
+    peripherals::SPI1,
+    peripherals::UART0,
     peripherals::UART1,
     peripherals::USB,
-    uart::{Uart, BufferedInterruptHandler, BufferedUart, BufferedUartRx, Config as UartConfig, StopBits, Parity},
+    uart::{Uart, BufferedInterruptHandler, BufferedUart, BufferedUartRx, BufferedUartTx, Config as UartConfig, DataBits, FlowControl, StopBits, Parity},
+    spi::{Async, Config as SpiConfig, Spi},
+    usb::host::{BootKeyboard, Error as UsbHostError, Host as UsbHost},
     usb::{Driver as UsbDriver, InterruptHandler},
 };
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::channel;
-use embassy_time::Timer;
-use embassy_usb::class::hid::{HidReaderWriter, ReportId, RequestHandler, State as HidState};
+use embassy_sync::pipe::Pipe;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State as CdcState};
+use embassy_usb::class::hid::{
+    HidBootProtocol, HidProtocolMode, HidReaderWriter, HidSubclass, HidWriter, ReportId, RequestHandler, State as HidState,
+};
 use embassy_usb::control::OutResponse;
 use embassy_usb::{Builder, Config, Handler};
 use embedded_io_async::{Read, Write};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
 use static_cell::StaticCell;
 use core::default::Default;
-use usbd_hid::descriptor::KeyboardUsage;
-use usbd_hid::descriptor::{KeyboardReport, SerializedDescriptor};
+use keyboard::charmap::Layout;
+use keyboard::console::Console;
+use keyboard::diagnostics;
+use keyboard::extrakey::{self, Extras};
+use keyboard::keymap::{Action, KeyboardUsage, Keymap};
+use keyboard::layouts::board::{self, COLUMNS, LAYERS, ROWS};
+use keyboard::macros::{self, Macro, Macros, Step, UnicodeMode};
+use keyboard::matrix::Scan;
+use keyboard::mousekey::MouseKeys;
+use keyboard::report::{Keys, NKRO_DESCRIPTOR, NKRO_LEN};
+use keyboard::router::{Route, Router, Typed};
+use keyboard::serial::{self, Session, Settings as SerialSettings};
+use keyboard::store;
+use keyboard::via::{self, Change};
+use link::frame::{Decoder, Frame};
+use link::message::{Config as LinkConfig, Message};
+use link::session::{Error as LinkError, Link};
+use terminal::cursor::RefreshMode;
+use terminal::framebuffer::Framebuffer;
+use terminal::grid;
+use terminal::stream::Parser;
+use terminal::Terminal;
 use {defmt_rtt as _, panic_probe as _};
 
 #[link_section = ".start_block"]
@@ -42,15 +77,309 @@ pub static IMAGE_DEF: ImageDef = ImageDef::secure_exe();
 
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
+    UART0_IRQ => BufferedInterruptHandler<UART0>;
     UART1_IRQ => BufferedInterruptHandler<UART1>;
 });
 
-static EVENT_CHANNEL: channel::Channel<CriticalSectionRawMutex, KeyboardUsage, 10> = channel::Channel::new();
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+type TeebKeymap = Keymap<ROWS, COLUMNS, LAYERS>;
+type TeebFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+type FlashError = PartitionError<embassy_rp::flash::Error>;
+
+// big endian action codes, as stored
+const CODES_LEN: usize = 2 * TeebKeymap::POSITIONS;
+// then VIA's macro buffer
+const RECORD_LEN: usize = CODES_LEN + macros::BUFFER;
+
+// the keymap as last set from the host, kept in the last two sectors of
+// flash and passed on to the bottom half, which scans with it
+static KEYMAP: BlockingMutex<CriticalSectionRawMutex, RefCell<TeebKeymap>> = BlockingMutex::new(RefCell::new(board::KEYMAP));
+const KEYMAP_OFFSET: u32 = (FLASH_SIZE - 2 * ERASE_SIZE) as u32;
+
+// the macros set from VIA, saved along with the keymap
+static MACRO_BUFFER: BlockingMutex<CriticalSectionRawMutex, RefCell<[u8; macros::BUFFER]>> =
+    BlockingMutex::new(RefCell::new([0; macros::BUFFER]));
+
+// tap-hold and combo timing, for the bottom half, which decides them
+const KEY_TIMING: LinkConfig = LinkConfig {
+    tapping_term_ms: 200,
+    permissive_hold: false,
+    hold_on_other_key_press: false,
+    combo_term_ms: 50,
+};
+
+// the whole keymap is to go down the link again
+static RESYNC: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// set by the host with SET_PROTOCOL, cleared again on bus reset
+static BOOT_PROTOCOL: AtomicBool = AtomicBool::new(false);
+
+// the bottom half's switches, while VIA's key tester watches them
+static MATRIX: BlockingMutex<CriticalSectionRawMutex, Cell<Scan<ROWS, COLUMNS>>> =
+    BlockingMutex::new(Cell::new([[false; COLUMNS]; ROWS]));
+static KEY_TEST: AtomicBool = AtomicBool::new(false);
+// the key tester is closed once it stops asking for so long
+const KEY_TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// requests for the bottom half, from the USB side
+static COMMANDS: channel::Channel<CriticalSectionRawMutex, Message, 8> = channel::Channel::new();
+
+// every set of keys held and every macro key, from the split link to the
+// HID writers, and the keys held on an external keyboard
+enum Event {
+    Keys(Keys),
+    Extras(Extras),
+    Macro(Macro),
+    External(Keys),
+}
+
+// keystrokes as bytes, when routed to the terminal on the display, the
+// serial terminal session or the USB host's console rather than to the
+// USB host as a keyboard
+static LOCAL_INPUT: Pipe<CriticalSectionRawMutex, 64> = Pipe::new();
+static SERIAL_INPUT: Pipe<CriticalSectionRawMutex, 64> = Pipe::new();
+static CONSOLE_INPUT: Pipe<CriticalSectionRawMutex, 64> = Pipe::new();
+
+// what the terminal on the display is to show: the serial session's
+// output, echo and setup menu, and the USB host's console
+static SCREEN: Pipe<CriticalSectionRawMutex, 256> = Pipe::new();
+
+// e-paper takes seconds to refresh, so the screen is drawn once what's
+// being shown has had this long to arrive
+const SETTLE: Duration = Duration::from_millis(200);
+
+// the serial line as it starts, changed from the setup menu behind Ctrl+]
+const SERIAL: SerialSettings = SerialSettings::new();
+
+// both Control keys together, and nothing else, move keystrokes on to the
+// next route: USB, local, serial
+const ROUTE_CHORD: [KeyboardUsage; 2] = [KeyboardUsage::KeyboardLeftControl, KeyboardUsage::KeyboardRightControl];
+
+// the keycaps' layout, for text typed on the terminal routes.  US has no
+// AltGr, so Right Alt is Compose.
+const LAYOUT: Layout = Layout::Us;
+const COMPOSE: KeyboardUsage = KeyboardUsage::KeyboardRightAlt;
+
+// the consumer, system and mouse keys held, for GET_REPORT
+static EXTRAS: BlockingMutex<CriticalSectionRawMutex, Cell<Extras>> = BlockingMutex::new(Cell::new(Extras::new()));
+static EVENT_CHANNEL: channel::Channel<CriticalSectionRawMutex, Event, 10> = channel::Channel::new();
+
+// Played by M(0), M(1) … in the keymap where VIA hasn't set a macro of
+// the same number, characters outside ASCII typed with the host's Unicode
+// input
+const MACROS: &[&[Step]] = &[keyboard::sequence!(Unicode('¯'), "\\_(", Unicode('ツ'), ")_/", Unicode('¯'))];
+const UNICODE: UnicodeMode = UnicodeMode::Linux;
+
+// device state, kept by `MyDeviceHandler`, with a signal on every change
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+static REMOTE_WAKEUP_ENABLED: AtomicBool = AtomicBool::new(false);
+static DEVICE_STATE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// a key went down while the host slept
+static REMOTE_WAKEUP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
-    let driver = UsbDriver::new(p.USB, Irqs);
+    // Pico 2's VBUS sense: the top half is the keyboard of a USB host that
+    // powers the port, and hosts a keyboard of its own when nothing does
+    let vbus = Input::new(p.PIN_24, Pull::None);
+
+    let flash = BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(TeebFlash::new_blocking(p.FLASH)));
+    let mut storage = Storage::new(BlockingPartition::new(&flash, KEYMAP_OFFSET, 2 * ERASE_SIZE as u32));
+    match storage.load() {
+        Ok(Some((keymap, buffer))) => {
+            KEYMAP.lock(|k| *k.borrow_mut() = keymap);
+            MACRO_BUFFER.lock(|b| *b.borrow_mut() = buffer);
+        }
+        Ok(None) => info!("No keymap saved, using the default"),
+        Err(e) => warn!("Failed to load the keymap: {:?}", Debug2Format(&e)),
+    }
+
+    let (tx_pin, rx_pin, uart) = (p.PIN_8, p.PIN_9, p.UART1);
+
+    static TX_BUF: StaticCell<[u8; 100]> = StaticCell::new();
+    let tx_buf = &mut TX_BUF.init([0; 100])[..];
+    static RX_BUF: StaticCell<[u8; 100]> = StaticCell::new();
+    let rx_buf = &mut RX_BUF.init([0; 100])[..];
+    let mut uconfig = UartConfig::default();
+    uconfig.parity = Parity::ParityEven;
+    uconfig.stop_bits = StopBits::STOP2;
+
+    let uart = BufferedUart::new(uart, Irqs, tx_pin, rx_pin, tx_buf, rx_buf, uconfig);
+    let (mut tx, mut rx) = uart.split();
+
+    // the serial terminal session, with RTS on GPIO 3 and CTS on GPIO 2
+    static SERIAL_TX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    let serial_tx_buf = &mut SERIAL_TX_BUF.init([0; 256])[..];
+    static SERIAL_RX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+    let serial_rx_buf = &mut SERIAL_RX_BUF.init([0; 256])[..];
+    let serial_uart = BufferedUart::new_with_rtscts(
+        p.UART0,
+        Irqs,
+        p.PIN_0,
+        p.PIN_1,
+        p.PIN_3,
+        p.PIN_2,
+        serial_tx_buf,
+        serial_rx_buf,
+        uart_config(&SERIAL.line),
+    );
+    let serial_fut = serial_session(serial_uart);
+
+    // the e-paper panel on SPI1
+    let mut spi_config = SpiConfig::default();
+    spi_config.frequency = 4_000_000;
+    let epd = Epd {
+        spi: Spi::new_txonly(p.SPI1, p.PIN_14, p.PIN_15, p.DMA_CH0, spi_config),
+        cs: Output::new(p.PIN_13, Level::High),
+        dc: Output::new(p.PIN_12, Level::Low),
+        reset: Output::new(p.PIN_11, Level::High),
+        busy: Input::new(p.PIN_10, Pull::Up),
+    };
+    let display_fut = display(epd);
+
+    let in_fut = async {
+        let mut link = Link::new(Duration::from_millis(20));
+        let mut decoder = Decoder::new();
+        let mut read_buf = [0; 32];
+        let mut ticker = Ticker::every(Duration::from_secs(10));
+        write(&mut tx, send(&mut link, Message::GetVersion)).await;
+        write(&mut tx, send(&mut link, Message::Time(Instant::now().as_micros()))).await;
+        // position of the next key to push down, as room on the link allows,
+        // and whether the key timing is still to go
+        let mut resync = 0;
+        let mut timing = true;
+        loop {
+            if timing && !link.is_full() {
+                write(&mut tx, send(&mut link, Message::Config(KEY_TIMING))).await;
+                timing = false;
+            }
+            while resync < TeebKeymap::POSITIONS && !link.is_full() {
+                let (layer, row, column) = TeebKeymap::position(resync);
+                let code = KEYMAP.lock(|keymap| keymap.borrow().code(resync));
+                let message = Message::SetAction {
+                    layer: layer as u8,
+                    row: row as u8,
+                    column: column as u8,
+                    code,
+                };
+                write(&mut tx, send(&mut link, message)).await;
+                resync += 1;
+            }
+
+            let deadline = link.deadline().unwrap_or(Instant::MAX);
+            // commands wait while the link is full
+            let full = link.is_full();
+            let command = async move {
+                if full {
+                    core::future::pending().await
+                } else {
+                    COMMANDS.receive().await
+                }
+            };
+            let periodic = select(ticker.next(), RESYNC.wait());
+            let n = match select4(rx.read(&mut read_buf), command, Timer::at(deadline), periodic).await {
+                Either4::First(Ok(n)) => n,
+                Either4::First(Err(e)) => {
+                    defmt::info!("{:?}", e);
+                    continue;
+                }
+                Either4::Second(message) => {
+                    write(&mut tx, send(&mut link, message)).await;
+                    continue;
+                }
+                Either4::Third(()) => {
+                    write(&mut tx, link.retransmit(Instant::now())).await;
+                    continue;
+                }
+                Either4::Fourth(Either::First(())) => {
+                    // keeps the bottom half's clock in step, and its health
+                    // in the log
+                    write(&mut tx, send(&mut link, Message::Time(Instant::now().as_micros()))).await;
+                    write(&mut tx, send(&mut link, Message::GetHealth)).await;
+                    continue;
+                }
+                Either4::Fourth(Either::Second(())) => {
+                    resync = 0;
+                    timing = true;
+                    continue;
+                }
+            };
+
+            for byte in &read_buf[..n] {
+                let packet = match decoder.push(*byte) {
+                    Some(Ok(packet)) => packet,
+                    Some(Err(e)) => {
+                        defmt::info!("bad frame {:?}", Debug2Format(&e));
+                        continue;
+                    }
+                    None => continue,
+                };
+                let received = link.receive(&packet, Instant::now());
+                write(&mut tx, received.reply).await;
+                if received.restarted {
+                    // it scans with the default keymap and timing again, and
+                    // isn't testing keys
+                    resync = 0;
+                    timing = true;
+                    if KEY_TEST.load(Ordering::Relaxed) {
+                        write(&mut tx, send(&mut link, Message::Diagnostics(true))).await;
+                    }
+                }
+
+                match received.message {
+                    Some(Ok(Message::Keys(report))) => EVENT_CHANNEL.send(Event::Keys(Keys::from_nkro(&report))).await,
+                    Some(Ok(Message::Extras(bytes))) => EVENT_CHANNEL.send(Event::Extras(Extras::from_bytes(&bytes))).await,
+                    Some(Ok(Message::Macro(code))) => match Action::from_code(code) {
+                        Some(Action::Macro(key)) => EVENT_CHANNEL.send(Event::Macro(key)).await,
+                        _ => warn!("unknown macro {:x}", code),
+                    },
+                    Some(Ok(Message::Version(version))) => {
+                        info!(
+                            "bottom half firmware {}.{}.{}, protocol {}",
+                            version.major, version.minor, version.patch, version.protocol
+                        );
+                    }
+                    Some(Ok(Message::Health(health))) => {
+                        info!(
+                            "bottom half up {}ms, {} retransmits, {} bad frames",
+                            health.uptime_ms, health.retransmits, health.bad_frames
+                        );
+                    }
+                    Some(Ok(Message::Matrix(bytes))) => MATRIX.lock(|matrix| matrix.set(diagnostics::unpack(&bytes))),
+                    Some(Ok(Message::KeyStats(stats))) => {
+                        if stats.stuck {
+                            warn!("key {}/{} stuck, held {}ms", stats.row, stats.column, stats.held_ms);
+                        } else {
+                            info!(
+                                "key {}/{}: {} presses, {} chatter, {} ghosts",
+                                stats.row, stats.column, stats.presses, stats.chatter, stats.ghosts
+                            );
+                        }
+                    }
+                    Some(Ok(message)) => warn!("unexpected {:?}", Debug2Format(&message)),
+                    Some(Err(e)) => warn!("bad message {:?}", Debug2Format(&e)),
+                    None => {}
+                }
+            }
+        }
+    };
+
+    if vbus.is_high() {
+        join(join(in_fut, serial_fut), join(display_fut, usb_device(p.USB, storage))).await;
+    } else {
+        info!("No USB host, hosting an external keyboard");
+        let usb_fut = join(external_keyboard(p.USB), terminals());
+        join(join(in_fut, serial_fut), join(display_fut, usb_fut)).await;
+    }
+}
+
+// The top half as the USB host's keyboard, with VIA on a raw HID interface
+async fn usb_device(usb: USB, mut storage: Storage<'_>) {
+    let driver = UsbDriver::new(usb, Irqs);
 
     let mut config = Config::new(0xc0de, 0xcafe);
     config.manufacturer = Some("Embassy");
@@ -58,13 +387,20 @@ async fn main(_spawner: Spawner) {
     config.serial_number = Some("12345678");
     config.max_power = 100;
     config.max_packet_size_0 = 64;
+    config.supports_remote_wakeup = true;
+
+    // the console's two interfaces go together, by an IAD
+    config.device_class = 0xef;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
 
     let mut config_descriptor = [0; 256];
     let mut bos_descriptor = [0; 256];
     let mut msos_descriptor = [0; 256];
     let mut control_buf = [0; 64];
     let mut request_handler = MyRequestHandler {};
-    let mut device_handler = MyDeviceHandler::new();
+    let mut device_handler = MyDeviceHandler {};
 
     let mut state = HidState::new();
 
@@ -79,38 +415,171 @@ async fn main(_spawner: Spawner) {
 
     builder.handler(&mut device_handler);
 
+    let mut control_handler = MyRequestHandler {};
     let config = embassy_usb::class::hid::Config {
-        report_descriptor: KeyboardReport::desc(),
-        request_handler: None,
-        poll_ms: 60,
+        report_descriptor: NKRO_DESCRIPTOR,
+        request_handler: Some(&mut control_handler),
+        poll_ms: 1,
         max_packet_size: 64,
+        hid_subclass: HidSubclass::Boot,
+        hid_boot_protocol: HidBootProtocol::Keyboard,
     };
-    let hid = HidReaderWriter::<_, 1, 8>::new(&mut builder, &mut state, config);
-
-    let (tx_pin, rx_pin, uart) = (p.PIN_8, p.PIN_9, p.UART1);
+    let hid = HidReaderWriter::<_, 1, NKRO_LEN>::new(&mut builder, &mut state, config);
 
-    static TX_BUF: StaticCell<[u8; 100]> = StaticCell::new();
-    let tx_buf = &mut TX_BUF.init([0; 100])[..];
-    static RX_BUF: StaticCell<[u8; 100]> = StaticCell::new();
-    let rx_buf = &mut RX_BUF.init([0; 100])[..];
-    let mut uconfig = UartConfig::default();
-    uconfig.parity = Parity::ParityEven;
-    uconfig.stop_bits = StopBits::STOP2;
+    // consumer, system and mouse keys, by report ID
+    let mut extra_state = HidState::new();
+    let mut extra_handler = ExtraRequestHandler {};
+    let config = embassy_usb::class::hid::Config {
+        report_descriptor: extrakey::DESCRIPTOR,
+        request_handler: Some(&mut extra_handler),
+        poll_ms: 1,
+        max_packet_size: 8,
+        hid_subclass: HidSubclass::No,
+        hid_boot_protocol: HidBootProtocol::None,
+    };
+    let mut extra_writer = HidWriter::<_, { extrakey::MOUSE_LEN }>::new(&mut builder, &mut extra_state, config);
 
-    let mut uart = Uart::new_blocking(uart, tx_pin, rx_pin, uconfig);
+    // raw HID for VIA
+    let mut via_state = HidState::new();
+    let config = embassy_usb::class::hid::Config {
+        report_descriptor: via::DESCRIPTOR,
+        request_handler: None,
+        poll_ms: 1,
+        max_packet_size: 32,
+        hid_subclass: HidSubclass::No,
+        hid_boot_protocol: HidBootProtocol::None,
+    };
+    let via_hid = HidReaderWriter::<_, { via::REPORT_LEN }, { via::REPORT_LEN }>::new(&mut builder, &mut via_state, config);
 
-    // let (mut tx, mut rx) = uart.split();
+    // a serial console, for a getty on the host
+    let mut console_state = CdcState::new();
+    let console = CdcAcmClass::new(&mut builder, &mut console_state, 64);
 
     let (reader, mut writer) = hid.split();
+    let (mut via_reader, mut via_writer) = via_hid.split();
+    let (mut console_tx, mut console_rx, console_control) = console.split_with_control();
     let mut usb = builder.build();
-    let usb_fut = usb.run();
-    let in_fut = async {
+    let usb_fut = async {
         loop {
-            let mut read_buf: [u8; 11] = [0; 11];
+            usb.run_until_suspend().await;
+            match select(usb.wait_resume(), REMOTE_WAKEUP.wait()).await {
+                Either::First(()) => {}
+                Either::Second(()) => {
+                    if let Err(e) = usb.remote_wakeup().await {
+                        warn!("Remote wakeup failed: {:?}", e);
+                    }
+                }
+            }
+        }
+    };
+
+    // Reports only go to a configured, awake host.  Otherwise the latest set
+    // of keys waits, and a key going down asks a sleeping host to wake.
+    //
+    // While keystrokes are routed to a terminal they go there as bytes
+    // instead, and the host is left with every key up.  Consumer, system
+    // and mouse keys stay with the host.
+    //
+    // A macro plays one report per write, so the host sees every one and
+    // the channel keeps draining in between.  Keys held meanwhile go once
+    // it is done.  Consumer and system keys go on every change, mouse keys
+    // as they move.
+    let hid_fut = async {
+        let mut macros = Macros::new(MACROS);
+        macros.set_unicode(UNICODE);
+        let mut router = router();
+        let mut mouse = MouseKeys::new();
+        let mut keys = Keys::new();
+        let mut extras = Extras::new();
+        let mut unsent = false;
+        let (mut consumer_unsent, mut system_unsent) = (false, false);
+        loop {
+            let awake = CONFIGURED.load(Ordering::Relaxed) && !SUSPENDED.load(Ordering::Relaxed);
+            let deadline = match (macros.deadline(), mouse.deadline()) {
+                (Some(a), Some(b)) if awake => a.min(b),
+                (Some(deadline), None) | (None, Some(deadline)) if awake => deadline,
+                _ => Instant::MAX,
+            };
+            let deadline = deadline.min(router.deadline().unwrap_or(Instant::MAX));
+            match select3(EVENT_CHANNEL.receive(), DEVICE_STATE.wait(), Timer::at(deadline)).await {
+                Either3::First(Event::Keys(next)) => {
+                    let update = router.update(&next, Instant::now());
+                    if let Some(left) = update.left {
+                        info!("keystrokes to {:?}", Debug2Format(&router.route()));
+                        if left == Route::Usb {
+                            keys = Keys::new();
+                            macros.record(&keys);
+                            unsent = true;
+                        }
+                    }
+                    if router.route() == Route::Usb {
+                        macros.record(router.held());
+                        keys = *router.held();
+                        unsent = true;
+                    }
+                    type_bytes(router.route(), &update.typed);
+                }
+                Either3::First(Event::Extras(next)) => {
+                    consumer_unsent |= next.consumer != extras.consumer;
+                    system_unsent |= next.system != extras.system;
+                    mouse.set(next.mouse, Instant::now());
+                    extras = next;
+                    EXTRAS.lock(|held| held.set(next));
+                }
+                Either3::First(Event::Macro(key)) if router.route() == Route::Usb => {
+                    MACRO_BUFFER.lock(|buffer| macros.set_buffer(&buffer.borrow()));
+                    macros.press(key, &keys, Instant::now());
+                    unsent = true;
+                }
+                // an external keyboard needs the port, so there's none
+                Either3::First(Event::Macro(_) | Event::External(_)) => {}
+                Either3::Second(()) | Either3::Third(()) => {}
+            }
+            type_bytes(router.route(), &router.tick(Instant::now()));
+            if !CONFIGURED.load(Ordering::Relaxed) {
+                continue;
+            }
+            if SUSPENDED.load(Ordering::Relaxed) {
+                let pressed = (unsent && !keys.is_empty()) || !extras.is_empty();
+                if pressed && REMOTE_WAKEUP_ENABLED.load(Ordering::Relaxed) {
+                    REMOTE_WAKEUP.signal(());
+                }
+                continue;
+            }
 
-            match uart.blocking_read(&mut read_buf) {
-                Ok(_) => defmt::info!("{:?}", &read_buf),
-                Err(e) => defmt::info!("{:?}", e)
+            if consumer_unsent {
+                match extra_writer.write(&extras.consumer_report()).await {
+                    Ok(()) => consumer_unsent = false,
+                    Err(e) => warn!("Failed to send consumer report: {:?}", e),
+                }
+            }
+            if system_unsent {
+                match extra_writer.write(&extras.system_report()).await {
+                    Ok(()) => system_unsent = false,
+                    Err(e) => warn!("Failed to send system report: {:?}", e),
+                }
+            }
+            if let Some(report) = mouse.next(Instant::now()) {
+                if let Err(e) = extra_writer.write(&report).await {
+                    warn!("Failed to send mouse report: {:?}", e);
+                }
+            }
+
+            let (report, played) = match macros.next(Instant::now()) {
+                Some(report) => (report, true),
+                // waiting out a delay
+                None if macros.is_playing() => continue,
+                None if unsent => (keys, false),
+                None => continue,
+            };
+            let result = if BOOT_PROTOCOL.load(Ordering::Relaxed) {
+                writer.write_serialize(&report.boot()).await
+            } else {
+                writer.write(&report.nkro()).await
+            };
+            match result {
+                Ok(()) => unsent &= played,
+                Err(e) => warn!("Failed to send report: {:?}", e),
             }
         }
     };
@@ -119,7 +588,475 @@ async fn main(_spawner: Spawner) {
         reader.run(false, &mut request_handler).await;
     };
 
-    join(usb_fut, join(in_fut, out_fut)).await;
+    // Keymap changes from VIA are passed on straight away, and saved with
+    // the macros once the host has been quiet for a while, as it sends a
+    // whole keymap or macro buffer a few bytes at a time.
+    //
+    // VIA's key tester polls the switches.  The bottom half tests keys while
+    // it does, sending them up and logging each key's presses, chatter and
+    // ghosts, and any stuck.
+    let via_fut = async {
+        let mut report = [0; via::REPORT_LEN];
+        let mut save_at = Instant::MAX;
+        let mut test_until = Instant::MAX;
+        loop {
+            match select(via_reader.read(&mut report), Timer::at(save_at.min(test_until))).await {
+                Either::First(Ok(_)) => {
+                    let uptime_ms = Instant::now().as_millis() as u32;
+                    let matrix = MATRIX.lock(|matrix| matrix.get());
+                    let change = KEYMAP.lock(|keymap| {
+                        MACRO_BUFFER.lock(|buffer| {
+                            let (mut keymap, mut buffer) = (keymap.borrow_mut(), buffer.borrow_mut());
+                            via::handle(&mut report, &mut keymap, &board::KEYMAP, &mut buffer, uptime_ms, &matrix)
+                        })
+                    });
+                    if let Err(e) = via_writer.write(&report).await {
+                        warn!("Failed to answer VIA: {:?}", e);
+                    }
+                    match change {
+                        Change::None => continue,
+                        Change::Key { layer, row, column } => {
+                            let code = KEYMAP.lock(|keymap| {
+                                keymap.borrow().get(layer as usize, row as usize, column as usize).code()
+                            });
+                            COMMANDS.send(Message::SetAction { layer, row, column, code }).await;
+                        }
+                        Change::Keymap => RESYNC.signal(()),
+                        Change::Macros => {}
+                        Change::Bootloader => {
+                            // the bottom half too, given time to take it
+                            COMMANDS.send(Message::Bootloader).await;
+                            Timer::after_millis(100).await;
+                            info!("Rebooting to the bootloader");
+                            embassy_rp::rom_data::reboot(0x0002 | 0x0100, 10, 0, 0);
+                        }
+                        Change::Matrix => {
+                            if !KEY_TEST.swap(true, Ordering::Relaxed) {
+                                info!("Key test started");
+                                COMMANDS.send(Message::Diagnostics(true)).await;
+                            }
+                            test_until = Instant::now() + KEY_TEST_TIMEOUT;
+                            continue;
+                        }
+                    }
+                    save_at = Instant::now() + Duration::from_millis(500);
+                }
+                Either::First(Err(e)) => warn!("Failed to read from VIA: {:?}", e),
+                Either::Second(()) if Instant::now() >= test_until => {
+                    test_until = Instant::MAX;
+                    KEY_TEST.store(false, Ordering::Relaxed);
+                    MATRIX.lock(|matrix| matrix.set([[false; COLUMNS]; ROWS]));
+                    info!("Key test stopped");
+                    COMMANDS.send(Message::Diagnostics(false)).await;
+                }
+                Either::Second(()) => {
+                    save_at = Instant::MAX;
+                    let keymap = KEYMAP.lock(|keymap| *keymap.borrow());
+                    let buffer = MACRO_BUFFER.lock(|buffer| *buffer.borrow());
+                    match storage.save(&keymap, &buffer) {
+                        Ok(()) => info!("Keymap saved"),
+                        Err(e) => warn!("Failed to save the keymap: {:?}", Debug2Format(&e)),
+                    }
+                }
+            }
+        }
+    };
+
+    // The host's console on the display.  Keystrokes wait for the host to
+    // open the port and be ready for them, by DTR and RTS, and it's told the
+    // display's size when it asks with CSI 18 t, as `resize` does.
+    let console_fut = async {
+        let mut console = Console::new(grid::COLUMNS as u16, grid::ROWS as u16);
+        let mut typed = [0; 64];
+        let mut packet = [0; 64];
+        loop {
+            let event = select3(
+                CONSOLE_INPUT.read(&mut typed),
+                console_rx.read_packet(&mut packet),
+                console_control.control_changed(),
+            )
+            .await;
+            match event {
+                Either3::First(n) => console.typed(&typed[..n]),
+                // waits for the display, which holds the host off by NAKing
+                Either3::Second(Ok(n)) => SCREEN.write_all(&console.received(&packet[..n])).await,
+                // not configured, or the host has gone
+                Either3::Second(Err(_)) => console_rx.wait_connection().await,
+                Either3::Third(()) => {
+                    let coding = console_tx.line_coding();
+                    console.set_data_bits(coding.data_bits());
+                    if let Some(notice) = console.set_lines(console_tx.dtr(), console_tx.rts()) {
+                        show(notice);
+                    }
+                    info!("Console at {} baud, DTR {}, RTS {}", coding.data_rate(), console_tx.dtr(), console_tx.rts());
+                }
+            }
+            if let Some(bytes) = console.take_sending() {
+                for chunk in bytes.chunks(64) {
+                    if let Err(e) = console_tx.write_packet(chunk).await {
+                        warn!("Failed to write to the console: {:?}", e);
+                    }
+                }
+                // a full last packet needs a short one to end the transfer
+                if bytes.len() % 64 == 0 {
+                    let _ = console_tx.write_packet(&[]).await;
+                }
+            }
+        }
+    };
+
+    join(usb_fut, join(hid_fut, join(out_fut, join(via_fut, console_fut)))).await;
+}
+
+// Keymap and macro records in two flash sectors, so a save cut short by
+// losing power leaves the one before; see `keyboard::store`
+struct Storage<'a> {
+    partition: BlockingPartition<'a, NoopRawMutex, TeebFlash>,
+    // the slot and sequence number of the next save
+    next: (usize, u32),
+}
+
+impl<'a> Storage<'a> {
+    fn new(partition: BlockingPartition<'a, NoopRawMutex, TeebFlash>) -> Self {
+        Self { partition, next: (0, 0) }
+    }
+
+    fn load(&mut self) -> Result<Option<(TeebKeymap, [u8; macros::BUFFER])>, FlashError> {
+        let mut slots = [[0; store::HEADER + RECORD_LEN]; 2];
+        for (i, slot) in slots.iter_mut().enumerate() {
+            self.partition.read((i * ERASE_SIZE) as u32, slot)?;
+        }
+        let newest = store::newest([&slots[0], &slots[1]]);
+        self.next = store::next(newest);
+        // nothing saved, or saved for a keymap of another shape.  Keymaps
+        // saved before there were macros have none.
+        let record = newest.map(|(_, record)| record.data);
+        Ok(record.filter(|data| [CODES_LEN, RECORD_LEN].contains(&data.len())).map(|data| {
+            let mut keymap = board::KEYMAP;
+            keymap.read_codes(&data[..CODES_LEN]);
+            let mut buffer = [0; macros::BUFFER];
+            if data.len() == RECORD_LEN {
+                buffer.copy_from_slice(&data[CODES_LEN..]);
+            }
+            (keymap, buffer)
+        }))
+    }
+
+    // Erase, data, then header: see `keyboard::store`
+    fn save(&mut self, keymap: &TeebKeymap, buffer: &[u8; macros::BUFFER]) -> Result<(), FlashError> {
+        let (slot, seq) = self.next;
+        let mut data = [0; RECORD_LEN];
+        keymap.write_codes(&mut data[..CODES_LEN]);
+        data[CODES_LEN..].copy_from_slice(buffer);
+        let offset = (slot * ERASE_SIZE) as u32;
+        self.partition.erase(offset, offset + ERASE_SIZE as u32)?;
+        self.partition.write(offset + store::HEADER as u32, &data)?;
+        self.partition.write(offset, &store::header(seq, &data))?;
+        self.next = (1 - slot, seq.wrapping_add(1));
+        Ok(())
+    }
+}
+
+// With no USB host the port hosts a keyboard or barcode scanner instead,
+// read through the boot protocol, one device after another
+async fn external_keyboard(usb: USB) {
+    let mut host = UsbHost::new(usb, Irqs);
+    let mut config = [0; 256];
+    loop {
+        let (device, descriptors) = match host.enumerate(&mut config).await {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Failed to enumerate the USB device: {:?}", e);
+                host.wait_disconnect().await;
+                continue;
+            }
+        };
+        info!("USB device {:04x}:{:04x} attached", device.vendor_id, device.product_id);
+        let mut keyboard = match BootKeyboard::new(&mut host, &device, descriptors).await {
+            Ok(keyboard) => keyboard,
+            Err(e) => {
+                warn!("Not a keyboard: {:?}", e);
+                host.wait_disconnect().await;
+                continue;
+            }
+        };
+
+        let mut keys = Keys::new();
+        loop {
+            match keyboard.read().await {
+                // with too many keys held to say which, the last set stands
+                Ok(report) => match Keys::from_boot(&report) {
+                    Some(next) if next != keys => {
+                        keys = next;
+                        EVENT_CHANNEL.send(Event::External(keys)).await;
+                    }
+                    _ => {}
+                },
+                Err(UsbHostError::Disconnected) => break,
+                Err(e) => warn!("Failed to read the external keyboard: {:?}", e),
+            }
+        }
+        info!("USB device detached");
+        EVENT_CHANNEL.send(Event::External(Keys::new())).await;
+    }
+}
+
+// Without a USB host, keystrokes from both keyboards go to the terminal on
+// the display or the serial session, the USB host's routes left out.
+// Consumer, system, mouse and macro keys have nowhere to go.
+async fn terminals() {
+    let mut router = router();
+    router.set_routes(&[Route::Local, Route::Serial]);
+    let (mut split, mut external) = (Keys::new(), Keys::new());
+    loop {
+        let deadline = router.deadline().unwrap_or(Instant::MAX);
+        match select(EVENT_CHANNEL.receive(), Timer::at(deadline)).await {
+            Either::First(Event::Keys(keys)) => split = keys,
+            Either::First(Event::External(keys)) => external = keys,
+            Either::First(Event::Extras(_) | Event::Macro(_)) | Either::Second(()) => {}
+        }
+        let update = router.update(&split.union(&external), Instant::now());
+        if update.left.is_some() {
+            info!("keystrokes to {:?}", Debug2Format(&router.route()));
+        }
+        type_bytes(router.route(), &update.typed);
+        type_bytes(router.route(), &router.tick(Instant::now()));
+    }
+}
+
+fn router() -> Router {
+    let mut chord = Keys::new();
+    for usage in ROUTE_CHORD {
+        chord.press(usage);
+    }
+    let mut router = Router::new(chord);
+    router.set_layout(LAYOUT);
+    router.set_compose(COMPOSE);
+    router
+}
+
+// The serial terminal session on UART0, through an RS-232 level shifter:
+// keystrokes routed to it go out, Ctrl+] opening the setup menu, and what
+// comes back goes to the screen
+async fn serial_session(mut uart: BufferedUart<'_, UART0>) {
+    let mut session = Session::new(SERIAL);
+    uart.set_rtscts(SERIAL.line.flow == serial::Flow::RtsCts);
+    uart.set_flow_control(flow_control(SERIAL.line.flow)).unwrap();
+    let mut typed = [0; 64];
+    let mut received = [0; 64];
+    loop {
+        let event = select(SERIAL_INPUT.read(&mut typed), uart.read(&mut received)).await;
+        match event {
+            Either::First(n) => {
+                let output = session.typed(&typed[..n]);
+                show(&output.show);
+                if let Err(e) = uart.write_all(&output.send).await {
+                    warn!("Failed to write to the serial line: {:?}", e);
+                }
+                if output.send_break {
+                    // a quarter of a second
+                    uart.send_break(session.settings().line.baud / 4).await;
+                }
+                if output.line {
+                    let line = session.settings().line;
+                    uart.set_config(&uart_config(&line));
+                    uart.set_rtscts(line.flow == serial::Flow::RtsCts);
+                    uart.set_flow_control(flow_control(line.flow)).unwrap();
+                    info!("Serial line at {} baud", line.baud);
+                }
+            }
+            // waits for the display, leaving the rest in the RX buffer until
+            // flow control holds the host off
+            Either::Second(Ok(n)) => SCREEN.write_all(&session.received(&received[..n])).await,
+            Either::Second(Err(e)) => warn!("Serial line error: {:?}", e),
+        }
+    }
+}
+
+// The session's line settings as the UART takes them
+fn uart_config(line: &serial::Line) -> UartConfig {
+    let mut config = UartConfig::default();
+    config.baudrate = line.baud;
+    config.data_bits = match line.data_bits {
+        7 => DataBits::DataBits7,
+        _ => DataBits::DataBits8,
+    };
+    config.stop_bits = match line.stop_bits {
+        2 => StopBits::STOP2,
+        _ => StopBits::STOP1,
+    };
+    config.parity = match line.parity {
+        serial::Parity::None => Parity::ParityNone,
+        serial::Parity::Even => Parity::ParityEven,
+        serial::Parity::Odd => Parity::ParityOdd,
+    };
+    config
+}
+
+// The host is held off at three quarters of the 256 byte RX buffer, leaving
+// room for what it sends before it stops, and let go on at a quarter
+fn flow_control(flow: serial::Flow) -> FlowControl {
+    FlowControl {
+        xon_xoff: flow == serial::Flow::XonXoff,
+        rts: flow == serial::Flow::RtsCts,
+        high_water: 192,
+        low_water: 64,
+    }
+}
+
+// The terminal on the display, drawing what's sent to SCREEN and what's
+// typed on the local route.  The panel refreshes while more arrives, and
+// again afterwards if any did.
+async fn display(mut epd: Epd<'static>) {
+    // too big for the stack
+    static TERMINAL: StaticCell<Terminal<'static>> = StaticCell::new();
+    static FRAMEBUFFER: StaticCell<Framebuffer> = StaticCell::new();
+    let terminal = TERMINAL.init_with(Terminal::new);
+    let framebuffer = FRAMEBUFFER.init_with(Framebuffer::new);
+    terminal.set_refresh_mode(RefreshMode::Epd);
+    let mut parser = Parser::new();
+    epd.init().await;
+
+    let mut changed = false;
+    loop {
+        if !changed {
+            feed(terminal, &mut parser).await;
+        }
+        let _ = with_timeout(SETTLE, async {
+            loop {
+                feed(terminal, &mut parser).await;
+            }
+        })
+        .await;
+
+        terminal.draw(framebuffer);
+        terminal.idle(framebuffer);
+        changed = false;
+        let more = async {
+            loop {
+                feed(terminal, &mut parser).await;
+                changed = true;
+            }
+        };
+        select(epd.refresh(framebuffer.bytes()), more).await;
+    }
+}
+
+// Whatever's next for the screen, or typed on the local route, into the
+// terminal.  Typing there is a typewriter's, with nothing to echo it: the
+// terminal takes Enter's CR as a new line already, and rubs out with an
+// erase in line, so Backspace becomes one.
+async fn feed(terminal: &mut Terminal<'_>, parser: &mut Parser) {
+    let (mut received, mut typed) = ([0; 64], [0; 64]);
+    let (bytes, local) = match select(SCREEN.read(&mut received), LOCAL_INPUT.read(&mut typed)).await {
+        Either::First(n) => (&received[..n], false),
+        Either::Second(n) => (&typed[..n], true),
+    };
+    for byte in bytes {
+        let bytes: &[u8] = match byte {
+            0x7f if local => b"\x1b[K",
+            _ => core::slice::from_ref(byte),
+        };
+        for byte in bytes {
+            if let Some(c) = parser.push(*byte) {
+                terminal.push(c);
+            }
+        }
+    }
+}
+
+// The 800x300 e-paper panel, taken to have a UC8179 controller as the
+// 7.5" panels it's cut from do: a command byte with DC low, its data with
+// DC high, and BUSY low while it works.  It inks set bits, so the
+// terminal's light text on dark comes out dark on the paper.
+struct Epd<'a> {
+    spi: Spi<'a, SPI1, Async>,
+    cs: Output<'a>,
+    dc: Output<'a>,
+    reset: Output<'a>,
+    busy: Input<'a>,
+}
+
+impl Epd<'_> {
+    async fn init(&mut self) {
+        self.reset.set_low();
+        Timer::after_millis(2).await;
+        self.reset.set_high();
+        Timer::after_millis(20).await;
+        self.wait().await;
+        // power setting, then black and white with the OTP's waveforms,
+        // 800x300, and VCOM and data interval
+        self.command(0x01, &[0x07, 0x07, 0x3f, 0x3f]).await;
+        self.command(0x00, &[0x1f]).await;
+        self.command(0x61, &[0x03, 0x20, 0x01, 0x2c]).await;
+        self.command(0x50, &[0x10, 0x07]).await;
+    }
+
+    // A full refresh with `frame`, powered only while it's under way
+    async fn refresh(&mut self, frame: &[u8]) {
+        self.command(0x04, &[]).await;
+        self.wait().await;
+        self.command(0x13, frame).await;
+        self.command(0x12, &[]).await;
+        Timer::after_millis(1).await;
+        self.wait().await;
+        self.command(0x02, &[]).await;
+        self.wait().await;
+    }
+
+    async fn command(&mut self, command: u8, data: &[u8]) {
+        self.cs.set_low();
+        self.dc.set_low();
+        // DMA writes don't fail
+        let _ = self.spi.write(&[command]).await;
+        if !data.is_empty() {
+            self.dc.set_high();
+            let _ = self.spi.write(data).await;
+        }
+        self.cs.set_high();
+    }
+
+    async fn wait(&mut self) {
+        self.busy.wait_for_high().await;
+    }
+}
+
+// Notices and echo for the screen, dropped if the display has fallen
+// behind.  What hosts send waits for room instead.
+fn show(bytes: &[u8]) {
+    let _ = SCREEN.try_write(bytes);
+}
+
+// Bytes typed on a terminal route, dropped if its reader has fallen behind
+fn type_bytes(route: Route, typed: &Typed) {
+    let pipe = match route {
+        Route::Usb => return,
+        Route::Local => &LOCAL_INPUT,
+        Route::Serial => &SERIAL_INPUT,
+        Route::Console => &CONSOLE_INPUT,
+    };
+    if !typed.is_empty() && pipe.try_write(typed).map_or(true, |n| n < typed.len()) {
+        warn!("Dropped keystrokes for {:?}", Debug2Format(&route));
+    }
+}
+
+fn send(link: &mut Link, message: Message) -> Option<Frame> {
+    match link.send(message, Instant::now()) {
+        Ok(frame) => frame,
+        Err(LinkError::Full) => {
+            warn!("link full, dropped {:?}", Debug2Format(&message));
+            None
+        }
+        Err(LinkError::Message(_)) => unreachable!(),
+    }
+}
+
+async fn write(tx: &mut BufferedUartTx<'_, UART1>, frames: impl IntoIterator<Item = Frame>) {
+    for frame in frames {
+        if let Err(e) = tx.write_all(&frame).await {
+            warn!("Failed to write to the link: {:?}", e);
+        }
+    }
 }
 
 struct MyRequestHandler {}
@@ -132,6 +1069,12 @@ impl RequestHandler for MyRequestHandler {
 
     fn set_report(&mut self, id: ReportId, data: &[u8]) -> OutResponse {
         info!("Set report for {:?}: {=[u8]}", id, data);
+        // the only output report is the LEDs, for the bottom half
+        if let (ReportId::Out(_), Some(leds)) = (id, data.first()) {
+            if COMMANDS.try_send(Message::Leds(*leds)).is_err() {
+                warn!("Dropped LEDs {:08b}", leds);
+            }
+        }
         OutResponse::Accepted
     }
 
@@ -143,23 +1086,46 @@ impl RequestHandler for MyRequestHandler {
         info!("Get idle rate for {:?}", id);
         None
     }
+
+    fn get_protocol(&self) -> HidProtocolMode {
+        if BOOT_PROTOCOL.load(Ordering::Relaxed) {
+            HidProtocolMode::Boot
+        } else {
+            HidProtocolMode::Report
+        }
+    }
+
+    fn set_protocol(&mut self, protocol: HidProtocolMode) -> OutResponse {
+        info!("Switching to {:?} protocol", protocol);
+        BOOT_PROTOCOL.store(protocol == HidProtocolMode::Boot, Ordering::Relaxed);
+        OutResponse::Accepted
+    }
 }
 
-struct MyDeviceHandler {
-    configured: AtomicBool,
+// GET_REPORT on the consumer, system and mouse interface
+struct ExtraRequestHandler {}
+
+impl RequestHandler for ExtraRequestHandler {
+    fn get_report(&mut self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        match id {
+            ReportId::In(id) => EXTRAS.lock(|extras| extras.get().report(id, buf)),
+            _ => None,
+        }
+    }
 }
 
+struct MyDeviceHandler {}
+
 impl MyDeviceHandler {
-    fn new() -> Self {
-        MyDeviceHandler {
-            configured: AtomicBool::new(false),
-        }
+    fn set_configured(&mut self, configured: bool) {
+        CONFIGURED.store(configured, Ordering::Relaxed);
+        DEVICE_STATE.signal(());
     }
 }
 
 impl Handler for MyDeviceHandler {
     fn enabled(&mut self, enabled: bool) {
-        self.configured.store(false, Ordering::Relaxed);
+        self.set_configured(false);
         if enabled {
             info!("Device enabled");
         } else {
@@ -168,21 +1134,37 @@ impl Handler for MyDeviceHandler {
     }
 
     fn reset(&mut self) {
-        self.configured.store(false, Ordering::Relaxed);
+        SUSPENDED.store(false, Ordering::Relaxed);
+        self.set_configured(false);
         info!("Bus reset, the Vbus current limit is 100mA");
     }
 
     fn addressed(&mut self, addr: u8) {
-        self.configured.store(false, Ordering::Relaxed);
+        self.set_configured(false);
         info!("USB address set to: {}", addr);
     }
 
     fn configured(&mut self, configured: bool) {
-        self.configured.store(configured, Ordering::Relaxed);
+        self.set_configured(configured);
         if configured {
             info!("Device configured, it may now draw up to the configured current limit from Vbus.")
         } else {
             info!("Device is no longer configured, the Vbus current limit is 100mA.");
         }
     }
+
+    fn suspended(&mut self, suspended: bool) {
+        SUSPENDED.store(suspended, Ordering::Relaxed);
+        DEVICE_STATE.signal(());
+        if suspended {
+            info!("Device suspended");
+        } else {
+            info!("Device resumed");
+        }
+    }
+
+    fn remote_wakeup_enabled(&mut self, enabled: bool) {
+        REMOTE_WAKEUP_ENABLED.store(enabled, Ordering::Relaxed);
+        info!("Remote wakeup {}", if enabled { "enabled" } else { "disabled" });
+    }
 }
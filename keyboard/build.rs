@@ -0,0 +1,124 @@
+// Compiles every layout in layouts/ into a module of `keyboard::layouts`:
+// the matrix pins and a `keymap!`, checked here for shape so that a
+// mistake in the file reads as one.  `keyboard::layouts::board` is the one
+// named by TEEB_BOARD, pcb-lite if it isn't set.
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use toml::{Table, Value};
+
+// GPIOs the bottom half's firmware takes for itself, on any board
+const RESERVED: [(i64, &str); 2] = [(4, "the split link's UART TX"), (5, "the split link's UART RX")];
+
+fn main() {
+    println!("cargo:rerun-if-changed=layouts");
+    println!("cargo:rerun-if-env-changed=TEEB_BOARD");
+    let mut paths: Vec<_> = fs::read_dir("layouts")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+        .collect();
+    paths.sort();
+
+    let board = env::var("TEEB_BOARD").unwrap_or("pcb-lite".into());
+    if !paths.iter().any(|path| path.file_stem().is_some_and(|stem| *stem == *board)) {
+        panic!("TEEB_BOARD: there's no layouts/{board}.toml");
+    }
+
+    let mut out = String::new();
+    for path in paths {
+        let layout = layout(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        out.push_str(&layout);
+    }
+    writeln!(out, "// the board built for, from TEEB_BOARD").unwrap();
+    writeln!(out, "pub use {} as board;", board.replace('-', "_")).unwrap();
+    fs::write(Path::new(&env::var_os("OUT_DIR").unwrap()).join("layouts.rs"), out).unwrap();
+}
+
+fn layout(path: &Path) -> Result<String, String> {
+    let table: Table = fs::read_to_string(path).unwrap().parse().map_err(|e| format!("{e}"))?;
+    let mcu = table.get("board").and_then(|board| board.get("mcu")).and_then(Value::as_str);
+    let gpios = match mcu {
+        Some("rp2350a") => 30,
+        Some("rp2350b") => 48,
+        _ => return Err(format!("board.mcu must be \"rp2350a\" or \"rp2350b\", not {mcu:?}")),
+    };
+    let matrix = table.get("matrix").and_then(Value::as_table).ok_or("no [matrix]")?;
+    let rows = pins(matrix, "rows", gpios)?;
+    let columns = pins(matrix, "columns", gpios)?;
+    for (i, pin) in columns.iter().enumerate() {
+        if rows.contains(pin) {
+            return Err(format!("matrix.columns[{i}]: GPIO {pin} is a row too"));
+        }
+    }
+
+    let layers = table.get("layer").and_then(Value::as_array).ok_or("no [[layer]]")?;
+    if layers.is_empty() || layers.len() > 32 {
+        return Err("there must be 1 to 32 layers".into());
+    }
+    let mut keymap = String::new();
+    for (n, layer) in layers.iter().enumerate() {
+        let keys = layer.get("keys").and_then(Value::as_array).ok_or(format!("layer {n} has no keys"))?;
+        if keys.len() != rows.len() {
+            return Err(format!("layer {n} has {} rows, the matrix {}", keys.len(), rows.len()));
+        }
+        keymap.push_str("        [\n");
+        for (r, row) in keys.iter().enumerate() {
+            let row = row.as_array().ok_or(format!("layer {n} row {r} isn't a list"))?;
+            if row.len() != columns.len() {
+                return Err(format!("layer {n} row {r} has {} keys, the matrix {}", row.len(), columns.len()));
+            }
+            keymap.push_str("            [");
+            for key in row {
+                let key = key.as_str().filter(|key| is_key(key)).ok_or(format!("layer {n} row {r}: bad key {key:?}"))?;
+                write!(keymap, "{key}, ").unwrap();
+            }
+            keymap.push_str("],\n");
+        }
+        keymap.push_str("        ],\n");
+    }
+
+    let name = path.file_stem().unwrap().to_str().unwrap().replace('-', "_");
+    let mut out = String::new();
+    writeln!(out, "// from {}", path.display()).unwrap();
+    writeln!(out, "pub mod {name} {{").unwrap();
+    writeln!(out, "    pub const ROWS: usize = {};", rows.len()).unwrap();
+    writeln!(out, "    pub const COLUMNS: usize = {};", columns.len()).unwrap();
+    writeln!(out, "    pub const LAYERS: usize = {};", layers.len()).unwrap();
+    writeln!(out, "    pub const ROW_PINS: [u8; ROWS] = {rows:?};").unwrap();
+    writeln!(out, "    pub const COLUMN_PINS: [u8; COLUMNS] = {columns:?};").unwrap();
+    writeln!(out, "    pub const KEYMAP: crate::keymap::Keymap<ROWS, COLUMNS, LAYERS> = crate::keymap! {{").unwrap();
+    out.push_str(&keymap);
+    writeln!(out, "    }};\n}}").unwrap();
+    Ok(out)
+}
+
+// GPIO numbers the MCU has, each once and none the firmware takes
+fn pins(matrix: &Table, name: &str, gpios: i64) -> Result<Vec<u8>, String> {
+    let pins = matrix.get(name).and_then(Value::as_array).ok_or(format!("no {name} in [matrix]"))?;
+    if pins.is_empty() {
+        return Err(format!("matrix.{name} is empty"));
+    }
+    let mut out = Vec::new();
+    for (i, pin) in pins.iter().enumerate() {
+        let pin = pin.as_integer().ok_or(format!("matrix.{name}[{i}] isn't a GPIO number"))?;
+        if !(0..gpios).contains(&pin) {
+            return Err(format!("matrix.{name}[{i}]: the MCU has no GPIO {pin}, only 0 to {}", gpios - 1));
+        }
+        if let Some((_, what)) = RESERVED.iter().find(|(reserved, _)| *reserved == pin) {
+            return Err(format!("matrix.{name}[{i}]: GPIO {pin} is {what}"));
+        }
+        if out.contains(&(pin as u8)) {
+            return Err(format!("matrix.{name}[{i}]: GPIO {pin} is wired twice"));
+        }
+        out.push(pin as u8);
+    }
+    Ok(out)
+}
+
+// Only what `keymap!` takes, so nothing else ends up in the code
+fn is_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || "_(), ".contains(c))
+}
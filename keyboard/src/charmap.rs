@@ -0,0 +1,417 @@
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage::{self, *};
+
+use crate::macros::ascii;
+
+// The host's keyboard layout, as the device types text itself
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Layout {
+    Us,
+    Uk,
+    De,
+    Dvorak,
+}
+
+// What a key types: a character, or a dead key's accent, as its combining
+// character, waiting on the next key
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Symbol {
+    Char(char),
+    Dead(char),
+}
+
+const ACUTE: char = '\u{301}';
+const GRAVE: char = '\u{300}';
+const CIRCUMFLEX: char = '\u{302}';
+const TILDE: char = '\u{303}';
+const DIAERESIS: char = '\u{308}';
+const CEDILLA: char = '\u{327}';
+
+// Keys that differ from US, plain, shifted and with AltGr, nothing as '\0'.
+// Combining characters are dead keys.
+type Keys = &'static [(KeyboardUsage, [char; 3])];
+
+const UK: Keys = &[
+    (KeyboardBacktickTilde, ['`', '¬', '¦']),
+    (Keyboard2At, ['2', '"', '\0']),
+    (Keyboard3Hash, ['3', '£', '\0']),
+    (Keyboard4Dollar, ['4', '$', '€']),
+    (KeyboardSingleDoubleQuote, ['\'', '@', '\0']),
+    (KeyboardBackslashBar, ['#', '~', '\0']),
+    (KeyboardNonUSHash, ['#', '~', '\0']),
+    (KeyboardNonUSSlash, ['\\', '|', '\0']),
+];
+
+const DE: Keys = &[
+    (KeyboardBacktickTilde, [CIRCUMFLEX, '°', '\0']),
+    (Keyboard2At, ['2', '"', '²']),
+    (Keyboard3Hash, ['3', '§', '³']),
+    (Keyboard6Caret, ['6', '&', '\0']),
+    (Keyboard7Ampersand, ['7', '/', '{']),
+    (Keyboard8Asterisk, ['8', '(', '[']),
+    (Keyboard9OpenParens, ['9', ')', ']']),
+    (Keyboard0CloseParens, ['0', '=', '}']),
+    (KeyboardDashUnderscore, ['ß', '?', '\\']),
+    (KeyboardEqualPlus, [ACUTE, GRAVE, '\0']),
+    (KeyboardQq, ['q', 'Q', '@']),
+    (KeyboardEe, ['e', 'E', '€']),
+    (KeyboardYy, ['z', 'Z', '\0']),
+    (KeyboardOpenBracketBrace, ['ü', 'Ü', '\0']),
+    (KeyboardCloseBracketBrace, ['+', '*', '~']),
+    (KeyboardSemiColon, ['ö', 'Ö', '\0']),
+    (KeyboardSingleDoubleQuote, ['ä', 'Ä', '\0']),
+    (KeyboardBackslashBar, ['#', '\'', '\0']),
+    (KeyboardNonUSHash, ['#', '\'', '\0']),
+    (KeyboardZz, ['y', 'Y', '\0']),
+    (KeyboardMm, ['m', 'M', 'µ']),
+    (KeyboardCommaLess, [',', ';', '\0']),
+    (KeyboardPeriodGreater, ['.', ':', '\0']),
+    (KeyboardSlashQuestion, ['-', '_', '\0']),
+    (KeyboardNonUSSlash, ['<', '>', '|']),
+];
+
+const DVORAK: Keys = &[
+    (KeyboardDashUnderscore, ['[', '{', '\0']),
+    (KeyboardEqualPlus, [']', '}', '\0']),
+    (KeyboardQq, ['\'', '"', '\0']),
+    (KeyboardWw, [',', '<', '\0']),
+    (KeyboardEe, ['.', '>', '\0']),
+    (KeyboardRr, ['p', 'P', '\0']),
+    (KeyboardTt, ['y', 'Y', '\0']),
+    (KeyboardYy, ['f', 'F', '\0']),
+    (KeyboardUu, ['g', 'G', '\0']),
+    (KeyboardIi, ['c', 'C', '\0']),
+    (KeyboardOo, ['r', 'R', '\0']),
+    (KeyboardPp, ['l', 'L', '\0']),
+    (KeyboardOpenBracketBrace, ['/', '?', '\0']),
+    (KeyboardCloseBracketBrace, ['=', '+', '\0']),
+    (KeyboardSs, ['o', 'O', '\0']),
+    (KeyboardDd, ['e', 'E', '\0']),
+    (KeyboardFf, ['u', 'U', '\0']),
+    (KeyboardGg, ['i', 'I', '\0']),
+    (KeyboardHh, ['d', 'D', '\0']),
+    (KeyboardJj, ['h', 'H', '\0']),
+    (KeyboardKk, ['t', 'T', '\0']),
+    (KeyboardLl, ['n', 'N', '\0']),
+    (KeyboardSemiColon, ['s', 'S', '\0']),
+    (KeyboardSingleDoubleQuote, ['-', '_', '\0']),
+    (KeyboardZz, [';', ':', '\0']),
+    (KeyboardXx, ['q', 'Q', '\0']),
+    (KeyboardCc, ['j', 'J', '\0']),
+    (KeyboardVv, ['k', 'K', '\0']),
+    (KeyboardBb, ['x', 'X', '\0']),
+    (KeyboardNn, ['b', 'B', '\0']),
+    (KeyboardCommaLess, ['w', 'W', '\0']),
+    (KeyboardPeriodGreater, ['v', 'V', '\0']),
+    (KeyboardSlashQuestion, ['z', 'Z', '\0']),
+];
+
+// Control, Alt (the left one) and GUI, either hand, as report modifier bits
+const COMMAND: u8 = 0x9d;
+const SHIFT: u8 = 0x22;
+const ALT_GR: u8 = 0x40;
+
+// What a key types with `modifiers` held.  Nothing for keys that don't type,
+// or held with Control, Alt or GUI.  Caps Lock shifts letters only.
+pub fn symbol(layout: Layout, usage: KeyboardUsage, modifiers: u8, caps_lock: bool) -> Option<Symbol> {
+    if modifiers & COMMAND != 0 {
+        return None;
+    }
+    let shift = modifiers & SHIFT != 0;
+    let level = if modifiers & ALT_GR != 0 { 2 } else { shift as usize };
+    let keys = match layout {
+        Layout::Us => &[][..],
+        Layout::Uk => UK,
+        Layout::De => DE,
+        Layout::Dvorak => DVORAK,
+    };
+    let c = match keys.iter().find(|(u, _)| *u == usage) {
+        Some((_, chars)) => chars[level],
+        None if level < 2 => (b' '..0x7f).find(|byte| ascii(*byte) == Some((usage, shift)))? as char,
+        None => return None,
+    };
+    match c {
+        '\0' => None,
+        '\u{300}'..='\u{36f}' => Some(Symbol::Dead(c)),
+        c if caps_lock && c.is_alphabetic() && level < 2 => Some(Symbol::Char(swap_case(c))),
+        c => Some(Symbol::Char(c)),
+    }
+}
+
+// Only where it's one character the other way: not ß
+fn swap_case(c: char) -> char {
+    let swapped: &mut dyn Iterator<Item = char> = match c.is_uppercase() {
+        true => &mut c.to_lowercase(),
+        false => &mut c.to_uppercase(),
+    };
+    match (swapped.next(), swapped.next()) {
+        (Some(swapped), None) => swapped,
+        _ => c,
+    }
+}
+
+// Accents and the letters they go on, each with the letter it makes
+const ACCENTS: &[(char, char, &str, &str)] = &[
+    (ACUTE, '´', "aeiouyAEIOUYcnszCNSZ", "áéíóúýÁÉÍÓÚÝćńśźĆŃŚŹ"),
+    (GRAVE, '`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    (CIRCUMFLEX, '^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    (TILDE, '~', "anoANO", "ãñõÃÑÕ"),
+    (DIAERESIS, '¨', "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+    (CEDILLA, '¸', "cC", "çÇ"),
+];
+
+// A letter with an accent, the accent as its combining character
+pub fn accent(accent: char, letter: char) -> Option<char> {
+    let (_, _, letters, accented) = ACCENTS.iter().find(|(a, ..)| *a == accent)?;
+    accented.chars().nth(letters.chars().position(|l| l == letter)?)
+}
+
+// The accent on its own, as typed by a dead key and then Space
+fn spacing(accent: char) -> char {
+    ACCENTS.iter().find(|(a, ..)| *a == accent).map_or(accent, |(_, spacing, ..)| *spacing)
+}
+
+// Compose sequences beyond an accent and a letter, as X11 has them.  Either
+// order will do.
+const COMPOSE: &[(&str, char)] = &[
+    ("ss", 'ß'),
+    ("ae", 'æ'),
+    ("AE", 'Æ'),
+    ("oe", 'œ'),
+    ("OE", 'Œ'),
+    ("o/", 'ø'),
+    ("O/", 'Ø'),
+    ("oa", 'å'),
+    ("oA", 'Å'),
+    ("!!", '¡'),
+    ("??", '¿'),
+    ("<<", '«'),
+    (">>", '»'),
+    ("=e", '€'),
+    ("-L", '£'),
+    ("=Y", '¥'),
+    ("oc", '©'),
+    ("or", '®'),
+    ("so", '§'),
+    ("oo", '°'),
+    ("12", '½'),
+    ("14", '¼'),
+    ("34", '¾'),
+    ("+-", '±'),
+    ("xx", '×'),
+    (":-", '÷'),
+];
+
+// The character the Compose key makes of two more: an accent given as the
+// ASCII character most like it, then a letter, or one from `COMPOSE`
+pub fn compose(first: char, second: char) -> Option<char> {
+    let accented = |mark: char, letter: char| {
+        let combining = match mark {
+            '\'' => ACUTE,
+            '`' => GRAVE,
+            '^' => CIRCUMFLEX,
+            '~' => TILDE,
+            '"' => DIAERESIS,
+            ',' => CEDILLA,
+            _ => return None,
+        };
+        accent(combining, letter)
+    };
+    let table = |a: char, b: char| {
+        COMPOSE.iter().find(|(keys, _)| keys.chars().eq([a, b])).map(|(_, c)| *c)
+    };
+    accented(first, second)
+        .or_else(|| accented(second, first))
+        .or_else(|| table(first, second))
+        .or_else(|| table(second, first))
+}
+
+pub type Chars = Vec<char, 2>;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Pending {
+    None,
+    Dead(char),
+    Compose,
+    Composing(char),
+}
+
+// Turns key presses into text, through dead keys and the Compose key.  Caps
+// Lock is kept here, toggled by its key.
+pub struct Composer {
+    layout: Layout,
+    compose: KeyboardUsage,
+    caps_lock: bool,
+    pending: Pending,
+}
+
+impl Composer {
+    pub const fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            compose: KeyboardApplication,
+            caps_lock: false,
+            pending: Pending::None,
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        self.pending = Pending::None;
+    }
+
+    // Menu by default
+    pub fn set_compose(&mut self, usage: KeyboardUsage) {
+        self.compose = usage;
+    }
+
+    pub fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+
+    // What a key pressed types, nothing for now if it's part of a dead key
+    // or Compose sequence.  None for keys that aren't text at all, which
+    // also end any sequence, except the modifiers.
+    pub fn press(&mut self, usage: KeyboardUsage, modifiers: u8) -> Option<Chars> {
+        let mut chars = Chars::new();
+        if usage == self.compose {
+            self.pending = Pending::Compose;
+            return Some(chars);
+        }
+        if usage == KeyboardCapsLock {
+            self.caps_lock = !self.caps_lock;
+            return Some(chars);
+        }
+        let Some(symbol) = symbol(self.layout, usage, modifiers, self.caps_lock) else {
+            if !(0xe0..=0xe7).contains(&(usage as u8)) {
+                self.pending = Pending::None;
+            }
+            return None;
+        };
+
+        let pending = core::mem::replace(&mut self.pending, Pending::None);
+        match (pending, symbol) {
+            (Pending::None, Symbol::Char(c)) => {
+                let _ = chars.push(c);
+            }
+            (Pending::None, Symbol::Dead(a)) => self.pending = Pending::Dead(a),
+            (Pending::Dead(a), Symbol::Char(c)) => {
+                if let Some(accented) = accent(a, c) {
+                    let _ = chars.push(accented);
+                } else {
+                    let _ = chars.push(spacing(a));
+                    if c != ' ' {
+                        let _ = chars.push(c);
+                    }
+                }
+            }
+            // twice for the accent itself
+            (Pending::Dead(a), Symbol::Dead(b)) => {
+                let _ = chars.push(spacing(a));
+                if a != b {
+                    self.pending = Pending::Dead(b);
+                }
+            }
+            (Pending::Compose, Symbol::Char(c)) => self.pending = Pending::Composing(c),
+            (Pending::Compose, Symbol::Dead(a)) => self.pending = Pending::Composing(spacing(a)),
+            // sequences that make nothing are dropped, as X11 does
+            (Pending::Composing(first), Symbol::Char(c)) => chars.extend(compose(first, c)),
+            (Pending::Composing(first), Symbol::Dead(a)) => chars.extend(compose(first, spacing(a))),
+        }
+        Some(chars)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compose, symbol, Composer, Layout, Symbol};
+    use crate::keymap::KeyboardUsage::{self, *};
+
+    fn typed(composer: &mut Composer, keys: &[(KeyboardUsage, u8)]) -> heapless::String<32> {
+        let mut text = heapless::String::new();
+        for (usage, modifiers) in keys {
+            for c in composer.press(*usage, *modifiers).unwrap_or_default() {
+                text.push(c).unwrap();
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn test_symbol() {
+        assert_eq!(symbol(Layout::Us, KeyboardAa, 0, false), Some(Symbol::Char('a')));
+        assert_eq!(symbol(Layout::Us, Keyboard2At, 0x20, false), Some(Symbol::Char('@')));
+        assert_eq!(symbol(Layout::Us, KeyboardAa, 0x01, false), None);
+        assert_eq!(symbol(Layout::Us, KeyboardAa, 0x40, false), None);
+        assert_eq!(symbol(Layout::Uk, Keyboard3Hash, 0x02, false), Some(Symbol::Char('£')));
+        assert_eq!(symbol(Layout::Uk, KeyboardNonUSHash, 0, false), Some(Symbol::Char('#')));
+        assert_eq!(symbol(Layout::De, KeyboardZz, 0, false), Some(Symbol::Char('y')));
+        assert_eq!(symbol(Layout::De, KeyboardQq, 0x40, false), Some(Symbol::Char('@')));
+        assert_eq!(symbol(Layout::De, KeyboardSemiColon, 0, true), Some(Symbol::Char('Ö')));
+        assert_eq!(symbol(Layout::De, KeyboardDashUnderscore, 0, true), Some(Symbol::Char('ß')));
+        assert_eq!(symbol(Layout::De, KeyboardEqualPlus, 0x02, false), Some(Symbol::Dead('\u{300}')));
+        assert_eq!(symbol(Layout::Dvorak, KeyboardSs, 0x02, false), Some(Symbol::Char('O')));
+        assert_eq!(symbol(Layout::Dvorak, KeyboardQq, 0, false), Some(Symbol::Char('\'')));
+        // Caps Lock leaves digits alone, and Shift undoes it
+        assert_eq!(symbol(Layout::Us, Keyboard1Exclamation, 0, true), Some(Symbol::Char('1')));
+        assert_eq!(symbol(Layout::Us, KeyboardAa, 0x02, true), Some(Symbol::Char('a')));
+        assert_eq!(symbol(Layout::Us, KeyboardF1, 0, false), None);
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        let mut composer = Composer::new(Layout::De);
+        let text = typed(
+            &mut composer,
+            &[
+                (KeyboardEqualPlus, 0),
+                (KeyboardEe, 0),
+                (KeyboardLeftShift, 0x02),
+                (KeyboardEqualPlus, 0x02),
+                (KeyboardAa, 0x02),
+                (KeyboardBacktickTilde, 0),
+                (KeyboardSpacebar, 0),
+                (KeyboardBacktickTilde, 0),
+                (KeyboardXx, 0),
+                (KeyboardEqualPlus, 0),
+                (KeyboardEqualPlus, 0),
+            ],
+        );
+        assert_eq!(text, "éÀ^^x´");
+    }
+
+    #[test]
+    fn test_compose() {
+        assert_eq!(compose('"', 'u'), Some('ü'));
+        assert_eq!(compose('e', '\''), Some('é'));
+        assert_eq!(compose('/', 'o'), Some('ø'));
+        assert_eq!(compose('q', 'q'), None);
+
+        let mut composer = Composer::new(Layout::Us);
+        composer.set_compose(KeyboardRightAlt);
+        let text = typed(
+            &mut composer,
+            &[
+                (KeyboardRightAlt, 0x40),
+                (KeyboardSs, 0),
+                (KeyboardSs, 0),
+                (KeyboardRightAlt, 0x40),
+                (KeyboardCommaLess, 0),
+                (KeyboardCc, 0),
+                (KeyboardCapsLock, 0),
+                (KeyboardRightAlt, 0x40),
+                (KeyboardSingleDoubleQuote, 0),
+                (KeyboardEe, 0),
+                // nothing made of these, and the arrow ends it
+                (KeyboardRightAlt, 0x40),
+                (KeyboardQq, 0),
+                (KeyboardQq, 0),
+                (KeyboardRightAlt, 0x40),
+                (KeyboardLeftArrow, 0),
+                (KeyboardAa, 0),
+            ],
+        );
+        assert_eq!(text, "ßçÉA");
+        assert!(composer.caps_lock());
+    }
+}
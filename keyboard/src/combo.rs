@@ -0,0 +1,221 @@
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::keymap::Action;
+use crate::state::Event;
+
+// Keys that, pressed together within the combo term, do `action` instead
+// of what they each do
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Combo<'a> {
+    pub keys: &'a [(usize, usize)],
+    pub action: Action,
+}
+
+// What the layers are fed: matrix events, plus combos going down and up
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Input {
+    Event(Event),
+    Combo { action: Action, pressed: bool },
+}
+
+pub type Inputs = Vec<(Input, Instant), 8>;
+
+// Holds back presses of combo keys until a combo completes, can no longer
+// complete or the combo term runs out, then passes them on with the time
+// they actually happened
+pub struct Combos<'a> {
+    combos: &'a [Combo<'a>],
+    term: Duration,
+    pending: Vec<(Event, Instant), 4>,
+    // per combo, which of its keys are still held since it fired
+    engaged: [u8; 8],
+}
+
+impl<'a> Combos<'a> {
+    pub fn new(combos: &'a [Combo<'a>], term: Duration) -> Self {
+        assert!(combos.len() <= 8 && combos.iter().all(|combo| combo.keys.len() <= 4));
+        Self {
+            combos,
+            term,
+            pending: Vec::new(),
+            engaged: [0; 8],
+        }
+    }
+
+    pub fn set_term(&mut self, term: Duration) {
+        self.term = term;
+    }
+
+    pub fn update(&mut self, event: Event, now: Instant) -> Inputs {
+        let mut inputs = Inputs::new();
+        match event {
+            Event::Press { row, column } if self.is_combo_key(row, column) => {
+                let _ = self.pending.push((event, now));
+                if let Some(index) = self.completed() {
+                    self.fire(index, now, &mut inputs);
+                } else if !self.possible() {
+                    self.flush(&mut inputs);
+                }
+            }
+            Event::Press { .. } => {
+                self.flush(&mut inputs);
+                let _ = inputs.push((Input::Event(event), now));
+            }
+            Event::Release { row, column } => {
+                if let Some(index) = self.engaged_by(row, column) {
+                    let key = self.combos[index].keys.iter().position(|key| *key == (row, column)).unwrap();
+                    let first = self.engaged[index] == (1 << self.combos[index].keys.len()) - 1;
+                    self.engaged[index] &= !(1 << key);
+                    if first {
+                        let _ = inputs.push((
+                            Input::Combo {
+                                action: self.combos[index].action,
+                                pressed: false,
+                            },
+                            now,
+                        ));
+                    }
+                } else {
+                    self.flush(&mut inputs);
+                    let _ = inputs.push((Input::Event(event), now));
+                }
+            }
+        }
+        inputs
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Inputs {
+        let mut inputs = Inputs::new();
+        if let Some((_, since)) = self.pending.first() {
+            if now.saturating_duration_since(*since) >= self.term {
+                self.flush(&mut inputs);
+            }
+        }
+        inputs
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.first().map(|(_, since)| *since + self.term)
+    }
+
+    fn is_combo_key(&self, row: usize, column: usize) -> bool {
+        self.combos.iter().any(|combo| combo.keys.contains(&(row, column)))
+    }
+
+    fn is_pending(&self, key: &(usize, usize)) -> bool {
+        self.pending
+            .iter()
+            .any(|(event, _)| matches!(event, Event::Press { row, column } if (*row, *column) == *key))
+    }
+
+    fn completed(&self) -> Option<usize> {
+        self.combos
+            .iter()
+            .position(|combo| combo.keys.iter().all(|key| self.is_pending(key)))
+    }
+
+    // Whether some combo still has all of the pending keys
+    fn possible(&self) -> bool {
+        self.combos.iter().any(|combo| {
+            self.pending.iter().all(|(event, _)| match event {
+                Event::Press { row, column } => combo.keys.contains(&(*row, *column)),
+                Event::Release { .. } => false,
+            })
+        })
+    }
+
+    fn engaged_by(&self, row: usize, column: usize) -> Option<usize> {
+        (0..self.combos.len()).find(|index| {
+            self.combos[*index]
+                .keys
+                .iter()
+                .position(|key| *key == (row, column))
+                .is_some_and(|key| self.engaged[*index] & 1 << key != 0)
+        })
+    }
+
+    fn fire(&mut self, index: usize, now: Instant, inputs: &mut Inputs) {
+        let combo = self.combos[index];
+        self.pending.retain(|(event, _)| match event {
+            Event::Press { row, column } => !combo.keys.contains(&(*row, *column)),
+            Event::Release { .. } => true,
+        });
+        self.engaged[index] = (1 << combo.keys.len()) - 1;
+        let _ = inputs.push((
+            Input::Combo {
+                action: combo.action,
+                pressed: true,
+            },
+            now,
+        ));
+    }
+
+    fn flush(&mut self, inputs: &mut Inputs) {
+        for (event, since) in self.pending.iter() {
+            let _ = inputs.push((Input::Event(*event), *since));
+        }
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use super::{Combo, Combos, Input};
+    use crate::keymap::{Action, KeyboardUsage};
+    use crate::state::Event;
+
+    const ESCAPE: Action = Action::Key(KeyboardUsage::KeyboardEscape);
+    const COMBOS: [Combo; 1] = [Combo {
+        keys: &[(0, 0), (0, 1)],
+        action: ESCAPE,
+    }];
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn press(column: usize) -> Event {
+        Event::Press { row: 0, column }
+    }
+
+    fn release(column: usize) -> Event {
+        Event::Release { row: 0, column }
+    }
+
+    #[test]
+    fn test_combo() {
+        let mut combos = Combos::new(&COMBOS, Duration::from_millis(50));
+        assert!(combos.update(press(0), at(0)).is_empty());
+        assert_eq!(combos.deadline(), Some(at(50)));
+        assert_eq!(
+            combos.update(press(1), at(10)),
+            [(Input::Combo { action: ESCAPE, pressed: true }, at(10))]
+        );
+        assert_eq!(
+            combos.update(release(1), at(30)),
+            [(Input::Combo { action: ESCAPE, pressed: false }, at(30))]
+        );
+        assert!(combos.update(release(0), at(40)).is_empty());
+        assert_eq!(combos.update(press(2), at(50)), [(Input::Event(press(2)), at(50))]);
+    }
+
+    #[test]
+    fn test_no_combo() {
+        let mut combos = Combos::new(&COMBOS, Duration::from_millis(50));
+        combos.update(press(0), at(0));
+        assert!(combos.tick(at(49)).is_empty());
+        assert_eq!(combos.tick(at(50)), [(Input::Event(press(0)), at(0))]);
+
+        // another key breaks the chord up, in order
+        combos.update(release(0), at(60));
+        combos.update(press(1), at(70));
+        assert_eq!(
+            combos.update(press(2), at(80)),
+            [(Input::Event(press(1)), at(70)), (Input::Event(press(2)), at(80))]
+        );
+        assert_eq!(combos.update(release(1), at(90)), [(Input::Event(release(1)), at(90))]);
+    }
+}
@@ -0,0 +1,145 @@
+use core::fmt::Write;
+
+use crate::serial::Bytes;
+
+// xterm's request for the text area's size, in characters
+const SIZE_QUERY: &[u8] = b"\x1b[18t";
+
+// The device's end of a USB serial console, such as a getty on the host:
+// what the host sends is shown, and keystrokes, as bytes from the router,
+// wait until the host has the port open (DTR) and is ready for them (RTS).
+// Asked for its size, it answers as xterm does, with the text grid it's
+// shown on.
+pub struct Console {
+    columns: u16,
+    rows: u16,
+    dtr: bool,
+    rts: bool,
+    seven_bit: bool,
+    // how much of SIZE_QUERY the host has sent so far
+    matched: usize,
+    sending: Bytes,
+}
+
+impl Console {
+    pub const fn new(columns: u16, rows: u16) -> Self {
+        Self {
+            columns,
+            rows,
+            dtr: false,
+            rts: false,
+            seven_bit: false,
+            matched: 0,
+            sending: Bytes::new(),
+        }
+    }
+
+    // From the host's line coding: with 7 data bits the top bit is stripped
+    // both ways
+    pub fn set_data_bits(&mut self, bits: u8) {
+        self.seven_bit = bits == 7;
+    }
+
+    // The host's control lines.  What to show, if the port was just opened
+    // or closed; what was waiting to go is dropped on closing.
+    pub fn set_lines(&mut self, dtr: bool, rts: bool) -> Option<&'static [u8]> {
+        let opened = dtr && !self.dtr;
+        let closed = !dtr && self.dtr;
+        self.dtr = dtr;
+        self.rts = rts;
+        if opened {
+            Some(b"\r\n[console connected]\r\n")
+        } else if closed {
+            self.sending.clear();
+            Some(b"\r\n[console hung up]\r\n")
+        } else {
+            None
+        }
+    }
+
+    pub fn typed(&mut self, bytes: &[u8]) {
+        if !self.dtr {
+            return;
+        }
+        for byte in bytes {
+            // dropped once the host has fallen too far behind
+            let _ = self.sending.push(self.strip(*byte));
+        }
+    }
+
+    // What the host sent, as it's to be shown
+    pub fn received(&mut self, bytes: &[u8]) -> Bytes {
+        let mut show = Bytes::new();
+        for byte in bytes {
+            let byte = self.strip(*byte);
+            let _ = show.push(byte);
+            self.matched = match self.matched {
+                n if byte == SIZE_QUERY[n] => n + 1,
+                _ if byte == SIZE_QUERY[0] => 1,
+                _ => 0,
+            };
+            if self.matched == SIZE_QUERY.len() {
+                self.matched = 0;
+                let mut reply = Bytes::new();
+                let _ = write!(reply, "\x1b[8;{};{}t", self.rows, self.columns);
+                for byte in reply {
+                    let _ = self.sending.push(byte);
+                }
+            }
+        }
+        show
+    }
+
+    // The bytes the host is ready for, if any
+    pub fn take_sending(&mut self) -> Option<Bytes> {
+        if !self.dtr || !self.rts || self.sending.is_empty() {
+            return None;
+        }
+        Some(core::mem::take(&mut self.sending))
+    }
+
+    fn strip(&self, byte: u8) -> u8 {
+        if self.seven_bit {
+            byte & 0x7f
+        } else {
+            byte
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Console;
+
+    #[test]
+    fn test_lines() {
+        let mut console = Console::new(89, 20);
+        console.typed(b"lost");
+        assert!(console.set_lines(true, false).is_some());
+        console.typed(b"root\r");
+        assert_eq!(console.take_sending(), None);
+        assert_eq!(console.set_lines(true, true), None);
+        assert_eq!(console.take_sending().unwrap(), b"root\r");
+        assert_eq!(console.take_sending(), None);
+
+        console.typed(b"x");
+        assert!(console.set_lines(false, false).is_some());
+        console.set_lines(true, true);
+        assert_eq!(console.take_sending(), None);
+    }
+
+    #[test]
+    fn test_received() {
+        let mut console = Console::new(80, 24);
+        console.set_lines(true, true);
+        assert_eq!(console.received(b"a\x1b[1"), b"a\x1b[1");
+        assert_eq!(console.take_sending(), None);
+        assert_eq!(console.received(b"8t\x1b\x1b[18t"), b"8t\x1b\x1b[18t");
+        assert_eq!(console.take_sending().unwrap(), b"\x1b[8;24;80t\x1b[8;24;80t");
+
+        console.set_data_bits(7);
+        assert_eq!(console.received(&[0xc1]), b"A");
+        console.typed(&[0xe9]);
+        assert_eq!(console.take_sending().unwrap(), b"i");
+    }
+}
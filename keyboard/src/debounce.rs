@@ -0,0 +1,213 @@
+use embassy_time::{Duration, Instant};
+
+use crate::matrix::Scan;
+
+// Per-key debouncing: fed the raw switch reading on every scan, returns the
+// debounced state
+pub trait Debounce: Copy {
+    fn new(window: Duration) -> Self;
+
+    fn update(&mut self, raw: bool, now: Instant) -> bool;
+}
+
+// Presses are reported as soon as they are seen.  Releases are only reported
+// once the switch has read open for the whole window, so bounce on either
+// edge never produces a spurious release.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Eager {
+    window: Duration,
+    pressed: bool,
+    // when the switch last read closed
+    closed: Instant,
+}
+
+impl Debounce for Eager {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pressed: false,
+            closed: Instant::MIN,
+        }
+    }
+
+    fn update(&mut self, raw: bool, now: Instant) -> bool {
+        if raw {
+            self.pressed = true;
+            self.closed = now;
+        } else if self.pressed && now.saturating_duration_since(self.closed) >= self.window {
+            self.pressed = false;
+        }
+        self.pressed
+    }
+}
+
+// Any change is only reported once the switch has read the same for the
+// whole window
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Deferred {
+    window: Duration,
+    pressed: bool,
+    raw: bool,
+    changed: Instant,
+}
+
+impl Debounce for Deferred {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pressed: false,
+            raw: false,
+            changed: Instant::MIN,
+        }
+    }
+
+    fn update(&mut self, raw: bool, now: Instant) -> bool {
+        if raw != self.raw {
+            self.raw = raw;
+            self.changed = now;
+        }
+        if self.raw != self.pressed && now.saturating_duration_since(self.changed) >= self.window {
+            self.pressed = self.raw;
+        }
+        self.pressed
+    }
+}
+
+// Integrates time spent closed against time spent open.  The key is pressed
+// once the integrator fills up to the window and released once it drains
+// back to empty, so occasional noise in a long run is absorbed.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Integrator {
+    window: Duration,
+    pressed: bool,
+    level: Duration,
+    last: Option<Instant>,
+}
+
+impl Debounce for Integrator {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pressed: false,
+            level: Duration::from_ticks(0),
+            last: None,
+        }
+    }
+
+    fn update(&mut self, raw: bool, now: Instant) -> bool {
+        let elapsed = match self.last {
+            Some(last) => now.saturating_duration_since(last),
+            None => Duration::from_ticks(0),
+        };
+        self.last = Some(now);
+
+        self.level = if raw {
+            (self.level + elapsed).min(self.window)
+        } else {
+            Duration::from_ticks(self.level.as_ticks().saturating_sub(elapsed.as_ticks()))
+        };
+
+        if self.level >= self.window {
+            self.pressed = true;
+        } else if self.level == Duration::from_ticks(0) {
+            self.pressed = false;
+        }
+        self.pressed
+    }
+}
+
+pub struct Debouncer<D: Debounce, const ROWS: usize, const COLUMNS: usize> {
+    keys: [[D; COLUMNS]; ROWS],
+}
+
+impl<D: Debounce, const ROWS: usize, const COLUMNS: usize> Debouncer<D, ROWS, COLUMNS> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            keys: [[D::new(window); COLUMNS]; ROWS],
+        }
+    }
+
+    pub fn debounce(&mut self, scan: &Scan<ROWS, COLUMNS>, now: Instant) -> Scan<ROWS, COLUMNS> {
+        let mut debounced = [[false; COLUMNS]; ROWS];
+        for (row, keys) in self.keys.iter_mut().enumerate() {
+            for (column, key) in keys.iter_mut().enumerate() {
+                debounced[row][column] = key.update(scan[row][column], now);
+            }
+        }
+        debounced
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use super::{Debounce, Debouncer, Deferred, Eager, Integrator};
+
+    // switch closing at 10ms with 3ms of bounce and opening at 40ms with 2ms
+    // of bounce, sampled every millisecond
+    const BOUNCY: [bool; 60] = {
+        let mut trace = [false; 60];
+        let mut t = 10;
+        while t < 40 {
+            trace[t] = true;
+            t += 1;
+        }
+        trace[11] = false;
+        trace[12] = true;
+        trace[13] = false;
+        trace[41] = true;
+        trace[42] = false;
+        trace[43] = true;
+        trace
+    };
+
+    // times, in ms, at which the debounced state changes
+    fn edges<D: Debounce>(trace: &[bool]) -> heapless::Vec<(u64, bool), 8> {
+        let mut key = D::new(Duration::from_millis(5));
+        let mut pressed = false;
+        let mut edges = heapless::Vec::new();
+        for (t, raw) in trace.iter().enumerate() {
+            let now = Instant::from_millis(t as u64);
+            if key.update(*raw, now) != pressed {
+                pressed = !pressed;
+                edges.push((t as u64, pressed)).unwrap();
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn test_eager() {
+        assert_eq!(edges::<Eager>(&BOUNCY), [(10, true), (48, false)]);
+    }
+
+    #[test]
+    fn test_deferred() {
+        assert_eq!(edges::<Deferred>(&BOUNCY), [(19, true), (49, false)]);
+    }
+
+    #[test]
+    fn test_integrator() {
+        assert_eq!(edges::<Integrator>(&BOUNCY), [(18, true), (48, false)]);
+    }
+
+    #[test]
+    fn test_noise_ignored() {
+        let mut trace = [false; 30];
+        trace[5] = true;
+        trace[20] = true;
+        trace[21] = true;
+        assert_eq!(edges::<Deferred>(&trace), []);
+        assert_eq!(edges::<Integrator>(&trace), []);
+    }
+
+    #[test]
+    fn test_debouncer() {
+        let mut debouncer: Debouncer<Deferred, 1, 2> = Debouncer::new(Duration::from_millis(5));
+        for t in 0..5 {
+            assert_eq!(debouncer.debounce(&[[true, false]], Instant::from_millis(t)), [[false, false]]);
+        }
+        assert_eq!(debouncer.debounce(&[[true, false]], Instant::from_millis(5)), [[true, false]]);
+    }
+}
@@ -0,0 +1,271 @@
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::matrix::Scan;
+
+// What a key has been up to, for finding bad switches, sockets and diodes
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct KeyStats {
+    pub presses: u32,
+    // bursts of transitions closer together than the debounce window
+    pub chatter: u16,
+    // times it read closed as a corner of a rectangle of closed switches,
+    // as a missing or shorted diode makes a fourth key appear for three
+    pub ghosts: u16,
+    // since it went down, zero while it's up
+    pub held: Duration,
+    pub stuck: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Tracker {
+    presses: u32,
+    chatter: u16,
+    ghosts: u16,
+    raw: bool,
+    // the last raw transition, and whether it came inside the window of
+    // the one before
+    edge: Option<Instant>,
+    bouncing: bool,
+    pressed: bool,
+    down: Instant,
+    stuck: bool,
+    corner: bool,
+}
+
+impl Tracker {
+    const fn new() -> Self {
+        Self {
+            presses: 0,
+            chatter: 0,
+            ghosts: 0,
+            raw: false,
+            edge: None,
+            bouncing: false,
+            pressed: false,
+            down: Instant::MIN,
+            stuck: false,
+            corner: false,
+        }
+    }
+}
+
+// What changed in a scan: whether any switch reads differently, and the keys
+// whose stats are worth reporting, having been released, chattered, ghosted
+// or become stuck
+#[derive(Debug, Eq, PartialEq)]
+pub struct Findings {
+    pub matrix: bool,
+    pub keys: Vec<(usize, usize), 8>,
+}
+
+// Watches the raw and debounced scans side by side.  A key held down for
+// `stuck_after` is reported stuck, once, until it's released.
+pub struct Diagnostics<const ROWS: usize, const COLUMNS: usize> {
+    window: Duration,
+    stuck_after: Duration,
+    raw: Scan<ROWS, COLUMNS>,
+    keys: [[Tracker; COLUMNS]; ROWS],
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Diagnostics<ROWS, COLUMNS> {
+    pub const fn new(window: Duration, stuck_after: Duration) -> Self {
+        Self {
+            window,
+            stuck_after,
+            raw: [[false; COLUMNS]; ROWS],
+            keys: [[Tracker::new(); COLUMNS]; ROWS],
+        }
+    }
+
+    // The switches as last read, before debouncing
+    pub fn raw(&self) -> &Scan<ROWS, COLUMNS> {
+        &self.raw
+    }
+
+    pub fn stats(&self, row: usize, column: usize, now: Instant) -> Option<KeyStats> {
+        let key = self.keys.get(row)?.get(column)?;
+        Some(KeyStats {
+            presses: key.presses,
+            chatter: key.chatter,
+            ghosts: key.ghosts,
+            held: match key.pressed {
+                true => now.saturating_duration_since(key.down),
+                false => Duration::from_ticks(0),
+            },
+            stuck: key.stuck,
+        })
+    }
+
+    // Starts the counts over, as for a new key test
+    pub fn reset(&mut self) {
+        for key in self.keys.iter_mut().flatten() {
+            key.presses = 0;
+            key.chatter = 0;
+            key.ghosts = 0;
+        }
+    }
+
+    pub fn update(&mut self, raw: &Scan<ROWS, COLUMNS>, debounced: &Scan<ROWS, COLUMNS>, now: Instant) -> Findings {
+        let mut findings = Findings {
+            matrix: *raw != self.raw,
+            keys: Vec::new(),
+        };
+        let corners = corners(raw);
+        for (row, keys) in self.keys.iter_mut().enumerate() {
+            for (column, key) in keys.iter_mut().enumerate() {
+                let mut report = false;
+                if raw[row][column] != key.raw {
+                    let bounce = key.edge.is_some_and(|edge| now.saturating_duration_since(edge) < self.window);
+                    if bounce && !key.bouncing {
+                        key.chatter = key.chatter.saturating_add(1);
+                        report = true;
+                    }
+                    key.bouncing = bounce;
+                    key.raw = raw[row][column];
+                    key.edge = Some(now);
+                }
+
+                if debounced[row][column] != key.pressed {
+                    key.pressed = debounced[row][column];
+                    if key.pressed {
+                        key.presses = key.presses.saturating_add(1);
+                        key.down = now;
+                    } else {
+                        key.stuck = false;
+                        report = true;
+                    }
+                }
+                if key.pressed && !key.stuck && now.saturating_duration_since(key.down) >= self.stuck_after {
+                    key.stuck = true;
+                    report = true;
+                }
+
+                let corner = corners[row][column];
+                if corner && !key.corner {
+                    key.ghosts = key.ghosts.saturating_add(1);
+                    report = true;
+                }
+                key.corner = corner;
+
+                if report {
+                    // past the eighth in one scan, a key waits for its next
+                    // finding
+                    let _ = findings.keys.push((row, column));
+                }
+            }
+        }
+        self.raw = *raw;
+        findings
+    }
+}
+
+// The closed switches that are corners of a rectangle: two rows closed in
+// the same two columns
+fn corners<const ROWS: usize, const COLUMNS: usize>(scan: &Scan<ROWS, COLUMNS>) -> Scan<ROWS, COLUMNS> {
+    let mut corners = [[false; COLUMNS]; ROWS];
+    for a in 0..ROWS {
+        for b in a + 1..ROWS {
+            let shared = (0..COLUMNS).filter(|c| scan[a][*c] && scan[b][*c]).count();
+            if shared < 2 {
+                continue;
+            }
+            for c in (0..COLUMNS).filter(|c| scan[a][*c] && scan[b][*c]) {
+                corners[a][c] = true;
+                corners[b][c] = true;
+            }
+        }
+    }
+    corners
+}
+
+// A scan as a bit per switch, row by row, least significant bit first, as
+// sent over the split link.  Switches past the end of `out` are left off.
+pub fn pack<const ROWS: usize, const COLUMNS: usize>(scan: &Scan<ROWS, COLUMNS>, out: &mut [u8]) {
+    out.fill(0);
+    for (i, closed) in scan.iter().flatten().enumerate() {
+        if let Some(byte) = out.get_mut(i / 8) {
+            *byte |= (*closed as u8) << (i % 8);
+        }
+    }
+}
+
+pub fn unpack<const ROWS: usize, const COLUMNS: usize>(bytes: &[u8]) -> Scan<ROWS, COLUMNS> {
+    let mut scan = [[false; COLUMNS]; ROWS];
+    for (i, closed) in scan.iter_mut().flatten().enumerate() {
+        *closed = bytes.get(i / 8).is_some_and(|byte| byte & 1 << (i % 8) != 0);
+    }
+    scan
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use super::{pack, unpack, Diagnostics};
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn test_presses_and_chatter() {
+        let mut diagnostics: Diagnostics<1, 2> = Diagnostics::new(Duration::from_millis(5), Duration::from_secs(10));
+        let up = [[false, false]];
+        let down = [[true, false]];
+
+        let findings = diagnostics.update(&down, &down, at(0));
+        assert!(findings.matrix);
+        assert!(findings.keys.is_empty());
+        // bouncing open and closed again inside the window
+        assert_eq!(diagnostics.update(&up, &down, at(1)).keys, [(0, 0)]);
+        assert!(diagnostics.update(&down, &down, at(2)).keys.is_empty());
+        assert!(!diagnostics.update(&down, &down, at(3)).matrix);
+
+        diagnostics.update(&up, &down, at(50));
+        assert_eq!(diagnostics.update(&up, &up, at(55)).keys, [(0, 0)]);
+        let stats = diagnostics.stats(0, 0, at(60)).unwrap();
+        assert_eq!((stats.presses, stats.chatter, stats.ghosts), (1, 1, 0));
+        assert_eq!(stats.held, Duration::from_ticks(0));
+        assert_eq!(diagnostics.stats(1, 0, at(60)), None);
+    }
+
+    #[test]
+    fn test_stuck() {
+        let mut diagnostics: Diagnostics<1, 1> = Diagnostics::new(Duration::from_millis(5), Duration::from_secs(10));
+        diagnostics.update(&[[true]], &[[true]], at(0));
+        assert!(diagnostics.update(&[[true]], &[[true]], at(9_999)).keys.is_empty());
+        assert_eq!(diagnostics.update(&[[true]], &[[true]], at(10_000)).keys, [(0, 0)]);
+        assert!(diagnostics.update(&[[true]], &[[true]], at(20_000)).keys.is_empty());
+        let stats = diagnostics.stats(0, 0, at(20_000)).unwrap();
+        assert!(stats.stuck);
+        assert_eq!(stats.held, Duration::from_secs(20));
+
+        diagnostics.update(&[[false]], &[[false]], at(20_001));
+        assert!(!diagnostics.stats(0, 0, at(20_001)).unwrap().stuck);
+    }
+
+    #[test]
+    fn test_ghosts() {
+        let mut diagnostics: Diagnostics<2, 3> = Diagnostics::new(Duration::from_millis(5), Duration::from_secs(10));
+        let three = [[true, true, false], [true, false, false]];
+        diagnostics.update(&three, &three, at(0));
+        let four = [[true, true, false], [true, true, false]];
+        let findings = diagnostics.update(&four, &four, at(10));
+        assert_eq!(findings.keys, [(0, 0), (0, 1), (1, 0), (1, 1)]);
+        assert_eq!(diagnostics.stats(1, 1, at(10)).unwrap().ghosts, 1);
+        assert!(diagnostics.update(&four, &four, at(20)).keys.is_empty());
+
+        diagnostics.reset();
+        assert_eq!(diagnostics.stats(1, 1, at(20)).unwrap().ghosts, 0);
+    }
+
+    #[test]
+    fn test_pack() {
+        let scan = [[true, false, false], [false, false, true], [true, true, true]];
+        let mut bytes = [0; 2];
+        pack(&scan, &mut bytes);
+        assert_eq!(bytes, [0b1110_0001, 0b0000_0001]);
+        assert_eq!(unpack::<3, 3>(&bytes), scan);
+    }
+}
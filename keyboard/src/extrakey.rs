@@ -0,0 +1,271 @@
+use crate::keymap::Action;
+use crate::layer::Key;
+use crate::mousekey::MouseKey;
+
+// Consumer page usages, in the order of QMK's keycodes 0xa8 to 0xbe
+pub const MUTE: u16 = 0x00e2;
+pub const VOLUME_UP: u16 = 0x00e9;
+pub const VOLUME_DOWN: u16 = 0x00ea;
+pub const NEXT_TRACK: u16 = 0x00b5;
+pub const PREVIOUS_TRACK: u16 = 0x00b6;
+pub const STOP: u16 = 0x00b7;
+pub const PLAY_PAUSE: u16 = 0x00cd;
+pub const MEDIA_SELECT: u16 = 0x0183;
+pub const EJECT: u16 = 0x00b8;
+pub const MAIL: u16 = 0x018a;
+pub const CALCULATOR: u16 = 0x0192;
+pub const MY_COMPUTER: u16 = 0x0194;
+pub const WWW_SEARCH: u16 = 0x0221;
+pub const WWW_HOME: u16 = 0x0223;
+pub const WWW_BACK: u16 = 0x0224;
+pub const WWW_FORWARD: u16 = 0x0225;
+pub const WWW_STOP: u16 = 0x0226;
+pub const WWW_REFRESH: u16 = 0x0227;
+pub const WWW_FAVORITES: u16 = 0x022a;
+pub const FAST_FORWARD: u16 = 0x00b3;
+pub const REWIND: u16 = 0x00b4;
+pub const BRIGHTNESS_UP: u16 = 0x006f;
+pub const BRIGHTNESS_DOWN: u16 = 0x0070;
+
+pub const CONSUMER_USAGES: [u16; 23] = [
+    MUTE,
+    VOLUME_UP,
+    VOLUME_DOWN,
+    NEXT_TRACK,
+    PREVIOUS_TRACK,
+    STOP,
+    PLAY_PAUSE,
+    MEDIA_SELECT,
+    EJECT,
+    MAIL,
+    CALCULATOR,
+    MY_COMPUTER,
+    WWW_SEARCH,
+    WWW_HOME,
+    WWW_BACK,
+    WWW_FORWARD,
+    WWW_STOP,
+    WWW_REFRESH,
+    WWW_FAVORITES,
+    FAST_FORWARD,
+    REWIND,
+    BRIGHTNESS_UP,
+    BRIGHTNESS_DOWN,
+];
+
+// Generic desktop system control usages, QMK's keycodes 0xa5 to 0xa7
+pub const POWER: u16 = 0x0081;
+pub const SLEEP: u16 = 0x0082;
+pub const WAKE: u16 = 0x0083;
+
+pub const SYSTEM_USAGES: [u16; 3] = [POWER, SLEEP, WAKE];
+
+pub const CONSUMER_ID: u8 = 1;
+pub const SYSTEM_ID: u8 = 2;
+pub const MOUSE_ID: u8 = 3;
+
+// Reports with their ID first
+pub const CONSUMER_LEN: usize = 3;
+pub const SYSTEM_LEN: usize = 3;
+pub const MOUSE_LEN: usize = 6;
+
+// The keys a keyboard report can't carry, one report ID each: a consumer
+// and a system control usage, and a mouse with eight buttons, a wheel and
+// horizontal pan
+#[rustfmt::skip]
+pub const DESCRIPTOR: &[u8] = &[
+    0x05, 0x0c,       // Usage Page (Consumer)
+    0x09, 0x01,       // Usage (Consumer Control)
+    0xa1, 0x01,       // Collection (Application)
+    0x85, 0x01,       //   Report ID (1)
+    0x19, 0x01,       //   Usage Minimum (1)
+    0x2a, 0xa0, 0x02, //   Usage Maximum (0x2a0)
+    0x15, 0x01,       //   Logical Minimum (1)
+    0x26, 0xa0, 0x02, //   Logical Maximum (0x2a0)
+    0x95, 0x01,       //   Report Count (1)
+    0x75, 0x10,       //   Report Size (16)
+    0x81, 0x00,       //   Input (Data, Array, Absolute)
+    0xc0,             // End Collection
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x80,       // Usage (System Control)
+    0xa1, 0x01,       // Collection (Application)
+    0x85, 0x02,       //   Report ID (2)
+    0x19, 0x01,       //   Usage Minimum (1)
+    0x2a, 0xb7, 0x00, //   Usage Maximum (0xb7)
+    0x15, 0x01,       //   Logical Minimum (1)
+    0x26, 0xb7, 0x00, //   Logical Maximum (0xb7)
+    0x95, 0x01,       //   Report Count (1)
+    0x75, 0x10,       //   Report Size (16)
+    0x81, 0x00,       //   Input (Data, Array, Absolute)
+    0xc0,             // End Collection
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x02,       // Usage (Mouse)
+    0xa1, 0x01,       // Collection (Application)
+    0x85, 0x03,       //   Report ID (3)
+    0x09, 0x01,       //   Usage (Pointer)
+    0xa1, 0x00,       //   Collection (Physical)
+    0x05, 0x09,       //     Usage Page (Button)
+    0x19, 0x01,       //     Usage Minimum (1)
+    0x29, 0x08,       //     Usage Maximum (8)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x95, 0x08,       //     Report Count (8)
+    0x75, 0x01,       //     Report Size (1)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute)
+    0x05, 0x01,       //     Usage Page (Generic Desktop)
+    0x09, 0x30,       //     Usage (X)
+    0x09, 0x31,       //     Usage (Y)
+    0x09, 0x38,       //     Usage (Wheel)
+    0x15, 0x81,       //     Logical Minimum (-127)
+    0x25, 0x7f,       //     Logical Maximum (127)
+    0x95, 0x03,       //     Report Count (3)
+    0x75, 0x08,       //     Report Size (8)
+    0x81, 0x06,       //     Input (Data, Variable, Relative)
+    0x05, 0x0c,       //     Usage Page (Consumer)
+    0x0a, 0x38, 0x02, //     Usage (AC Pan)
+    0x95, 0x01,       //     Report Count (1)
+    0x81, 0x06,       //     Input (Data, Variable, Relative)
+    0xc0,             //   End Collection
+    0xc0,             // End Collection
+];
+
+// As sent over the split link
+pub const EXTRAS_LEN: usize = 8;
+
+// Consumer, system and mouse keys held, alongside `Keys`.  One consumer
+// and one system usage at a time, the latest pressed; mouse keys by
+// `MouseKey` bit.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct Extras {
+    pub consumer: u16,
+    pub system: u16,
+    pub mouse: u32,
+}
+
+impl Extras {
+    pub const fn new() -> Self {
+        Self {
+            consumer: 0,
+            system: 0,
+            mouse: 0,
+        }
+    }
+
+    // Takes on a key from the layers, returning whether anything changed
+    pub fn apply(&mut self, key: Key) -> bool {
+        let before = *self;
+        let Key::Extra { action, down } = key else {
+            return false;
+        };
+        let update = |held: &mut u16, usage: u16| {
+            if down {
+                *held = usage;
+            } else if *held == usage {
+                *held = 0;
+            }
+        };
+        match action {
+            Action::Consumer(usage) => update(&mut self.consumer, usage),
+            Action::System(usage) => update(&mut self.system, usage),
+            Action::Mouse(key) if down => self.mouse |= key.bit(),
+            Action::Mouse(key) => self.mouse &= !key.bit(),
+            _ => {}
+        }
+        *self != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::new()
+    }
+
+    pub fn to_bytes(&self) -> [u8; EXTRAS_LEN] {
+        let mut bytes = [0; EXTRAS_LEN];
+        bytes[..2].copy_from_slice(&self.consumer.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.system.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.mouse.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; EXTRAS_LEN]) -> Self {
+        Self {
+            consumer: u16::from_le_bytes([bytes[0], bytes[1]]),
+            system: u16::from_le_bytes([bytes[2], bytes[3]]),
+            mouse: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+
+    pub fn consumer_report(&self) -> [u8; CONSUMER_LEN] {
+        let [low, high] = self.consumer.to_le_bytes();
+        [CONSUMER_ID, low, high]
+    }
+
+    pub fn system_report(&self) -> [u8; SYSTEM_LEN] {
+        let [low, high] = self.system.to_le_bytes();
+        [SYSTEM_ID, low, high]
+    }
+
+    // The buttons held, without movement
+    pub fn mouse_report(&self) -> [u8; MOUSE_LEN] {
+        [MOUSE_ID, MouseKey::buttons(self.mouse), 0, 0, 0, 0]
+    }
+
+    // The report for an ID, as asked for with GET_REPORT
+    pub fn report(&self, id: u8, out: &mut [u8]) -> Option<usize> {
+        let mut report = [0; MOUSE_LEN];
+        let len = match id {
+            CONSUMER_ID => {
+                report[..CONSUMER_LEN].copy_from_slice(&self.consumer_report());
+                CONSUMER_LEN
+            }
+            SYSTEM_ID => {
+                report[..SYSTEM_LEN].copy_from_slice(&self.system_report());
+                SYSTEM_LEN
+            }
+            MOUSE_ID => {
+                report = self.mouse_report();
+                MOUSE_LEN
+            }
+            _ => return None,
+        };
+        out.get_mut(..len)?.copy_from_slice(&report[..len]);
+        Some(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Extras, MUTE, SLEEP, VOLUME_UP};
+    use crate::keymap::Action;
+    use crate::layer::Key;
+    use crate::mousekey::MouseKey;
+
+    fn key(action: Action, down: bool) -> Key {
+        Key::Extra { action, down }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut extras = Extras::new();
+        assert!(extras.apply(key(Action::Consumer(MUTE), true)));
+        assert!(extras.apply(key(Action::Consumer(VOLUME_UP), true)));
+        // Mute let go after Volume Up took over
+        assert!(!extras.apply(key(Action::Consumer(MUTE), false)));
+        assert_eq!(extras.consumer_report(), [1, 0xe9, 0x00]);
+
+        extras.apply(key(Action::System(SLEEP), true));
+        extras.apply(key(Action::Mouse(MouseKey::Button2), true));
+        assert_eq!(extras.system_report(), [2, 0x82, 0x00]);
+        assert_eq!(extras.mouse_report(), [3, 0x02, 0, 0, 0, 0]);
+        assert_eq!(Extras::from_bytes(&extras.to_bytes()), extras);
+
+        let mut out = [0; 8];
+        assert_eq!(extras.report(2, &mut out), Some(3));
+        assert_eq!(out[..3], extras.system_report());
+        assert_eq!(extras.report(4, &mut out), None);
+
+        extras.apply(key(Action::Consumer(VOLUME_UP), false));
+        extras.apply(key(Action::System(SLEEP), false));
+        extras.apply(key(Action::Mouse(MouseKey::Button2), false));
+        assert!(extras.is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+use embassy_time::{Duration, Instant};
+
+// Decides when scanning can stop.  Fed after every scan with whether any
+// switch read closed, raw or debounced, and reports the matrix idle once
+// nothing has been seen for the whole timeout.
+pub struct Idle {
+    timeout: Duration,
+    active: Instant,
+}
+
+impl Idle {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self { timeout, active: now }
+    }
+
+    pub fn update(&mut self, active: bool, now: Instant) -> bool {
+        if active {
+            self.active = now;
+        }
+        self.is_idle(now)
+    }
+
+    pub fn is_idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.active) >= self.timeout
+    }
+
+    // Woken by something other than a scan, e.g. a GPIO edge
+    pub fn wake(&mut self, now: Instant) {
+        self.active = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use super::Idle;
+
+    #[test]
+    fn test_idle() {
+        let mut idle = Idle::new(Duration::from_millis(10), Instant::from_millis(0));
+        assert!(!idle.update(false, Instant::from_millis(9)));
+        assert!(!idle.update(true, Instant::from_millis(9)));
+        assert!(!idle.update(false, Instant::from_millis(18)));
+        assert!(idle.update(false, Instant::from_millis(19)));
+
+        idle.wake(Instant::from_millis(30));
+        assert!(!idle.is_idle(Instant::from_millis(39)));
+        assert!(idle.is_idle(Instant::from_millis(40)));
+    }
+}
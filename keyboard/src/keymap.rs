@@ -0,0 +1,334 @@
+pub use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::extrakey::{CONSUMER_USAGES, SYSTEM_USAGES};
+use crate::macros::Macro;
+use crate::mousekey::MouseKey;
+
+// What a key position does on one layer
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Action {
+    // does nothing
+    No,
+    // falls through to the next active layer down
+    Trans,
+    Key(KeyboardUsage),
+    // layer on while held (MO)
+    Momentary(u8),
+    // layer flipped on every press (TG)
+    Toggle(u8),
+    // layer on, every other layer but the base off (TO)
+    To(u8),
+    // layer on for the next key press only (OSL)
+    OneShot(u8),
+    // layer when held, the key when tapped (LT)
+    LayerTap(u8, KeyboardUsage),
+    // modifier when held, the key when tapped (MT)
+    ModTap(KeyboardUsage, KeyboardUsage),
+    Macro(Macro),
+    // a consumer page usage: media keys, brightness (`extrakey::MUTE` …)
+    Consumer(u16),
+    // a system control usage: `extrakey::POWER`, `SLEEP` or `WAKE`
+    System(u16),
+    Mouse(MouseKey),
+}
+
+// Actions as 16-bit codes, numbered as QMK keycodes so that host tools can
+// read them: the usage itself (up to 0xa4, and the modifiers), 0x2000 +
+// mods << 8 for mod-tap, 0x4000 + layer << 8 for layer-tap, and the layer
+// keys from 0x5200, macros from 0x7700 and dynamic macros from 0x7c53.
+// System, consumer and mouse keys take QMK's codes between the usages and
+// the modifiers, only the consumer usages QMK has a code for among them.
+const TRANS: u16 = 0x0001;
+const MOD_TAP: u16 = 0x2000;
+const LAYER_TAP: u16 = 0x4000;
+const TO: u16 = 0x5200;
+const MOMENTARY: u16 = 0x5220;
+const TOGGLE: u16 = 0x5260;
+const ONE_SHOT: u16 = 0x5280;
+const SYSTEM: u16 = 0x00a5;
+const CONSUMER: u16 = 0x00a8;
+const MOUSE: u16 = 0x00cd;
+const MACRO: u16 = 0x7700;
+const RECORD: u16 = 0x7c53;
+const STOP: u16 = 0x7c55;
+const REPLAY: u16 = 0x7c56;
+
+impl Action {
+    pub fn code(self) -> u16 {
+        match self {
+            Action::No => 0,
+            Action::Trans => TRANS,
+            Action::Key(usage) => usage as u16,
+            Action::Momentary(layer) => MOMENTARY | layer as u16,
+            Action::Toggle(layer) => TOGGLE | layer as u16,
+            Action::To(layer) => TO | layer as u16,
+            Action::OneShot(layer) => ONE_SHOT | layer as u16,
+            Action::LayerTap(layer, usage) => LAYER_TAP | (layer as u16) << 8 | usage as u16,
+            Action::ModTap(modifier, usage) => {
+                // five bits: Control, Shift, Alt, GUI and whether they are
+                // the right hand ones
+                let modifier = (modifier as u16).wrapping_sub(0xe0) & 7;
+                let mods = if modifier < 4 { 1 << modifier } else { 0x10 | 1 << (modifier - 4) };
+                MOD_TAP | mods << 8 | usage as u16
+            }
+            Action::Macro(Macro::Play(n)) => MACRO | n as u16,
+            Action::Macro(Macro::Record(slot)) => RECORD + slot as u16,
+            Action::Macro(Macro::Stop) => STOP,
+            Action::Macro(Macro::Replay(slot)) => REPLAY + slot as u16,
+            Action::Consumer(usage) => table_code(&CONSUMER_USAGES, CONSUMER, usage),
+            Action::System(usage) => table_code(&SYSTEM_USAGES, SYSTEM, usage),
+            Action::Mouse(key) => MOUSE + key.index() as u16,
+        }
+    }
+
+    // None for codes with no action here, including mod-taps of more than
+    // one modifier
+    pub fn from_code(code: u16) -> Option<Self> {
+        let usage = KeyboardUsage::from(code as u8);
+        let layer = (code & 0x1f) as u8;
+        Some(match code {
+            0 => Action::No,
+            TRANS => Action::Trans,
+            0x0004..=0x00a4 | 0x00e0..=0x00e7 => Action::Key(usage),
+            0x00a5..=0x00a7 => Action::System(SYSTEM_USAGES[(code - SYSTEM) as usize]),
+            0x00a8..=0x00be => Action::Consumer(CONSUMER_USAGES[(code - CONSUMER) as usize]),
+            0x00cd..=0x00df => Action::Mouse(MouseKey::from_index((code - MOUSE) as u8)?),
+            0x2000..=0x3fff => {
+                let mods = (code >> 8 & 0x1f) as u8;
+                let hand = if mods & 0x10 != 0 { 4 } else { 0 };
+                let mods = mods & 0x0f;
+                if !mods.is_power_of_two() {
+                    return None;
+                }
+                Action::ModTap(KeyboardUsage::from(0xe0 + hand + mods.trailing_zeros() as u8), usage)
+            }
+            0x4000..=0x4fff => Action::LayerTap((code >> 8 & 0x0f) as u8, usage),
+            0x5200..=0x521f => Action::To(layer),
+            0x5220..=0x523f => Action::Momentary(layer),
+            0x5260..=0x527f => Action::Toggle(layer),
+            0x5280..=0x529f => Action::OneShot(layer),
+            0x7700..=0x777f => Action::Macro(Macro::Play(code as u8 & 0x7f)),
+            0x7c53..=0x7c54 => Action::Macro(Macro::Record((code - RECORD) as u8)),
+            STOP => Action::Macro(Macro::Stop),
+            0x7c56..=0x7c57 => Action::Macro(Macro::Replay((code - REPLAY) as u8)),
+            _ => return None,
+        })
+    }
+}
+
+// The code of a usage by its place in a table, nothing if it has none
+fn table_code(usages: &[u16], first: u16, usage: u16) -> u16 {
+    usages.iter().position(|u| *u == usage).map_or(0, |i| first + i as u16)
+}
+
+// Layer 0 is the base layer and always active
+#[derive(Clone, Copy)]
+pub struct Keymap<const ROWS: usize, const COLUMNS: usize, const LAYERS: usize> {
+    layers: [[[Action; COLUMNS]; ROWS]; LAYERS],
+}
+
+impl<const ROWS: usize, const COLUMNS: usize, const LAYERS: usize> Keymap<ROWS, COLUMNS, LAYERS> {
+    pub const fn new(layers: [[[Action; COLUMNS]; ROWS]; LAYERS]) -> Self {
+        assert!(LAYERS > 0 && LAYERS <= 32);
+        Self { layers }
+    }
+
+    pub fn get(&self, layer: usize, row: usize, column: usize) -> Action {
+        self.layers[layer][row][column]
+    }
+
+    // Positions are also numbered layer by layer and row by row, as VIA
+    // numbers its keymap buffer
+    pub const POSITIONS: usize = LAYERS * ROWS * COLUMNS;
+
+    pub fn position(index: usize) -> (usize, usize, usize) {
+        (index / (ROWS * COLUMNS), index / COLUMNS % ROWS, index % COLUMNS)
+    }
+
+    pub fn code(&self, index: usize) -> u16 {
+        let (layer, row, column) = Self::position(index);
+        self.get(layer, row, column).code()
+    }
+
+    // Codes with no action here become `Action::No`
+    pub fn set_code(&mut self, index: usize, code: u16) {
+        let (layer, row, column) = Self::position(index);
+        self.set(layer, row, column, Action::from_code(code).unwrap_or(Action::No));
+    }
+
+    // Every code, big endian, as stored in flash
+    pub fn write_codes(&self, out: &mut [u8]) {
+        for (index, bytes) in out.chunks_exact_mut(2).take(Self::POSITIONS).enumerate() {
+            bytes.copy_from_slice(&self.code(index).to_be_bytes());
+        }
+    }
+
+    pub fn read_codes(&mut self, bytes: &[u8]) {
+        for (index, bytes) in bytes.chunks_exact(2).take(Self::POSITIONS).enumerate() {
+            self.set_code(index, u16::from_be_bytes([bytes[0], bytes[1]]));
+        }
+    }
+
+    // Returns false, changing nothing, for a position off the map
+    pub fn set(&mut self, layer: usize, row: usize, column: usize, action: Action) -> bool {
+        let key = self.layers.get_mut(layer).and_then(|layer| layer.get_mut(row)).and_then(|row| row.get_mut(column));
+        match key {
+            Some(key) => {
+                *key = action;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// Builds a `Keymap` from layers of rows of keys, each one of
+//
+//  KeyboardAa     the usage, by its `KeyboardUsage` name
+//  _              transparent
+//  XX             nothing
+//  MO(n) TG(n) TO(n) OSL(n) LT(n, KeyboardSpacebar)
+//  MT(KeyboardLeftControl, KeyboardEscape)
+//  CC(MUTE) SYS(SLEEP)
+//                 consumer and system keys, by their `extrakey` name
+//  MS(Left) MS(Button1)
+//                 mouse keys, by their `MouseKey` name
+//  M(n)           compiled macro n
+//  DM_REC1 DM_REC2 DM_RSTP DM_PLY1 DM_PLY2
+//                 record, stop and replay macros on the fly
+#[macro_export]
+macro_rules! keymap {
+    ($([$([$($key:tt $(($($arg:tt)*))?),* $(,)?]),* $(,)?]),* $(,)?) => {
+        $crate::keymap::Keymap::new([$([$([$($crate::action!($key $(($($arg)*))?)),*]),*]),*])
+    };
+}
+
+#[macro_export]
+macro_rules! action {
+    (_) => {
+        $crate::keymap::Action::Trans
+    };
+    (XX) => {
+        $crate::keymap::Action::No
+    };
+    (MO($layer:expr)) => {
+        $crate::keymap::Action::Momentary($layer)
+    };
+    (TG($layer:expr)) => {
+        $crate::keymap::Action::Toggle($layer)
+    };
+    (TO($layer:expr)) => {
+        $crate::keymap::Action::To($layer)
+    };
+    (OSL($layer:expr)) => {
+        $crate::keymap::Action::OneShot($layer)
+    };
+    (LT($layer:expr, $usage:ident)) => {
+        $crate::keymap::Action::LayerTap($layer, $crate::keymap::KeyboardUsage::$usage)
+    };
+    (MT($modifier:ident, $usage:ident)) => {
+        $crate::keymap::Action::ModTap(
+            $crate::keymap::KeyboardUsage::$modifier,
+            $crate::keymap::KeyboardUsage::$usage,
+        )
+    };
+    (CC($usage:ident)) => {
+        $crate::keymap::Action::Consumer($crate::extrakey::$usage)
+    };
+    (SYS($usage:ident)) => {
+        $crate::keymap::Action::System($crate::extrakey::$usage)
+    };
+    (MS($key:ident)) => {
+        $crate::keymap::Action::Mouse($crate::mousekey::MouseKey::$key)
+    };
+    (M($n:expr)) => {
+        $crate::keymap::Action::Macro($crate::macros::Macro::Play($n))
+    };
+    (DM_REC1) => {
+        $crate::keymap::Action::Macro($crate::macros::Macro::Record(0))
+    };
+    (DM_REC2) => {
+        $crate::keymap::Action::Macro($crate::macros::Macro::Record(1))
+    };
+    (DM_RSTP) => {
+        $crate::keymap::Action::Macro($crate::macros::Macro::Stop)
+    };
+    (DM_PLY1) => {
+        $crate::keymap::Action::Macro($crate::macros::Macro::Replay(0))
+    };
+    (DM_PLY2) => {
+        $crate::keymap::Action::Macro($crate::macros::Macro::Replay(1))
+    };
+    ($usage:ident) => {
+        $crate::keymap::Action::Key($crate::keymap::KeyboardUsage::$usage)
+    };
+}
+
+// The keymap of the pcb-lite board, as in layouts/pcb-lite.toml
+pub const TEEB: Keymap<5, 14, 2> = crate::layouts::pcb_lite::KEYMAP;
+
+#[cfg(test)]
+mod test {
+    use super::{Action, Keymap, KeyboardUsage::*, TEEB};
+    use crate::extrakey::{BRIGHTNESS_DOWN, SLEEP, VOLUME_UP};
+    use crate::macros::Macro;
+    use crate::mousekey::MouseKey;
+
+    #[test]
+    fn test_codes() {
+        for layer in 0..2 {
+            for row in 0..5 {
+                for column in 0..14 {
+                    let action = TEEB.get(layer, row, column);
+                    assert_eq!(Action::from_code(action.code()), Some(action));
+                }
+            }
+        }
+        assert_eq!(Action::Key(KeyboardAa).code(), 0x0004);
+        assert_eq!(Action::Momentary(1).code(), 0x5221);
+        assert_eq!(Action::LayerTap(2, KeyboardSpacebar).code(), 0x422c);
+        assert_eq!(Action::ModTap(KeyboardLeftControl, KeyboardEscape).code(), 0x2129);
+        assert_eq!(Action::ModTap(KeyboardRightAlt, KeyboardEnter).code(), 0x3428);
+        assert_eq!(Action::from_code(0x3428), Some(Action::ModTap(KeyboardRightAlt, KeyboardEnter)));
+
+        assert_eq!(Action::Macro(Macro::Play(3)).code(), 0x7703);
+        assert_eq!(Action::Consumer(VOLUME_UP).code(), 0x00a9);
+        assert_eq!(Action::from_code(0x00be), Some(Action::Consumer(BRIGHTNESS_DOWN)));
+        assert_eq!(Action::from_code(0x00a6), Some(Action::System(SLEEP)));
+        assert_eq!(Action::from_code(0x00d1), Some(Action::Mouse(MouseKey::Button1)));
+        assert_eq!(Action::Mouse(MouseKey::Accel2).code(), 0x00df);
+        // a consumer usage without a code
+        assert_eq!(Action::Consumer(0x0030).code(), 0);
+        assert_eq!(Action::from_code(0x7c57), Some(Action::Macro(Macro::Replay(1))));
+
+        // Control+Shift mod-tap, and a user keycode
+        assert_eq!(Action::from_code(0x2329), None);
+        assert_eq!(Action::from_code(0x7e00), None);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut keymap = TEEB;
+        assert!(keymap.set(1, 4, 4, Action::Key(KeyboardEnter)));
+        assert_eq!(keymap.get(1, 4, 4), Action::Key(KeyboardEnter));
+        assert!(!keymap.set(2, 0, 0, Action::No));
+        assert!(!keymap.set(0, 0, 14, Action::No));
+    }
+
+    #[test]
+    fn test_codes_buffer() {
+        // layer 1, row 2, column 3: End
+        assert_eq!(Keymap::<5, 14, 2>::position(70 + 28 + 3), (1, 2, 3));
+        assert_eq!(TEEB.code(70 + 28 + 3), KeyboardEnd as u16);
+
+        let mut bytes = [0; 2 * Keymap::<5, 14, 2>::POSITIONS];
+        TEEB.write_codes(&mut bytes);
+        assert_eq!(bytes[2 * 58..2 * 59], [0x52, 0x21]);
+
+        let mut keymap = TEEB;
+        keymap.set_code(0, 0x7e00);
+        assert_eq!(keymap.get(0, 0, 0), Action::No);
+        keymap.read_codes(&bytes);
+        assert_eq!(keymap.get(0, 0, 0), Action::Key(KeyboardBacktickTilde));
+    }
+}
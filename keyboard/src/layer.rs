@@ -0,0 +1,621 @@
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::combo::Input;
+use crate::keymap::{Action, Keymap};
+use crate::macros::Macro;
+use crate::state::Event;
+
+// A usage going down or up, the result of running a matrix event through
+// the layers
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Key {
+    Down(KeyboardUsage),
+    Up(KeyboardUsage),
+    // a macro key pressed, for the macros to play
+    Macro(Macro),
+    // a consumer, system or mouse key going down or up
+    Extra { action: Action, down: bool },
+}
+
+// Inputs a pending dual-role key holds back
+const QUEUE: usize = 8;
+
+// The most one update can put out: the pending dual-role key, what's queued
+// behind it and the new input, each a tap at worst (a press and a release).
+// Each of those can end one key override and start another: the override's
+// key or replacement, and up to 8 modifiers put back, then the replacement
+// and up to 8 lifted.
+pub type Output = Vec<Key, { (1 + QUEUE + 1) * 2 * (2 + 2 * 8) }>;
+
+// How a dual-role key (LT, MT) that is still held decides between tap and
+// hold.  Released within the tapping term it taps, held past it it holds.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct TapHold {
+    pub tapping_term: Duration,
+    // another key pressed and released within the term makes it a hold
+    pub permissive_hold: bool,
+    // any other key pressed within the term makes it a hold
+    pub hold_on_other_key_press: bool,
+}
+
+impl TapHold {
+    pub const DEFAULT: Self = Self {
+        tapping_term: Duration::from_millis(200),
+        permissive_hold: false,
+        hold_on_other_key_press: false,
+    };
+}
+
+impl Default for TapHold {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// While `modifiers` are held, `key` sends `replacement` with those
+// modifiers lifted, e.g. Shift+Backspace as Delete
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct KeyOverride {
+    pub modifiers: u8,
+    pub key: KeyboardUsage,
+    pub replacement: KeyboardUsage,
+}
+
+// A dual-role key waiting to be decided, and what came after it
+struct Pending {
+    row: usize,
+    column: usize,
+    since: Instant,
+    queue: Deque<(Input, Instant), QUEUE>,
+}
+
+// The layer stack.  The action a key resolves to is remembered while it is
+// held, so that a release undoes its own press even if the layers changed
+// in between.
+pub struct Layers<'a, const ROWS: usize, const COLUMNS: usize, const LAYERS: usize> {
+    keymap: Keymap<ROWS, COLUMNS, LAYERS>,
+    tap_hold: TapHold,
+    overrides: &'a [KeyOverride],
+    active: u32,
+    oneshot: Option<u8>,
+    oneshot_held: bool,
+    held: [[Action; COLUMNS]; ROWS],
+    // most recently pressed position, to tell a tap from a hold
+    last: Option<(usize, usize)>,
+    pending: Option<Pending>,
+    // modifiers down, and an override in progress: its key, the
+    // replacement sent for it and the modifiers lifted
+    modifiers: u8,
+    overridden: Option<(KeyboardUsage, KeyboardUsage, u8)>,
+}
+
+impl<'a, const ROWS: usize, const COLUMNS: usize, const LAYERS: usize> Layers<'a, ROWS, COLUMNS, LAYERS> {
+    pub const fn new(keymap: Keymap<ROWS, COLUMNS, LAYERS>) -> Self {
+        Self {
+            keymap,
+            tap_hold: TapHold::DEFAULT,
+            overrides: &[],
+            active: 1,
+            oneshot: None,
+            oneshot_held: false,
+            held: [[Action::No; COLUMNS]; ROWS],
+            last: None,
+            pending: None,
+            modifiers: 0,
+            overridden: None,
+        }
+    }
+
+    pub fn set_tap_hold(&mut self, tap_hold: TapHold) {
+        self.tap_hold = tap_hold;
+    }
+
+    pub fn set_overrides(&mut self, overrides: &'a [KeyOverride]) {
+        self.overrides = overrides;
+    }
+
+    // Keys held keep doing what they did when pressed
+    pub fn keymap_mut(&mut self) -> &mut Keymap<ROWS, COLUMNS, LAYERS> {
+        &mut self.keymap
+    }
+
+    pub fn is_active(&self, layer: u8) -> bool {
+        self.layers() & 1 << layer != 0
+    }
+
+    // Highest active layer
+    pub fn top(&self) -> u8 {
+        31 - self.layers().leading_zeros() as u8
+    }
+
+    // Action at a position on the current layers, following transparent
+    // keys down
+    pub fn resolve(&self, row: usize, column: usize) -> Action {
+        let layers = self.layers();
+        (0..LAYERS)
+            .rev()
+            .filter(|layer| layers & 1 << layer != 0)
+            .map(|layer| self.keymap.get(layer, row, column))
+            .find(|action| *action != Action::Trans)
+            .unwrap_or(Action::No)
+    }
+
+    pub fn update(&mut self, input: Input, now: Instant) -> Output {
+        let mut keys = Output::new();
+        self.tick_into(now, &mut keys);
+        self.feed(input, now, &mut keys);
+        keys
+    }
+
+    // Decides a dual-role key whose tapping term has run out
+    pub fn tick(&mut self, now: Instant) -> Output {
+        let mut keys = Output::new();
+        self.tick_into(now, &mut keys);
+        keys
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending
+            .as_ref()
+            .map(|pending| pending.since + self.tap_hold.tapping_term)
+    }
+
+    fn tick_into(&mut self, now: Instant, keys: &mut Output) {
+        while self.deadline().is_some_and(|deadline| now >= deadline) {
+            self.decide(false, keys);
+        }
+    }
+
+    fn feed(&mut self, input: Input, now: Instant, keys: &mut Output) {
+        let Some(pending) = self.pending.as_mut() else {
+            return self.process(input, now, keys);
+        };
+
+        match input {
+            Input::Event(Event::Release { row, column }) if (row, column) == (pending.row, pending.column) => {
+                self.decide(true, keys);
+                self.feed(input, now, keys);
+            }
+            _ => {
+                // a key pressed and released since the dual-role key went down
+                let permissive = matches!(input, Input::Event(Event::Release { row, column })
+                    if pending.queue.iter().any(|(queued, _)| *queued == Input::Event(Event::Press { row, column })));
+                let pressed = matches!(input, Input::Event(Event::Press { .. }) | Input::Combo { pressed: true, .. });
+                let full = pending.queue.push_back((input, now)).is_err();
+
+                if full {
+                    self.decide(false, keys);
+                    self.feed(input, now, keys);
+                } else if (permissive && self.tap_hold.permissive_hold)
+                    || (pressed && self.tap_hold.hold_on_other_key_press)
+                {
+                    self.decide(false, keys);
+                }
+            }
+        }
+    }
+
+    // Settles the pending dual-role key as a tap or a hold, then replays
+    // what was queued behind it
+    fn decide(&mut self, tap: bool, keys: &mut Output) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        let (row, column) = (pending.row, pending.column);
+        let (layer, usage) = match self.held[row][column] {
+            Action::LayerTap(layer, usage) => (Some(layer), usage),
+            Action::ModTap(modifier, usage) => (None, if tap { usage } else { modifier }),
+            _ => unreachable!(),
+        };
+
+        if tap {
+            // the release that follows finds nothing left to undo
+            self.held[row][column] = Action::No;
+            self.key(Key::Down(usage), keys);
+            self.key(Key::Up(usage), keys);
+        } else if let Some(layer) = layer {
+            self.held[row][column] = Action::Momentary(layer);
+            self.active |= 1 << layer;
+        } else {
+            self.held[row][column] = Action::Key(usage);
+            self.key(Key::Down(usage), keys);
+        }
+
+        for (input, since) in pending.queue {
+            self.feed(input, since, keys);
+        }
+    }
+
+    fn process(&mut self, input: Input, now: Instant, keys: &mut Output) {
+        match input {
+            Input::Event(Event::Press { row, column }) => {
+                let action = self.resolve(row, column);
+                self.held[row][column] = action;
+                self.last = Some((row, column));
+                if let Action::LayerTap(..) | Action::ModTap(..) = action {
+                    self.pending = Some(Pending {
+                        row,
+                        column,
+                        since: now,
+                        queue: Deque::new(),
+                    });
+                    self.consume_oneshot();
+                } else {
+                    self.press(action, keys);
+                }
+            }
+            Input::Event(Event::Release { row, column }) => {
+                let action = core::mem::replace(&mut self.held[row][column], Action::No);
+                let tapped = self.last == Some((row, column));
+                self.release(action, tapped, keys);
+            }
+            Input::Combo { action, pressed: true } => {
+                self.last = None;
+                self.press(action, keys);
+            }
+            Input::Combo { action, pressed: false } => self.release(action, false, keys),
+        }
+    }
+
+    fn press(&mut self, action: Action, keys: &mut Output) {
+        match action {
+            Action::OneShot(layer) => {
+                self.oneshot = Some(layer);
+                self.oneshot_held = true;
+                return;
+            }
+            Action::Momentary(layer) | Action::LayerTap(layer, _) => self.active |= 1 << layer,
+            Action::Toggle(layer) => self.active ^= 1 << layer,
+            Action::To(layer) => self.active = 1 | 1 << layer,
+            Action::Key(usage) | Action::ModTap(usage, _) => self.key(Key::Down(usage), keys),
+            Action::Macro(key) => {
+                let _ = keys.push(Key::Macro(key));
+            }
+            Action::Consumer(_) | Action::System(_) | Action::Mouse(_) => {
+                let _ = keys.push(Key::Extra { action, down: true });
+            }
+            Action::No | Action::Trans => {}
+        }
+        self.consume_oneshot();
+    }
+
+    fn release(&mut self, action: Action, tapped: bool, keys: &mut Output) {
+        match action {
+            Action::OneShot(_) => {
+                self.oneshot_held = false;
+                // held while another key was pressed, so it acted like MO
+                if !tapped {
+                    self.oneshot = None;
+                }
+            }
+            Action::Momentary(layer) | Action::LayerTap(layer, _) => self.active &= !(1 << layer),
+            Action::Key(usage) | Action::ModTap(usage, _) => self.key(Key::Up(usage), keys),
+            Action::Consumer(_) | Action::System(_) | Action::Mouse(_) => {
+                let _ = keys.push(Key::Extra { action, down: false });
+            }
+            Action::Toggle(_) | Action::To(_) | Action::Macro(_) | Action::No | Action::Trans => {}
+        }
+    }
+
+    // any other key uses up a one-shot layer that is no longer held
+    fn consume_oneshot(&mut self) {
+        if !self.oneshot_held {
+            self.oneshot = None;
+        }
+    }
+
+    // Sends a usage on, applying key overrides
+    fn key(&mut self, key: Key, keys: &mut Output) {
+        let (usage, down) = match key {
+            Key::Down(usage) => (usage, true),
+            Key::Up(usage) => (usage, false),
+            Key::Macro(_) | Key::Extra { .. } => {
+                let _ = keys.push(key);
+                return;
+            }
+        };
+
+        if let Some(bit) = modifier_bit(usage) {
+            if down {
+                self.modifiers |= bit;
+            } else {
+                self.modifiers &= !bit;
+            }
+            // lifted by an override, stays up until the override is released
+            if self.overridden.is_some_and(|(_, _, lifted)| lifted & bit != 0) {
+                if !down {
+                    if let Some((_, _, lifted)) = self.overridden.as_mut() {
+                        *lifted &= !bit;
+                    }
+                }
+                return;
+            }
+            let _ = keys.push(key);
+            return;
+        }
+
+        if down {
+            let found = self
+                .overrides
+                .iter()
+                .find(|o| o.key == usage && self.modifiers & o.modifiers != 0);
+            if let Some(o) = found {
+                if let Some((_, replacement, lifted)) = self.overridden.take() {
+                    self.end_override(replacement, lifted, keys);
+                }
+                let lifted = self.modifiers & o.modifiers;
+                lift(lifted, false, keys);
+                self.overridden = Some((usage, o.replacement, lifted));
+                let _ = keys.push(Key::Down(o.replacement));
+                return;
+            }
+        } else if let Some((key, replacement, lifted)) = self.overridden {
+            if key == usage {
+                self.overridden = None;
+                return self.end_override(replacement, lifted, keys);
+            }
+        }
+        let _ = keys.push(key);
+    }
+
+    fn end_override(&mut self, replacement: KeyboardUsage, lifted: u8, keys: &mut Output) {
+        let _ = keys.push(Key::Up(replacement));
+        lift(lifted & self.modifiers, true, keys);
+    }
+
+    // The base layer is always active, even after MO(0) or TG(0) clears it
+    fn layers(&self) -> u32 {
+        let oneshot = self.oneshot.map_or(0, |layer| 1 << layer);
+        (self.active | oneshot | 1) & (u32::MAX >> (32 - LAYERS))
+    }
+}
+
+fn modifier_bit(usage: KeyboardUsage) -> Option<u8> {
+    let usage = usage as u8;
+    (0xe0..=0xe7).contains(&usage).then(|| 1 << (usage - 0xe0))
+}
+
+// Puts modifiers down or up again, by their report bits
+fn lift(modifiers: u8, down: bool, keys: &mut Output) {
+    for bit in 0..8 {
+        if modifiers & 1 << bit != 0 {
+            let usage = KeyboardUsage::from(0xe0 + bit);
+            let _ = keys.push(if down { Key::Down(usage) } else { Key::Up(usage) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+
+    use super::{Key, KeyOverride, Layers, Output, TapHold};
+    use crate::combo::Input;
+    use crate::keymap::{Action, Keymap, KeyboardUsage::*};
+    use crate::macros::Macro;
+    use crate::state::Event;
+
+    // one row: A, MO(1), TG(2), OSL(1), LT(1, Space), TO(2), MT(Ctrl, Esc), Backspace, Shift
+    const KEYMAP: Keymap<1, 9, 3> = crate::keymap! {
+        [[
+            KeyboardAa, MO(1), TG(2), OSL(1), LT(1, KeyboardSpacebar), TO(2),
+            MT(KeyboardLeftControl, KeyboardEscape), KeyboardBackspace, KeyboardLeftShift,
+        ]],
+        [[KeyboardBb, _, _, _, _, XX, _, _, _]],
+        [[_, XX, _, XX, XX, TO(0), _, _, _]],
+    };
+
+    fn press(column: usize) -> Input {
+        Input::Event(Event::Press { row: 0, column })
+    }
+
+    fn release(column: usize) -> Input {
+        Input::Event(Event::Release { row: 0, column })
+    }
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn tap<const C: usize, const L: usize>(layers: &mut Layers<'_, 1, C, L>, column: usize) -> [Output; 2] {
+        [layers.update(press(column), at(0)), layers.update(release(column), at(0))]
+    }
+
+    #[test]
+    fn test_macro() {
+        assert_eq!(KEYMAP.get(0, 0, 4), Action::LayerTap(1, KeyboardSpacebar));
+        assert_eq!(KEYMAP.get(0, 0, 6), Action::ModTap(KeyboardLeftControl, KeyboardEscape));
+        assert_eq!(KEYMAP.get(1, 0, 1), Action::Trans);
+        assert_eq!(KEYMAP.get(1, 0, 5), Action::No);
+    }
+
+    #[test]
+    fn test_momentary() {
+        let mut layers = Layers::new(KEYMAP);
+        assert!(layers.update(press(1), at(0)).is_empty());
+        assert_eq!(layers.update(press(0), at(0)), [Key::Down(KeyboardBb)]);
+        // layer released before the key: the key still comes up as B
+        layers.update(release(1), at(0));
+        assert_eq!(layers.update(release(0), at(0)), [Key::Up(KeyboardBb)]);
+        assert_eq!(tap(&mut layers, 0), [[Key::Down(KeyboardAa)], [Key::Up(KeyboardAa)]]);
+    }
+
+    #[test]
+    fn test_toggle_and_to() {
+        let mut layers = Layers::new(KEYMAP);
+        tap(&mut layers, 2);
+        assert_eq!(layers.top(), 2);
+        // transparent on layer 2 falls through to A
+        assert_eq!(layers.resolve(0, 0), Action::Key(KeyboardAa));
+        tap(&mut layers, 2);
+        assert_eq!(layers.top(), 0);
+
+        tap(&mut layers, 5);
+        assert!(layers.is_active(2));
+        tap(&mut layers, 5);
+        assert_eq!(layers.top(), 0);
+    }
+
+    #[test]
+    fn test_base_layer() {
+        const BASE: Keymap<1, 3, 2> = crate::keymap! {
+            [[MO(0), TG(0), KeyboardAa]],
+            [[_, _, KeyboardBb]],
+        };
+        let mut layers = Layers::new(BASE);
+        tap(&mut layers, 0);
+        assert_eq!(layers.top(), 0);
+        assert!(layers.is_active(0));
+        tap(&mut layers, 1);
+        assert_eq!(layers.top(), 0);
+        assert_eq!(layers.resolve(0, 2), Action::Key(KeyboardAa));
+    }
+
+    #[test]
+    fn test_one_shot() {
+        let mut layers = Layers::new(KEYMAP);
+        tap(&mut layers, 3);
+        assert!(layers.is_active(1));
+        assert_eq!(tap(&mut layers, 0), [[Key::Down(KeyboardBb)], [Key::Up(KeyboardBb)]]);
+        assert!(!layers.is_active(1));
+
+        // held over another key it is momentary
+        layers.update(press(3), at(0));
+        assert_eq!(tap(&mut layers, 0), [[Key::Down(KeyboardBb)], [Key::Up(KeyboardBb)]]);
+        layers.update(release(3), at(0));
+        assert!(!layers.is_active(1));
+    }
+
+    #[test]
+    fn test_tap_hold() {
+        let mut layers = Layers::new(KEYMAP);
+        assert!(layers.update(press(4), at(0)).is_empty());
+        assert_eq!(layers.deadline(), Some(at(200)));
+        assert_eq!(
+            layers.update(release(4), at(150)),
+            [Key::Down(KeyboardSpacebar), Key::Up(KeyboardSpacebar)]
+        );
+
+        // held past the term, the layer is on for the next key
+        layers.update(press(4), at(300));
+        assert!(layers.tick(at(499)).is_empty());
+        layers.tick(at(500));
+        assert!(layers.is_active(1));
+        assert_eq!(tap(&mut layers, 0), [[Key::Down(KeyboardBb)], [Key::Up(KeyboardBb)]]);
+        assert!(layers.update(release(4), at(600)).is_empty());
+        assert_eq!(layers.top(), 0);
+
+        // a key rolled over within the term follows the tap
+        layers.update(press(6), at(1000));
+        assert!(layers.update(press(0), at(1050)).is_empty());
+        assert_eq!(
+            layers.update(release(6), at(1100)),
+            [Key::Down(KeyboardEscape), Key::Up(KeyboardEscape), Key::Down(KeyboardAa)]
+        );
+    }
+
+    #[test]
+    fn test_permissive_hold() {
+        let mut layers = Layers::new(KEYMAP);
+        layers.set_tap_hold(TapHold {
+            permissive_hold: true,
+            ..TapHold::default()
+        });
+        layers.update(press(6), at(0));
+        assert!(layers.update(press(0), at(50)).is_empty());
+        assert_eq!(
+            layers.update(release(0), at(100)),
+            [Key::Down(KeyboardLeftControl), Key::Down(KeyboardAa), Key::Up(KeyboardAa)]
+        );
+        assert_eq!(layers.update(release(6), at(150)), [Key::Up(KeyboardLeftControl)]);
+    }
+
+    #[test]
+    fn test_hold_on_other_key_press() {
+        let mut layers = Layers::new(KEYMAP);
+        layers.set_tap_hold(TapHold {
+            hold_on_other_key_press: true,
+            ..TapHold::default()
+        });
+        layers.update(press(4), at(0));
+        assert_eq!(layers.update(press(0), at(50)), [Key::Down(KeyboardBb)]);
+    }
+
+    #[test]
+    fn test_combo_input() {
+        let mut layers = Layers::new(KEYMAP);
+        let escape = Action::Key(KeyboardEscape);
+        assert_eq!(
+            layers.update(Input::Combo { action: escape, pressed: true }, at(0)),
+            [Key::Down(KeyboardEscape)]
+        );
+        assert_eq!(
+            layers.update(Input::Combo { action: escape, pressed: false }, at(0)),
+            [Key::Up(KeyboardEscape)]
+        );
+
+        let replay = Action::Macro(Macro::Replay(0));
+        assert_eq!(
+            layers.update(Input::Combo { action: replay, pressed: true }, at(0)),
+            [Key::Macro(Macro::Replay(0))]
+        );
+        assert!(layers.update(Input::Combo { action: replay, pressed: false }, at(0)).is_empty());
+    }
+
+    #[test]
+    fn test_key_override() {
+        const OVERRIDES: [KeyOverride; 1] = [KeyOverride {
+            modifiers: 0x22,
+            key: KeyboardBackspace,
+            replacement: KeyboardDelete,
+        }];
+        let mut layers = Layers::new(KEYMAP);
+        layers.set_overrides(&OVERRIDES);
+
+        assert_eq!(layers.update(press(8), at(0)), [Key::Down(KeyboardLeftShift)]);
+        assert_eq!(
+            layers.update(press(7), at(0)),
+            [Key::Up(KeyboardLeftShift), Key::Down(KeyboardDelete)]
+        );
+        assert_eq!(
+            layers.update(release(7), at(0)),
+            [Key::Up(KeyboardDelete), Key::Down(KeyboardLeftShift)]
+        );
+        assert_eq!(layers.update(release(8), at(0)), [Key::Up(KeyboardLeftShift)]);
+        assert_eq!(tap(&mut layers, 7), [[Key::Down(KeyboardBackspace)], [Key::Up(KeyboardBackspace)]]);
+    }
+
+    #[test]
+    fn test_shared_replacement() {
+        // Backspace, H, Shift, Control
+        const KEYMAP: Keymap<1, 4, 1> = crate::keymap! {
+            [[KeyboardBackspace, KeyboardHh, KeyboardLeftShift, KeyboardLeftControl]],
+        };
+        const OVERRIDES: [KeyOverride; 2] = [
+            KeyOverride {
+                modifiers: 0x22,
+                key: KeyboardBackspace,
+                replacement: KeyboardDelete,
+            },
+            KeyOverride {
+                modifiers: 0x11,
+                key: KeyboardHh,
+                replacement: KeyboardDelete,
+            },
+        ];
+        let mut layers = Layers::new(KEYMAP);
+        layers.set_overrides(&OVERRIDES);
+
+        layers.update(press(3), at(0));
+        assert_eq!(
+            layers.update(press(1), at(0)),
+            [Key::Up(KeyboardLeftControl), Key::Down(KeyboardDelete)]
+        );
+        assert_eq!(
+            layers.update(release(1), at(0)),
+            [Key::Up(KeyboardDelete), Key::Down(KeyboardLeftControl)]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+// The boards, one module each, compiled from the files in layouts/ by
+// build.rs: `ROW_PINS` and `COLUMN_PINS` as GPIO numbers and the `KEYMAP`
+include!(concat!(env!("OUT_DIR"), "/layouts.rs"));
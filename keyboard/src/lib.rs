@@ -0,0 +1,23 @@
+#![no_std]
+#![allow(async_fn_in_trait)]
+
+pub mod charmap;
+pub mod combo;
+pub mod console;
+pub mod debounce;
+pub mod diagnostics;
+pub mod extrakey;
+pub mod idle;
+pub mod keymap;
+pub mod layer;
+pub mod layouts;
+pub mod macros;
+pub mod matrix;
+pub mod mousekey;
+pub mod report;
+pub mod router;
+pub mod serial;
+pub mod state;
+pub mod store;
+pub mod via;
+pub mod vt;
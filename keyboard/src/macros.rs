@@ -0,0 +1,604 @@
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+use usbd_hid::descriptor::KeyboardUsage::{self, *};
+
+use crate::report::Keys;
+
+// Keymap actions for macros, which play on the half talking to the host
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Macro {
+    // the compiled macro of that number (M0, M1 …)
+    Play(u8),
+    // records the keys typed into slot 0 or 1 until `Stop`
+    Record(u8),
+    Stop,
+    Replay(u8),
+}
+
+// One step of a macro
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Step {
+    Down(KeyboardUsage),
+    Up(KeyboardUsage),
+    // down then up
+    Tap(KeyboardUsage),
+    // milliseconds
+    Delay(u16),
+    // ASCII, typed as on a US layout; anything else is skipped
+    Text(&'static str),
+    // typed with the host's Unicode input, see `UnicodeMode`
+    Unicode(char),
+}
+
+// How the host takes a character by its code point
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum UnicodeMode {
+    // Ctrl+Shift+U, the code point in hex, Space (IBus and GTK)
+    Linux,
+    // Option held over each UTF-16 unit in hex, with the Unicode Hex Input
+    // source selected
+    Mac,
+    // Alt held over keypad + and the code point in hex.  Needs
+    // EnableHexNumpad set in the registry, and stops at U+FFFF.
+    Windows,
+}
+
+// Keys one recording can hold, downs and ups counted apart
+pub const RECORDING: usize = 128;
+
+// VIA's macro buffer: `COUNT` macros one after another, each ended by a 0.
+// Printable ASCII is typed; 0x01 starts an action, then 0x01 tap, 0x02 down
+// or 0x03 up and a usage, or 0x04 delay and the milliseconds in decimal
+// ended by '|'.
+pub const BUFFER: usize = 512;
+pub const COUNT: u8 = 16;
+
+const PREFIX: u8 = 0x01;
+const TAP: u8 = 0x01;
+const DOWN: u8 = 0x02;
+const UP: u8 = 0x03;
+const DELAY: u8 = 0x04;
+
+// each ASCII character, for `Step::Text`s one long
+static CHARACTERS: [u8; 128] = {
+    let mut characters = [0; 128];
+    let mut i = 0;
+    while i < 128 {
+        characters[i] = i as u8;
+        i += 1;
+    }
+    characters
+};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+enum Source {
+    Compiled(usize),
+    Recorded(usize),
+    // `Playing::step` is an offset into the buffer
+    Buffer,
+}
+
+#[derive(Copy, Clone)]
+struct Playing {
+    source: Source,
+    step: usize,
+    // into `Step::Text`
+    char: usize,
+}
+
+// Records and plays macros as a series of reports.  Reports are taken one
+// at a time with `next`, as the host takes them, so a macro of any length
+// only ever has the reports of one step expanded.
+pub struct Macros<'a> {
+    macros: &'a [&'a [Step]],
+    buffer: [u8; BUFFER],
+    unicode: UnicodeMode,
+    recorded: [Vec<Step, RECORDING>; 2],
+    recording: Option<usize>,
+    // keys as last seen while recording
+    seen: Keys,
+    playing: Option<Playing>,
+    // the keys as played so far, and the reports of the step under way
+    keys: Keys,
+    queue: Deque<Keys, 20>,
+    // not before the end of a delay
+    at: Instant,
+}
+
+impl<'a> Macros<'a> {
+    pub const fn new(macros: &'a [&'a [Step]]) -> Self {
+        Self {
+            macros,
+            buffer: [0; BUFFER],
+            unicode: UnicodeMode::Linux,
+            recorded: [Vec::new(), Vec::new()],
+            recording: None,
+            seen: Keys::new(),
+            playing: None,
+            keys: Keys::new(),
+            queue: Deque::new(),
+            at: Instant::MIN,
+        }
+    }
+
+    pub fn set_unicode(&mut self, unicode: UnicodeMode) {
+        self.unicode = unicode;
+    }
+
+    // The macros set from VIA, which M0, M1 … play ahead of the compiled
+    // ones of the same number.  Left as they are while one of them plays.
+    pub fn set_buffer(&mut self, buffer: &[u8; BUFFER]) {
+        if !self.is_playing() {
+            self.buffer = *buffer;
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.is_some() || !self.queue.is_empty()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // A macro key pressed, with `keys` held.  Another macro pressed while
+    // one plays is dropped, and any key but record stops a recording.
+    pub fn press(&mut self, key: Macro, keys: &Keys, now: Instant) {
+        if self.recording.take().is_some() || self.is_playing() {
+            return;
+        }
+        let (source, step) = match key {
+            Macro::Play(n) => match buffer_macro(&self.buffer, n as usize) {
+                Some(start) => (Source::Buffer, start),
+                None if (n as usize) < self.macros.len() => (Source::Compiled(n as usize), 0),
+                None => return,
+            },
+            Macro::Record(slot) if slot < 2 => {
+                self.recorded[slot as usize].clear();
+                self.recording = Some(slot as usize);
+                self.seen = *keys;
+                return;
+            }
+            Macro::Replay(slot) if slot < 2 => (Source::Recorded(slot as usize), 0),
+            _ => return,
+        };
+        self.playing = Some(Playing { source, step, char: 0 });
+        self.keys = Keys::new();
+        self.at = now;
+    }
+
+    // Takes every set of keys held, to record the changes.  A recording that
+    // runs out of room ends there.
+    pub fn record(&mut self, keys: &Keys) {
+        let Some(slot) = self.recording else {
+            return;
+        };
+        // modifiers go down first and come up last
+        let usages = || (0xe0..0xe8).chain(1..0xe0).map(KeyboardUsage::from);
+        let downs = usages().filter(|usage| keys.is_pressed(*usage) && !self.seen.is_pressed(*usage));
+        let ups = usages().rev().filter(|usage| !keys.is_pressed(*usage) && self.seen.is_pressed(*usage));
+        let steps = downs.map(Step::Down).chain(ups.map(Step::Up));
+        for step in steps {
+            if self.recorded[slot].push(step).is_err() {
+                self.recording = None;
+                break;
+            }
+        }
+        self.seen = *keys;
+    }
+
+    // When the next report is due, while a macro plays
+    pub fn deadline(&self) -> Option<Instant> {
+        self.is_playing().then_some(self.at)
+    }
+
+    // The next report of the macro playing, if one is due.  Once it has
+    // played out the keys held are for the host again.
+    pub fn next(&mut self, now: Instant) -> Option<Keys> {
+        self.fill(now);
+        self.queue.pop_front()
+    }
+
+    // Expands steps until there are reports, a delay, or the end
+    fn fill(&mut self, now: Instant) {
+        while self.queue.is_empty() && now >= self.at {
+            let Some(playing) = self.playing else {
+                return;
+            };
+            let step = match playing.source {
+                Source::Compiled(n) => self.macros[n].get(playing.step).map(|step| (*step, playing.step + 1)),
+                Source::Recorded(slot) => self.recorded[slot].get(playing.step).map(|step| (*step, playing.step + 1)),
+                Source::Buffer => buffer_step(&self.buffer, playing.step),
+            };
+            let Some((step, after)) = step else {
+                self.playing = None;
+                return;
+            };
+
+            let mut next = Playing {
+                step: after,
+                char: 0,
+                ..playing
+            };
+            match step {
+                Step::Down(usage) => {
+                    self.keys.press(usage);
+                    self.push();
+                }
+                Step::Up(usage) => {
+                    self.keys.release(usage);
+                    self.push();
+                }
+                Step::Tap(usage) => self.tap(&[], usage),
+                Step::Delay(ms) => self.at = now + Duration::from_millis(ms as u64),
+                Step::Text(text) => {
+                    if let Some(byte) = text.as_bytes().get(playing.char) {
+                        next = Playing {
+                            char: playing.char + 1,
+                            ..playing
+                        };
+                        match ascii(*byte) {
+                            Some((usage, true)) => self.tap(&[KeyboardLeftShift], usage),
+                            Some((usage, false)) => self.tap(&[], usage),
+                            None => {}
+                        }
+                    }
+                }
+                Step::Unicode(c) => self.unicode(c),
+            }
+            self.playing = Some(next);
+        }
+    }
+
+    fn unicode(&mut self, c: char) {
+        // keys the macro holds would change what the digits mean
+        let held = self.keys;
+        self.keys = Keys::new();
+        match self.unicode {
+            UnicodeMode::Linux => {
+                self.tap(&[KeyboardLeftControl, KeyboardLeftShift], KeyboardUu);
+                let digits = (c as u32).max(1).ilog2() / 4 + 1;
+                self.hex(c as u32, digits, false);
+                self.tap(&[], KeyboardSpacebar);
+            }
+            UnicodeMode::Mac => {
+                self.keys.press(KeyboardLeftAlt);
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    self.hex(*unit as u32, 4, false);
+                }
+                self.keys.release(KeyboardLeftAlt);
+                self.push();
+            }
+            UnicodeMode::Windows if (c as u32) <= 0xffff => {
+                self.keys.press(KeyboardLeftAlt);
+                self.tap(&[], KeypadPlus);
+                self.hex(c as u32, 4, true);
+                self.keys.release(KeyboardLeftAlt);
+                self.push();
+            }
+            UnicodeMode::Windows => {}
+        }
+        if held != self.keys {
+            self.keys = held;
+            self.push();
+        }
+    }
+
+    // The lowest `digits` hex digits of `value`, digits on the keypad
+    fn hex(&mut self, value: u32, digits: u32, keypad: bool) {
+        for digit in (0..digits).rev().map(|i| (value >> (4 * i) & 0xf) as u8) {
+            let usage = match digit {
+                0 if keypad => Keypad0Insert,
+                0 => Keyboard0CloseParens,
+                1..=9 if keypad => KeyboardUsage::from(Keypad1End as u8 + digit - 1),
+                1..=9 => KeyboardUsage::from(Keyboard1Exclamation as u8 + digit - 1),
+                _ => KeyboardUsage::from(KeyboardAa as u8 + digit - 10),
+            };
+            self.tap(&[], usage);
+        }
+    }
+
+    // Two reports: the usage down with `modifiers`, then both up again
+    fn tap(&mut self, modifiers: &[KeyboardUsage], usage: KeyboardUsage) {
+        let before = self.keys;
+        for modifier in modifiers {
+            self.keys.press(*modifier);
+        }
+        self.keys.press(usage);
+        self.push();
+        self.keys = before;
+        self.push();
+    }
+
+    fn push(&mut self) {
+        let _ = self.queue.push_back(self.keys);
+    }
+}
+
+// Where macro `n` of a VIA buffer starts, unless it's empty
+fn buffer_macro(buffer: &[u8], n: usize) -> Option<usize> {
+    let start = match n {
+        0 => 0,
+        _ => buffer.iter().enumerate().filter(|(_, byte)| **byte == 0).nth(n - 1)?.0 + 1,
+    };
+    buffer.get(start).is_some_and(|byte| *byte != 0).then_some(start)
+}
+
+// The step at `at` of a VIA buffer and where the one after starts, or None
+// at the macro's end.  Anything not understood is passed over as a delay
+// of nothing.
+fn buffer_step(buffer: &[u8], at: usize) -> Option<(Step, usize)> {
+    const NOTHING: Step = Step::Delay(0);
+    match *buffer.get(at)? {
+        0 => None,
+        PREFIX => match *buffer.get(at + 1)? {
+            action @ (TAP | DOWN | UP) => {
+                let usage = KeyboardUsage::from(*buffer.get(at + 2)?);
+                let step = match action {
+                    TAP => Step::Tap(usage),
+                    DOWN => Step::Down(usage),
+                    _ => Step::Up(usage),
+                };
+                Some((step, at + 3))
+            }
+            DELAY => {
+                let digits = buffer[at + 2..].iter().position(|byte| *byte == b'|')?;
+                let ms = buffer[at + 2..at + 2 + digits]
+                    .iter()
+                    .filter(|byte| byte.is_ascii_digit())
+                    .fold(0u16, |ms, byte| ms.saturating_mul(10).saturating_add((byte - b'0') as u16));
+                Some((Step::Delay(ms), at + 3 + digits))
+            }
+            _ => Some((NOTHING, at + 2)),
+        },
+        byte if byte.is_ascii() => {
+            let text = core::str::from_utf8(&CHARACTERS[byte as usize..byte as usize + 1]).unwrap();
+            Some((Step::Text(text), at + 1))
+        }
+        _ => Some((NOTHING, at + 1)),
+    }
+}
+
+// The usage typing an ASCII character on a US layout, and whether it takes
+// Shift
+pub fn ascii(byte: u8) -> Option<(KeyboardUsage, bool)> {
+    const PUNCTUATION: &[u8] = b"-=[]\\;'`,./";
+    const SHIFTED: &[u8] = b"_+{}|:\"~<>?";
+    const PUNCTUATION_USAGES: [KeyboardUsage; 11] = [
+        KeyboardDashUnderscore,
+        KeyboardEqualPlus,
+        KeyboardOpenBracketBrace,
+        KeyboardCloseBracketBrace,
+        KeyboardBackslashBar,
+        KeyboardSemiColon,
+        KeyboardSingleDoubleQuote,
+        KeyboardBacktickTilde,
+        KeyboardCommaLess,
+        KeyboardPeriodGreater,
+        KeyboardSlashQuestion,
+    ];
+    let usage = |base: KeyboardUsage, offset: u8| KeyboardUsage::from(base as u8 + offset);
+    Some(match byte {
+        b'a'..=b'z' => (usage(KeyboardAa, byte - b'a'), false),
+        b'A'..=b'Z' => (usage(KeyboardAa, byte - b'A'), true),
+        b'1'..=b'9' => (usage(Keyboard1Exclamation, byte - b'1'), false),
+        b'0' => (Keyboard0CloseParens, false),
+        b'\n' => (KeyboardEnter, false),
+        b'\t' => (KeyboardTab, false),
+        b' ' => (KeyboardSpacebar, false),
+        _ => {
+            if let Some(i) = b"!@#$%^&*()".iter().position(|b| *b == byte) {
+                (usage(Keyboard1Exclamation, i as u8), true)
+            } else if let Some(i) = PUNCTUATION.iter().position(|b| *b == byte) {
+                (PUNCTUATION_USAGES[i], false)
+            } else {
+                (PUNCTUATION_USAGES[SHIFTED.iter().position(|b| *b == byte)?], true)
+            }
+        }
+    })
+}
+
+// Builds the steps of a macro, each one of
+//
+//  "text"              typed, see `Step::Text`
+//  Tap(KeyboardEnter) Down(KeyboardLeftShift) Up(KeyboardLeftShift)
+//  Delay(100)          in milliseconds
+//  Unicode('→')
+#[macro_export]
+macro_rules! sequence {
+    ($($step:tt $(($($arg:tt)*))?),* $(,)?) => {
+        &[$($crate::step!($step $(($($arg)*))?)),*]
+    };
+}
+
+#[macro_export]
+macro_rules! step {
+    (Tap($usage:ident)) => {
+        $crate::macros::Step::Tap($crate::keymap::KeyboardUsage::$usage)
+    };
+    (Down($usage:ident)) => {
+        $crate::macros::Step::Down($crate::keymap::KeyboardUsage::$usage)
+    };
+    (Up($usage:ident)) => {
+        $crate::macros::Step::Up($crate::keymap::KeyboardUsage::$usage)
+    };
+    (Delay($ms:expr)) => {
+        $crate::macros::Step::Delay($ms)
+    };
+    (Unicode($c:expr)) => {
+        $crate::macros::Step::Unicode($c)
+    };
+    ($text:literal) => {
+        $crate::macros::Step::Text($text)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+    use heapless::Vec;
+
+    use super::{ascii, Macro, Macros, Step, UnicodeMode};
+    use crate::keymap::KeyboardUsage::{self, *};
+    use crate::report::Keys;
+
+    const MACROS: &[&[Step]] = &[
+        crate::sequence!("Hi!", Delay(50), Tap(KeyboardEnter)),
+        crate::sequence!(Down(KeyboardLeftShift), "a", Up(KeyboardLeftShift), Unicode('é')),
+    ];
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn keys(usages: &[KeyboardUsage]) -> Keys {
+        let mut keys = Keys::new();
+        for usage in usages {
+            keys.press(*usage);
+        }
+        keys
+    }
+
+    // Every report due by `now`
+    fn reports(macros: &mut Macros<'_>, now: Instant) -> Vec<Keys, 256> {
+        let mut reports = Vec::new();
+        while let Some(report) = macros.next(now) {
+            reports.push(report).unwrap();
+        }
+        reports
+    }
+
+    #[test]
+    fn test_ascii() {
+        assert_eq!(ascii(b'a'), Some((KeyboardAa, false)));
+        assert_eq!(ascii(b'Z'), Some((KeyboardZz, true)));
+        assert_eq!(ascii(b'0'), Some((Keyboard0CloseParens, false)));
+        assert_eq!(ascii(b')'), Some((Keyboard0CloseParens, true)));
+        assert_eq!(ascii(b'?'), Some((KeyboardSlashQuestion, true)));
+        assert_eq!(ascii(b'\''), Some((KeyboardSingleDoubleQuote, false)));
+        assert_eq!(ascii(0x7f), None);
+    }
+
+    #[test]
+    fn test_play() {
+        let mut macros = Macros::new(MACROS);
+        assert_eq!(macros.deadline(), None);
+        macros.press(Macro::Play(0), &Keys::new(), at(0));
+        assert_eq!(
+            reports(&mut macros, at(0)),
+            [
+                keys(&[KeyboardLeftShift, KeyboardHh]),
+                Keys::new(),
+                keys(&[KeyboardIi]),
+                Keys::new(),
+                keys(&[KeyboardLeftShift, Keyboard1Exclamation]),
+                Keys::new(),
+            ]
+        );
+        // then the delay
+        assert_eq!(macros.deadline(), Some(at(50)));
+        assert!(reports(&mut macros, at(49)).is_empty());
+        assert_eq!(reports(&mut macros, at(50)), [keys(&[KeyboardEnter]), Keys::new()]);
+        assert!(!macros.is_playing());
+
+        macros.press(Macro::Play(2), &Keys::new(), at(0));
+        assert!(!macros.is_playing());
+    }
+
+    #[test]
+    fn test_unicode() {
+        let mut macros = Macros::new(MACROS);
+        macros.press(Macro::Play(1), &Keys::new(), at(0));
+        let played = reports(&mut macros, at(0));
+        // Shift+A, Shift lifted while the code point goes, then put back
+        // and lifted again
+        let shifted = [keys(&[KeyboardLeftShift]), keys(&[KeyboardLeftShift, KeyboardAa]), keys(&[KeyboardLeftShift])];
+        assert_eq!(played[..3], shifted);
+        assert_eq!(played[3], Keys::new());
+        assert_eq!(played[4], keys(&[KeyboardLeftControl, KeyboardLeftShift, KeyboardUu]));
+        // e9: two digits, then Space
+        assert_eq!(played[6], keys(&[KeyboardEe]));
+        assert_eq!(played[8], keys(&[Keyboard9OpenParens]));
+        assert_eq!(played[10], keys(&[KeyboardSpacebar]));
+        assert_eq!(played.len(), 12);
+
+        let mut macros = Macros::new(MACROS);
+        macros.set_unicode(UnicodeMode::Windows);
+        macros.press(Macro::Play(1), &Keys::new(), at(0));
+        let played = reports(&mut macros, at(0));
+        assert_eq!(played[4], keys(&[KeyboardLeftAlt, KeypadPlus]));
+        assert_eq!(played[6], keys(&[KeyboardLeftAlt, Keypad0Insert]));
+        assert_eq!(played[10], keys(&[KeyboardLeftAlt, KeyboardEe]));
+        assert_eq!(played[12], keys(&[KeyboardLeftAlt, Keypad9PageUp]));
+        assert_eq!(played[14..], [Keys::new()]);
+    }
+
+    #[test]
+    fn test_buffer() {
+        let mut buffer = [0; super::BUFFER];
+        // M0 empty, so the compiled one; M1 "a", Shift held over Tab, then
+        // 20 ms and Enter
+        let m1 = b"\0a\x01\x02\xe1\x01\x01\x2b\x01\x03\xe1\x01\x0420|\x01\x01\x28\0";
+        buffer[..m1.len()].copy_from_slice(m1);
+        let mut macros = Macros::new(MACROS);
+        macros.set_buffer(&buffer);
+
+        macros.press(Macro::Play(1), &Keys::new(), at(0));
+        assert_eq!(
+            reports(&mut macros, at(0)),
+            [
+                keys(&[KeyboardAa]),
+                Keys::new(),
+                keys(&[KeyboardLeftShift]),
+                keys(&[KeyboardLeftShift, KeyboardTab]),
+                keys(&[KeyboardLeftShift]),
+                Keys::new(),
+            ]
+        );
+        assert_eq!(macros.deadline(), Some(at(20)));
+        // changes wait until it's done
+        macros.set_buffer(&[0; super::BUFFER]);
+        assert_eq!(reports(&mut macros, at(20)), [keys(&[KeyboardEnter]), Keys::new()]);
+
+        macros.set_buffer(&buffer);
+        macros.press(Macro::Play(0), &Keys::new(), at(0));
+        assert_eq!(reports(&mut macros, at(0))[0], keys(&[KeyboardLeftShift, KeyboardHh]));
+        reports(&mut macros, at(100));
+        macros.press(Macro::Play(2), &Keys::new(), at(0));
+        assert!(!macros.is_playing());
+    }
+
+    #[test]
+    fn test_record() {
+        let mut macros = Macros::new(MACROS);
+        let shift = keys(&[KeyboardLeftShift]);
+        // Shift is already down when recording starts
+        macros.press(Macro::Record(1), &shift, at(0));
+        assert!(macros.is_recording());
+        for held in [keys(&[KeyboardLeftShift, KeyboardAa]), Keys::new(), keys(&[KeyboardBb])] {
+            macros.record(&held);
+        }
+        macros.press(Macro::Stop, &Keys::new(), at(0));
+        assert!(!macros.is_recording());
+        macros.record(&Keys::new());
+
+        macros.press(Macro::Replay(1), &Keys::new(), at(0));
+        assert_eq!(
+            reports(&mut macros, at(0)),
+            [keys(&[KeyboardAa]), Keys::new(), Keys::new(), keys(&[KeyboardBb])]
+        );
+    }
+
+    #[test]
+    fn test_record_full() {
+        let mut macros = Macros::new(MACROS);
+        macros.press(Macro::Record(0), &Keys::new(), at(0));
+        for _ in 0..super::RECORDING {
+            macros.record(&keys(&[KeyboardAa]));
+            macros.record(&Keys::new());
+        }
+        assert!(!macros.is_recording());
+        macros.press(Macro::Replay(0), &Keys::new(), at(0));
+        assert_eq!(reports(&mut macros, at(0)).len(), super::RECORDING);
+    }
+}
@@ -0,0 +1,219 @@
+use embassy_futures::select::select_array;
+use embedded_hal::digital::{Error, ErrorKind, InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+
+pub type Scan<const ROWS: usize, const COLUMNS: usize> = [[bool; COLUMNS]; ROWS];
+
+pub trait Matrix<const ROWS: usize, const COLUMNS: usize> {
+    type Error;
+
+    async fn scan(&mut self) -> Result<Scan<ROWS, COLUMNS>, Self::Error>;
+}
+
+// Diode matrix where each column is driven high in turn and the rows, pulled
+// down, read high for every closed switch in that column
+pub struct PinMatrix<I, O, D, const ROWS: usize, const COLUMNS: usize> {
+    rows: [I; ROWS],
+    columns: [O; COLUMNS],
+    delay: D,
+    settle_us: u32,
+}
+
+impl<I, O, D, const ROWS: usize, const COLUMNS: usize> PinMatrix<I, O, D, ROWS, COLUMNS>
+where
+    I: InputPin,
+    O: OutputPin,
+    D: DelayNs,
+{
+    pub fn new(rows: [I; ROWS], columns: [O; COLUMNS], delay: D, settle_us: u32) -> Self {
+        Self {
+            rows,
+            columns,
+            delay,
+            settle_us,
+        }
+    }
+}
+
+impl<I, O, D, const ROWS: usize, const COLUMNS: usize> PinMatrix<I, O, D, ROWS, COLUMNS>
+where
+    I: InputPin + Wait,
+    O: OutputPin,
+    D: DelayNs,
+{
+    // Drives every column at once and waits for any row to go high, so that
+    // an idle keyboard sleeps on a GPIO edge instead of scanning.  The
+    // columns are low again on return, ready for the next scan; dropping the
+    // future part way leaves them driven, until `release`.
+    pub async fn wait_for_press(&mut self) -> Result<(), ErrorKind> {
+        for column in &mut self.columns {
+            column.set_high().map_err(|e| e.kind())?;
+        }
+        self.delay.delay_us(self.settle_us).await;
+        let (result, _) = select_array(self.rows.each_mut().map(|row| row.wait_for_high())).await;
+        for column in &mut self.columns {
+            column.set_low().map_err(|e| e.kind())?;
+        }
+        result.map_err(|e| e.kind())
+    }
+
+    // Drives every column low, after a cancelled `wait_for_press`
+    pub fn release(&mut self) -> Result<(), ErrorKind> {
+        for column in &mut self.columns {
+            column.set_low().map_err(|e| e.kind())?;
+        }
+        Ok(())
+    }
+}
+
+impl<I, O, D, const ROWS: usize, const COLUMNS: usize> Matrix<ROWS, COLUMNS> for PinMatrix<I, O, D, ROWS, COLUMNS>
+where
+    I: InputPin,
+    O: OutputPin,
+    D: DelayNs,
+{
+    type Error = ErrorKind;
+
+    async fn scan(&mut self) -> Result<Scan<ROWS, COLUMNS>, Self::Error> {
+        let mut scan = [[false; COLUMNS]; ROWS];
+        for (c, column) in self.columns.iter_mut().enumerate() {
+            column.set_high().map_err(|e| e.kind())?;
+            self.delay.delay_us(self.settle_us).await;
+            for (r, row) in self.rows.iter_mut().enumerate() {
+                scan[r][c] = row.is_high().map_err(|e| e.kind())?;
+            }
+            column.set_low().map_err(|e| e.kind())?;
+        }
+        Ok(scan)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use core::cell::RefCell;
+    use core::convert::Infallible;
+    use core::future::{poll_fn, Future};
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+    use embedded_hal_async::digital::Wait;
+
+    use super::{Matrix, PinMatrix};
+
+    // Columns and rows of a simulated switch matrix, with `pressed` standing
+    // in for the switches
+    pub struct Switches<const ROWS: usize, const COLUMNS: usize> {
+        pub pressed: RefCell<[[bool; COLUMNS]; ROWS]>,
+        driven: RefCell<[bool; COLUMNS]>,
+    }
+
+    impl<const ROWS: usize, const COLUMNS: usize> Switches<ROWS, COLUMNS> {
+        pub fn new() -> Self {
+            Self {
+                pressed: RefCell::new([[false; COLUMNS]; ROWS]),
+                driven: RefCell::new([false; COLUMNS]),
+            }
+        }
+
+        pub fn rows(&self) -> [Row<'_, ROWS, COLUMNS>; ROWS] {
+            core::array::from_fn(|row| Row(self, row))
+        }
+
+        pub fn columns(&self) -> [Column<'_, ROWS, COLUMNS>; COLUMNS] {
+            core::array::from_fn(|column| Column(self, column))
+        }
+    }
+
+    pub struct Row<'a, const ROWS: usize, const COLUMNS: usize>(&'a Switches<ROWS, COLUMNS>, usize);
+    pub struct Column<'a, const ROWS: usize, const COLUMNS: usize>(&'a Switches<ROWS, COLUMNS>, usize);
+
+    impl<const ROWS: usize, const COLUMNS: usize> ErrorType for Row<'_, ROWS, COLUMNS> {
+        type Error = Infallible;
+    }
+
+    impl<const ROWS: usize, const COLUMNS: usize> InputPin for Row<'_, ROWS, COLUMNS> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            let pressed = self.0.pressed.borrow();
+            let driven = self.0.driven.borrow();
+            Ok((0..COLUMNS).any(|column| driven[column] && pressed[self.1][column]))
+        }
+
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    // Only ever resolves on the first poll: the switches don't change while a
+    // test is blocked on them
+    impl<const ROWS: usize, const COLUMNS: usize> Wait for Row<'_, ROWS, COLUMNS> {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            poll_fn(|_| if self.is_high().unwrap() { Poll::Ready(Ok(())) } else { Poll::Pending }).await
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            poll_fn(|_| if self.is_low().unwrap() { Poll::Ready(Ok(())) } else { Poll::Pending }).await
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for_high().await
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            self.wait_for_low().await
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+    }
+
+    impl<const ROWS: usize, const COLUMNS: usize> ErrorType for Column<'_, ROWS, COLUMNS> {
+        type Error = Infallible;
+    }
+
+    impl<const ROWS: usize, const COLUMNS: usize> OutputPin for Column<'_, ROWS, COLUMNS> {
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.driven.borrow_mut()[self.1] = true;
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.driven.borrow_mut()[self.1] = false;
+            Ok(())
+        }
+    }
+
+    pub struct NoDelay;
+
+    impl embedded_hal_async::delay::DelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn test_scan() {
+        let switches: Switches<2, 3> = Switches::new();
+        let mut matrix = PinMatrix::new(switches.rows(), switches.columns(), NoDelay, 0);
+
+        let scan = embassy_futures::block_on(matrix.scan()).unwrap();
+        assert_eq!(scan, [[false; 3]; 2]);
+
+        switches.pressed.borrow_mut()[1][2] = true;
+        switches.pressed.borrow_mut()[0][0] = true;
+        let scan = embassy_futures::block_on(matrix.scan()).unwrap();
+        assert_eq!(scan, [[true, false, false], [false, false, true]]);
+    }
+
+    #[test]
+    fn test_wait_for_press() {
+        let switches: Switches<2, 3> = Switches::new();
+        let mut matrix = PinMatrix::new(switches.rows(), switches.columns(), NoDelay, 0);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        assert!(pin!(matrix.wait_for_press()).poll(&mut cx).is_pending());
+
+        switches.pressed.borrow_mut()[1][2] = true;
+        assert_eq!(pin!(matrix.wait_for_press()).poll(&mut cx), Poll::Ready(Ok(())));
+        assert_eq!(*switches.driven.borrow(), [false; 3]);
+    }
+}
@@ -0,0 +1,228 @@
+use embassy_time::{Duration, Instant};
+
+use crate::extrakey::{MOUSE_ID, MOUSE_LEN};
+
+// Mouse keys, numbered as QMK's keycodes 0xcd to 0xdf
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum MouseKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Button1,
+    Button2,
+    Button3,
+    Button4,
+    Button5,
+    Button6,
+    Button7,
+    Button8,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    // fixed speeds while held: slow, medium and fast
+    Accel0,
+    Accel1,
+    Accel2,
+}
+
+const KEYS: [MouseKey; 19] = [
+    MouseKey::Up,
+    MouseKey::Down,
+    MouseKey::Left,
+    MouseKey::Right,
+    MouseKey::Button1,
+    MouseKey::Button2,
+    MouseKey::Button3,
+    MouseKey::Button4,
+    MouseKey::Button5,
+    MouseKey::Button6,
+    MouseKey::Button7,
+    MouseKey::Button8,
+    MouseKey::WheelUp,
+    MouseKey::WheelDown,
+    MouseKey::WheelLeft,
+    MouseKey::WheelRight,
+    MouseKey::Accel0,
+    MouseKey::Accel1,
+    MouseKey::Accel2,
+];
+
+const MOVE: u32 = 0x0000f;
+const BUTTONS: u32 = 0x00ff0;
+const WHEEL: u32 = 0x0f000;
+
+impl MouseKey {
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        KEYS.get(index as usize).copied()
+    }
+
+    pub fn bit(self) -> u32 {
+        1 << self.index()
+    }
+
+    // The button byte of a report, from the keys held
+    pub fn buttons(held: u32) -> u8 {
+        ((held & BUTTONS) >> 4) as u8
+    }
+}
+
+// Report every 16ms while moving, accelerating from 8 to 80 units a report
+// over 30 reports; the wheel a notch every 80ms
+const INTERVAL: Duration = Duration::from_millis(16);
+const WHEEL_INTERVAL: Duration = Duration::from_millis(80);
+const DELTA: u32 = 8;
+const MAX_SPEED: u32 = 10;
+const TIME_TO_MAX: u32 = 30;
+
+// Turns the mouse keys held into mouse reports
+pub struct MouseKeys {
+    held: u32,
+    // buttons as last reported
+    buttons: u8,
+    // reports since movement started, for the acceleration
+    repeat: u32,
+    move_at: Instant,
+    wheel_at: Instant,
+}
+
+impl MouseKeys {
+    pub const fn new() -> Self {
+        Self {
+            held: 0,
+            buttons: 0,
+            repeat: 0,
+            move_at: Instant::MIN,
+            wheel_at: Instant::MIN,
+        }
+    }
+
+    pub fn set(&mut self, held: u32, now: Instant) {
+        if held & MOVE != 0 && self.held & MOVE == 0 {
+            self.repeat = 0;
+            self.move_at = now;
+        }
+        if held & WHEEL != 0 && self.held & WHEEL == 0 {
+            self.wheel_at = now;
+        }
+        self.held = held;
+    }
+
+    // When the next report is due, if one is
+    pub fn deadline(&self) -> Option<Instant> {
+        if MouseKey::buttons(self.held) != self.buttons {
+            return Some(Instant::MIN);
+        }
+        let moving = (self.held & MOVE != 0).then_some(self.move_at);
+        let wheeling = (self.held & WHEEL != 0).then_some(self.wheel_at);
+        moving.into_iter().chain(wheeling).min()
+    }
+
+    pub fn next(&mut self, now: Instant) -> Option<[u8; MOUSE_LEN]> {
+        let axis = |negative: MouseKey, positive: MouseKey| {
+            (self.held & positive.bit() != 0) as i32 - (self.held & negative.bit() != 0) as i32
+        };
+        let (mut x, mut y, mut wheel, mut pan) = (0, 0, 0, 0);
+        if self.held & MOVE != 0 && now >= self.move_at {
+            let unit = self.unit() as i32;
+            x = axis(MouseKey::Left, MouseKey::Right) * unit;
+            y = axis(MouseKey::Up, MouseKey::Down) * unit;
+            self.repeat = self.repeat.saturating_add(1);
+            self.move_at = now + INTERVAL;
+        }
+        if self.held & WHEEL != 0 && now >= self.wheel_at {
+            wheel = axis(MouseKey::WheelDown, MouseKey::WheelUp);
+            pan = axis(MouseKey::WheelLeft, MouseKey::WheelRight);
+            self.wheel_at = now + WHEEL_INTERVAL;
+        }
+
+        let buttons = MouseKey::buttons(self.held);
+        if buttons == self.buttons && (x, y, wheel, pan) == (0, 0, 0, 0) {
+            return None;
+        }
+        self.buttons = buttons;
+        Some([MOUSE_ID, buttons, x as u8, y as u8, wheel as u8, pan as u8])
+    }
+
+    // Units a report, from the accel key held or the time spent moving
+    fn unit(&self) -> u32 {
+        let max = DELTA * MAX_SPEED;
+        let unit = if self.held & MouseKey::Accel2.bit() != 0 {
+            max
+        } else if self.held & MouseKey::Accel1.bit() != 0 {
+            max / 2
+        } else if self.held & MouseKey::Accel0.bit() != 0 {
+            max / 4
+        } else {
+            DELTA + (max - DELTA) * self.repeat.min(TIME_TO_MAX) / TIME_TO_MAX
+        };
+        unit.min(127)
+    }
+}
+
+impl Default for MouseKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+
+    use super::{MouseKey, MouseKeys};
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn test_index() {
+        assert_eq!(MouseKey::from_index(5), Some(MouseKey::Button2));
+        assert_eq!(MouseKey::from_index(18), Some(MouseKey::Accel2));
+        assert_eq!(MouseKey::from_index(19), None);
+        assert_eq!(MouseKey::buttons(MouseKey::Button1.bit() | MouseKey::Left.bit()), 1);
+    }
+
+    #[test]
+    fn test_move() {
+        let mut mouse = MouseKeys::new();
+        assert_eq!(mouse.deadline(), None);
+        mouse.set(MouseKey::Right.bit() | MouseKey::Up.bit(), at(0));
+        assert_eq!(mouse.deadline(), Some(at(0)));
+        assert_eq!(mouse.next(at(0)), Some([3, 0, 8, (-8i8) as u8, 0, 0]));
+        assert_eq!(mouse.deadline(), Some(at(16)));
+        assert_eq!(mouse.next(at(10)), None);
+
+        // up to full speed
+        for n in 1..40 {
+            mouse.next(at(16 * n));
+        }
+        assert_eq!(mouse.next(at(16 * 40)), Some([3, 0, 80, (-80i8) as u8, 0, 0]));
+
+        // slow while Accel0 is held
+        mouse.set(MouseKey::Left.bit() | MouseKey::Accel0.bit(), at(700));
+        assert_eq!(mouse.next(at(700)), Some([3, 0, (-20i8) as u8, 0, 0, 0]));
+
+        mouse.set(0, at(800));
+        assert_eq!(mouse.deadline(), None);
+    }
+
+    #[test]
+    fn test_buttons_and_wheel() {
+        let mut mouse = MouseKeys::new();
+        mouse.set(MouseKey::Button1.bit(), at(0));
+        assert_eq!(mouse.next(at(0)), Some([3, 1, 0, 0, 0, 0]));
+        assert_eq!(mouse.deadline(), None);
+        mouse.set(MouseKey::Button1.bit() | MouseKey::WheelDown.bit(), at(10));
+        assert_eq!(mouse.next(at(10)), Some([3, 1, 0, 0, (-1i8) as u8, 0]));
+        assert_eq!(mouse.deadline(), Some(at(90)));
+        mouse.set(0, at(20));
+        assert_eq!(mouse.next(at(20)), Some([3, 0, 0, 0, 0, 0]));
+    }
+}
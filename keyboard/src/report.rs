@@ -0,0 +1,279 @@
+use usbd_hid::descriptor::{KeyboardReport, KeyboardUsage};
+
+use crate::layer::Key;
+
+// Modifier usages, LeftControl to RightGUI, are sent as bits in the first
+// byte of a report rather than as keycodes
+const MODIFIERS: core::ops::RangeInclusive<u8> = 0xe0..=0xe7;
+
+// Keycodes 0x00 to 0xdf, one bit each, after the modifier byte
+pub const NKRO_LEN: usize = 1 + 0xe0 / 8;
+
+// Report protocol keyboard: the modifier byte followed by a bitmap of every
+// other usage, and the usual five LEDs as output
+#[rustfmt::skip]
+pub const NKRO_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0xe0, //   Usage Minimum (LeftControl)
+    0x29, 0xe7, //   Usage Maximum (RightGUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0xdf, //   Usage Maximum (0xdf)
+    0x95, 0xe0, //   Report Count (224)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (Num Lock)
+    0x29, 0x05, //   Usage Maximum (Kana)
+    0x95, 0x05, //   Report Count (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0x75, 0x03, //   Report Size (3)
+    0x95, 0x01, //   Report Count (1)
+    0x91, 0x01, //   Output (Constant)
+    0xc0,       // End Collection
+];
+
+// The set of usages held down, from which either report is built
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct Keys {
+    modifier: u8,
+    bits: [u8; NKRO_LEN - 1],
+}
+
+impl Keys {
+    pub const fn new() -> Self {
+        Self {
+            modifier: 0,
+            bits: [0; NKRO_LEN - 1],
+        }
+    }
+
+    pub fn press(&mut self, usage: KeyboardUsage) {
+        self.set(usage as u8, true);
+    }
+
+    pub fn release(&mut self, usage: KeyboardUsage) {
+        self.set(usage as u8, false);
+    }
+
+    // Takes on a key from the layers, returning whether the set changed
+    pub fn apply(&mut self, key: Key) -> bool {
+        let before = *self;
+        match key {
+            Key::Down(usage) => self.press(usage),
+            Key::Up(usage) => self.release(usage),
+            Key::Macro(_) | Key::Extra { .. } => {}
+        }
+        *self != before
+    }
+
+    pub fn is_pressed(&self, usage: KeyboardUsage) -> bool {
+        let usage = usage as u8;
+        if MODIFIERS.contains(&usage) {
+            self.modifier & 1 << (usage - 0xe0) != 0
+        } else if usage < 0xe0 {
+            self.bits[usage as usize / 8] & 1 << (usage % 8) != 0
+        } else {
+            false
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modifier == 0 && self.bits.iter().all(|bits| *bits == 0)
+    }
+
+    // Modifier bits, as in the first byte of a report
+    pub fn modifiers(&self) -> u8 {
+        self.modifier
+    }
+
+    // The keys held here but not in `other`
+    pub fn difference(&self, other: &Keys) -> Keys {
+        let mut keys = *self;
+        keys.modifier &= !other.modifier;
+        for (bits, other) in keys.bits.iter_mut().zip(other.bits) {
+            *bits &= !other;
+        }
+        keys
+    }
+
+    // The keys held in both
+    pub fn intersection(&self, other: &Keys) -> Keys {
+        let mut keys = *self;
+        keys.modifier &= other.modifier;
+        for (bits, other) in keys.bits.iter_mut().zip(other.bits) {
+            *bits &= other;
+        }
+        keys
+    }
+
+    // The keys held in either
+    pub fn union(&self, other: &Keys) -> Keys {
+        let mut keys = *self;
+        keys.modifier |= other.modifier;
+        for (bits, other) in keys.bits.iter_mut().zip(other.bits) {
+            *bits |= other;
+        }
+        keys
+    }
+
+    // Non-modifier usages held, lowest first
+    pub fn keycodes(&self) -> impl Iterator<Item = u8> + '_ {
+        // usage 0 means no key, never held
+        (1..0xe0u8).filter(|usage| self.bits[*usage as usize / 8] & 1 << (usage % 8) != 0)
+    }
+
+    // Boot protocol report: up to six keycodes, or ErrorRollOver in every
+    // slot once more are held
+    pub fn boot(&self) -> KeyboardReport {
+        let mut keycodes = [0; 6];
+        for (i, keycode) in self.keycodes().enumerate() {
+            if i == keycodes.len() {
+                keycodes = [KeyboardUsage::KeyboardErrorRollOver as u8; 6];
+                break;
+            }
+            keycodes[i] = keycode;
+        }
+        KeyboardReport {
+            modifier: self.modifier,
+            reserved: 0,
+            leds: 0,
+            keycodes,
+        }
+    }
+
+    // Report protocol report, laid out as `NKRO_DESCRIPTOR`
+    pub fn nkro(&self) -> [u8; NKRO_LEN] {
+        let mut report = [0; NKRO_LEN];
+        report[0] = self.modifier;
+        report[1..].copy_from_slice(&self.bits);
+        report
+    }
+
+    pub fn from_nkro(report: &[u8; NKRO_LEN]) -> Self {
+        let mut keys = Self::new();
+        keys.modifier = report[0];
+        keys.bits.copy_from_slice(&report[1..]);
+        keys.bits[0] &= !1;
+        keys
+    }
+
+    // The keys in a boot protocol report from another keyboard, or None
+    // when it has too many held to say which, and the last set still stands
+    pub fn from_boot(report: &[u8; 8]) -> Option<Self> {
+        let keycodes = &report[2..];
+        if keycodes.contains(&(KeyboardUsage::KeyboardErrorRollOver as u8)) {
+            return None;
+        }
+        let mut keys = Self::new();
+        keys.modifier = report[0];
+        for keycode in keycodes {
+            keys.set(*keycode, true);
+        }
+        Some(keys)
+    }
+
+    fn set(&mut self, usage: u8, pressed: bool) {
+        let (byte, bit) = if MODIFIERS.contains(&usage) {
+            (&mut self.modifier, usage - 0xe0)
+        } else if (1..0xe0).contains(&usage) {
+            (&mut self.bits[usage as usize / 8], usage % 8)
+        } else {
+            return;
+        };
+        if pressed {
+            *byte |= 1 << bit;
+        } else {
+            *byte &= !(1 << bit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Keys, NKRO_LEN};
+    use crate::layer::Key;
+    use usbd_hid::descriptor::KeyboardUsage;
+
+    #[test]
+    fn test_boot() {
+        let mut keys = Keys::new();
+        assert!(keys.apply(Key::Down(KeyboardUsage::KeyboardQq)));
+        keys.press(KeyboardUsage::KeyboardLeftShift);
+        keys.press(KeyboardUsage::KeyboardAa);
+
+        let report = keys.boot();
+        assert_eq!(report.modifier, 0x02);
+        assert_eq!(
+            report.keycodes,
+            [KeyboardUsage::KeyboardAa as u8, KeyboardUsage::KeyboardQq as u8, 0, 0, 0, 0]
+        );
+
+        assert!(keys.apply(Key::Up(KeyboardUsage::KeyboardQq)));
+        assert!(!keys.apply(Key::Up(KeyboardUsage::KeyboardQq)));
+        keys.release(KeyboardUsage::KeyboardAa);
+        keys.release(KeyboardUsage::KeyboardLeftShift);
+        assert!(keys.is_empty());
+        assert_eq!(keys.boot().keycodes, [0; 6]);
+
+        let keys = Keys::from_boot(&[0x20, 0, KeyboardUsage::KeyboardZz as u8, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(keys.modifiers(), 0x20);
+        assert!(keys.keycodes().eq([KeyboardUsage::KeyboardZz as u8]));
+        assert_eq!(Keys::from_boot(&[0, 0, 1, 1, 1, 1, 1, 1]), None);
+    }
+
+    #[test]
+    fn test_rollover() {
+        let mut keys = Keys::new();
+        for usage in KeyboardUsage::KeyboardAa as u8..KeyboardUsage::KeyboardGg as u8 {
+            keys.press(KeyboardUsage::from(usage));
+        }
+        assert_eq!(keys.boot().keycodes, [4, 5, 6, 7, 8, 9]);
+
+        keys.press(KeyboardUsage::KeyboardZz);
+        assert_eq!(keys.boot().keycodes, [1; 6]);
+        assert!(keys.is_pressed(KeyboardUsage::KeyboardZz));
+    }
+
+    #[test]
+    fn test_nkro() {
+        let mut keys = Keys::new();
+        keys.press(KeyboardUsage::KeyboardRightGUI);
+        keys.press(KeyboardUsage::KeyboardAa);
+        keys.press(KeyboardUsage::KeypadHexadecimal);
+
+        let report = keys.nkro();
+        let mut expected = [0; NKRO_LEN];
+        expected[0] = 0x80;
+        expected[1] = 0x10;
+        expected[1 + 0xdd / 8] = 1 << (0xdd % 8);
+        assert_eq!(report, expected);
+        assert_eq!(Keys::from_nkro(&report), keys);
+    }
+
+    #[test]
+    fn test_sets() {
+        let mut a = Keys::new();
+        a.press(KeyboardUsage::KeyboardLeftShift);
+        a.press(KeyboardUsage::KeyboardAa);
+        let mut b = Keys::new();
+        b.press(KeyboardUsage::KeyboardAa);
+        b.press(KeyboardUsage::KeyboardBb);
+
+        let difference = a.difference(&b);
+        assert_eq!(difference.modifiers(), 0x02);
+        assert_eq!(difference.keycodes().count(), 0);
+        let intersection = a.intersection(&b);
+        assert_eq!(intersection.modifiers(), 0);
+        assert!(intersection.keycodes().eq([KeyboardUsage::KeyboardAa as u8]));
+        let union = a.union(&b);
+        assert_eq!(union.modifiers(), 0x02);
+        assert_eq!(union.keycodes().count(), 2);
+    }
+}
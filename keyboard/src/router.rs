@@ -0,0 +1,295 @@
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage;
+
+use crate::charmap::{Composer, Layout};
+use crate::report::Keys;
+use crate::vt;
+
+// Where keystrokes go
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Route {
+    // reports to the USB host
+    Usb,
+    // bytes to the terminal or editor on the device's own display
+    Local,
+    // bytes to the serial terminal session
+    Serial,
+    // bytes to the USB host's console, over CDC-ACM
+    Console,
+}
+
+impl Route {
+    pub fn next(self) -> Self {
+        match self {
+            Route::Usb => Route::Local,
+            Route::Local => Route::Serial,
+            Route::Serial => Route::Console,
+            Route::Console => Route::Usb,
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+const ALL_ROUTES: u8 = 0b1111;
+
+// Typematic repeat on the terminal routes
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+const REPEAT_INTERVAL: Duration = Duration::from_millis(33);
+
+pub type Typed = Vec<u8, 32>;
+
+// What came of a set of keys
+#[derive(Debug, Eq, PartialEq)]
+pub struct Update {
+    // the route just switched away from, to be told every key is up
+    pub left: Option<Route>,
+    // bytes for a terminal route
+    pub typed: Typed,
+}
+
+// Sends the keys held to one route at a time, moving on to the next when
+// exactly `chord` is held.  Keys held at a switch stay with the route they
+// went down on: the new one only sees them once they have been released and
+// pressed again.
+//
+// The terminal routes get text as UTF-8, typed with the layout set here,
+// and the other keys as an xterm sends them.
+pub struct Router {
+    route: Route,
+    // the routes with something to take keystrokes, as `Route::bit`s
+    routes: u8,
+    chord: Keys,
+    last: Keys,
+    masked: Keys,
+    // as the route sees them
+    held: Keys,
+    composer: Composer,
+    // the key to repeat, what it typed, and when next
+    repeat: Option<(KeyboardUsage, Typed, Instant)>,
+}
+
+impl Router {
+    pub const fn new(chord: Keys) -> Self {
+        Self {
+            route: Route::Usb,
+            routes: ALL_ROUTES,
+            chord,
+            last: Keys::new(),
+            masked: Keys::new(),
+            held: Keys::new(),
+            composer: Composer::new(Layout::Us),
+            repeat: None,
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.composer.set_layout(layout);
+    }
+
+    pub fn set_compose(&mut self, usage: KeyboardUsage) {
+        self.composer.set_compose(usage);
+    }
+
+    // Only these routes are switched between, as when there's no USB host,
+    // starting on the first unless the current one is among them
+    pub fn set_routes(&mut self, routes: &[Route]) {
+        self.routes = routes.iter().fold(0, |bits, route| bits | route.bit());
+        if !routes.contains(&self.route) {
+            self.route = routes.first().copied().unwrap_or(self.route);
+        }
+    }
+
+    pub fn route(&self) -> Route {
+        self.route
+    }
+
+    // The keys held as the current route sees them
+    pub fn held(&self) -> &Keys {
+        &self.held
+    }
+
+    // The route after the current one, of those to be switched between
+    fn next_route(&self) -> Route {
+        let mut route = self.route;
+        for _ in 0..4 {
+            route = route.next();
+            if self.routes & route.bit() != 0 {
+                break;
+            }
+        }
+        route
+    }
+
+    pub fn update(&mut self, keys: &Keys, now: Instant) -> Update {
+        let mut update = Update {
+            left: None,
+            typed: Typed::new(),
+        };
+        let switch = *keys == self.chord && self.last != self.chord;
+        self.last = *keys;
+        if switch {
+            update.left = Some(self.route);
+            self.route = self.next_route();
+            self.masked = *keys;
+            self.held = Keys::new();
+            self.repeat = None;
+            return update;
+        }
+
+        self.masked = self.masked.intersection(keys);
+        let held = keys.difference(&self.masked);
+        let pressed = held.difference(&self.held);
+        self.held = held;
+        if self.route == Route::Usb {
+            return update;
+        }
+
+        if self.repeat.as_ref().is_some_and(|(usage, ..)| !held.is_pressed(*usage)) {
+            self.repeat = None;
+        }
+        for usage in pressed.keycodes().map(KeyboardUsage::from) {
+            let bytes = self.type_key(usage, held.modifiers());
+            if !bytes.is_empty() {
+                let _ = update.typed.extend_from_slice(&bytes);
+                self.repeat = Some((usage, bytes, now + REPEAT_DELAY));
+            }
+        }
+        update
+    }
+
+    fn type_key(&mut self, usage: KeyboardUsage, modifiers: u8) -> Typed {
+        let mut typed = Typed::new();
+        match self.composer.press(usage, modifiers) {
+            Some(chars) => {
+                for c in chars {
+                    let _ = typed.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+            }
+            None => {
+                let _ = typed.extend_from_slice(&vt::encode(usage, modifiers));
+            }
+        }
+        typed
+    }
+
+    // When the key held is next due to repeat
+    pub fn deadline(&self) -> Option<Instant> {
+        self.repeat.as_ref().map(|(.., at)| *at)
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Typed {
+        match &mut self.repeat {
+            Some((_, typed, at)) if now >= *at => {
+                *at = now + REPEAT_INTERVAL;
+                typed.clone()
+            }
+            _ => Typed::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+
+    use super::{Route, Router};
+    use crate::charmap::Layout;
+    use crate::keymap::KeyboardUsage::{self, *};
+    use crate::report::Keys;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn keys(usages: &[KeyboardUsage]) -> Keys {
+        let mut keys = Keys::new();
+        for usage in usages {
+            keys.press(*usage);
+        }
+        keys
+    }
+
+    fn router() -> Router {
+        Router::new(keys(&[KeyboardLeftControl, KeyboardRightControl]))
+    }
+
+    #[test]
+    fn test_switch() {
+        let mut router = router();
+        router.update(&keys(&[KeyboardAa]), at(0));
+        assert_eq!(router.held(), &keys(&[KeyboardAa]));
+        router.update(&keys(&[KeyboardLeftControl]), at(0));
+        let update = router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl]), at(0));
+        assert_eq!(update.left, Some(Route::Usb));
+        assert_eq!(router.route(), Route::Local);
+        assert!(router.held().is_empty());
+
+        // Control stays with USB until let go of, so this is a plain a
+        let update = router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl, KeyboardAa]), at(0));
+        assert_eq!(update.typed, b"a");
+        router.update(&Keys::new(), at(0));
+        let update = router.update(&keys(&[KeyboardLeftControl, KeyboardCc]), at(0));
+        assert_eq!(update.typed, b"\x03");
+
+        router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl]), at(0));
+        assert_eq!(router.route(), Route::Serial);
+        router.update(&Keys::new(), at(0));
+        router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl]), at(0));
+        assert_eq!(router.route(), Route::Console);
+        router.update(&Keys::new(), at(0));
+        router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl]), at(0));
+        assert_eq!(router.route(), Route::Usb);
+    }
+
+    #[test]
+    fn test_routes() {
+        let mut router = router();
+        router.set_routes(&[Route::Local, Route::Serial]);
+        assert_eq!(router.route(), Route::Local);
+        let chord = keys(&[KeyboardLeftControl, KeyboardRightControl]);
+        for route in [Route::Serial, Route::Local, Route::Serial] {
+            router.update(&chord, at(0));
+            router.update(&Keys::new(), at(0));
+            assert_eq!(router.route(), route);
+        }
+
+        // already on one of them
+        router.set_routes(&[Route::Usb, Route::Serial]);
+        assert_eq!(router.route(), Route::Serial);
+        router.update(&chord, at(0));
+        assert_eq!(router.route(), Route::Usb);
+    }
+
+    #[test]
+    fn test_layout() {
+        let mut router = router();
+        router.set_layout(Layout::De);
+        router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl]), at(0));
+        router.update(&Keys::new(), at(0));
+
+        assert!(router.update(&keys(&[KeyboardEqualPlus]), at(0)).typed.is_empty());
+        assert_eq!(router.deadline(), None);
+        assert_eq!(router.update(&keys(&[KeyboardEe]), at(0)).typed, "é".as_bytes());
+        router.update(&Keys::new(), at(0));
+        assert_eq!(router.update(&keys(&[KeyboardUpArrow]), at(0)).typed, b"\x1b[A");
+    }
+
+    #[test]
+    fn test_repeat() {
+        let mut router = router();
+        router.update(&keys(&[KeyboardLeftControl, KeyboardRightControl]), at(0));
+        router.update(&Keys::new(), at(0));
+
+        assert_eq!(router.update(&keys(&[KeyboardLeftShift, KeyboardZz]), at(0)).typed, b"Z");
+        assert_eq!(router.deadline(), Some(at(500)));
+        assert!(router.tick(at(499)).is_empty());
+        assert_eq!(router.tick(at(500)), b"Z");
+        assert_eq!(router.deadline(), Some(at(533)));
+        router.update(&keys(&[KeyboardLeftShift]), at(510));
+        assert_eq!(router.deadline(), None);
+    }
+}
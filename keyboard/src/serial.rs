@@ -0,0 +1,317 @@
+use core::fmt::Write;
+
+use heapless::Vec;
+
+// Ctrl+], as telnet's escape character: opens the setup menu, where a
+// second one is sent on as itself
+pub const ESCAPE: u8 = 0x1d;
+
+pub const BAUD_RATES: [u32; 9] = [300, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200];
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Flow {
+    None,
+    RtsCts,
+    XonXoff,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Newline {
+    Cr,
+    Lf,
+    CrLf,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Line {
+    pub baud: u32,
+    // 7 or 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    // 1 or 2
+    pub stop_bits: u8,
+    pub flow: Flow,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Settings {
+    pub line: Line,
+    // show what's typed, for hosts that don't echo it
+    pub echo: bool,
+    // what Enter goes out as
+    pub enter: Newline,
+    // a line feed from the host returns the cursor too, for hosts that send
+    // bare line feeds
+    pub lf_as_crlf: bool,
+}
+
+impl Settings {
+    // 9600 8N1, as most consoles
+    pub const fn new() -> Self {
+        Self {
+            line: Line {
+                baud: 9600,
+                data_bits: 8,
+                parity: Parity::None,
+                stop_bits: 1,
+                flow: Flow::None,
+            },
+            echo: false,
+            enter: Newline::Cr,
+            lf_as_crlf: false,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub type Bytes = Vec<u8, 256>;
+
+// What came of keystrokes
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Output {
+    // to go out on the line
+    pub send: Bytes,
+    // for the screen: the echo and the setup menu
+    pub show: Bytes,
+    // the line settings changed, and the UART is to take them on
+    pub line: bool,
+    pub send_break: bool,
+}
+
+// A dumb terminal's end of a serial line: keystrokes, as bytes from the
+// router, go out with Enter translated, and what comes back is shown with
+// line feeds translated.  Ctrl+] opens a setup menu of one-key commands.
+pub struct Session {
+    settings: Settings,
+    setup: bool,
+}
+
+impl Session {
+    pub const fn new(settings: Settings) -> Self {
+        Self { settings, setup: false }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn in_setup(&self) -> bool {
+        self.setup
+    }
+
+    pub fn typed(&mut self, bytes: &[u8]) -> Output {
+        let mut output = Output::default();
+        for byte in bytes {
+            if self.setup {
+                self.command(*byte, &mut output);
+                continue;
+            }
+            match *byte {
+                ESCAPE => {
+                    self.setup = true;
+                    let _ = write!(output.show, "\r\n[serial setup] {}\r\n{}", Summary(&self.settings), MENU);
+                }
+                b'\r' => {
+                    let _ = output.send.extend_from_slice(match self.settings.enter {
+                        Newline::Cr => b"\r",
+                        Newline::Lf => b"\n",
+                        Newline::CrLf => b"\r\n",
+                    });
+                    if self.settings.echo {
+                        let _ = output.show.extend_from_slice(b"\r\n");
+                    }
+                }
+                byte => {
+                    let _ = output.send.push(byte);
+                    if self.settings.echo {
+                        let _ = output.show.push(byte);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    // What the host sent, as it's to be shown
+    pub fn received(&self, bytes: &[u8]) -> Bytes {
+        let mut show = Bytes::new();
+        for byte in bytes {
+            if *byte == b'\n' && self.settings.lf_as_crlf {
+                let _ = show.push(b'\r');
+            }
+            let _ = show.push(*byte);
+        }
+        show
+    }
+
+    fn command(&mut self, byte: u8, output: &mut Output) {
+        let settings = &mut self.settings;
+        let line = &mut settings.line;
+        match byte {
+            ESCAPE => {
+                self.setup = false;
+                let _ = output.send.push(ESCAPE);
+                let _ = output.show.extend_from_slice(b"[connected]\r\n");
+                return;
+            }
+            b'q' | b'\r' => {
+                self.setup = false;
+                let _ = output.show.extend_from_slice(b"[connected]\r\n");
+                return;
+            }
+            b'k' => {
+                self.setup = false;
+                output.send_break = true;
+                let _ = output.show.extend_from_slice(b"[break, connected]\r\n");
+                return;
+            }
+            b'b' => {
+                let next = BAUD_RATES.iter().position(|baud| *baud == line.baud).map_or(0, |i| i + 1);
+                line.baud = BAUD_RATES[next % BAUD_RATES.len()];
+                output.line = true;
+            }
+            b'd' => {
+                line.data_bits = if line.data_bits == 8 { 7 } else { 8 };
+                output.line = true;
+            }
+            b'p' => {
+                line.parity = match line.parity {
+                    Parity::None => Parity::Even,
+                    Parity::Even => Parity::Odd,
+                    Parity::Odd => Parity::None,
+                };
+                output.line = true;
+            }
+            b's' => {
+                line.stop_bits = if line.stop_bits == 1 { 2 } else { 1 };
+                output.line = true;
+            }
+            b'f' => {
+                line.flow = match line.flow {
+                    Flow::None => Flow::RtsCts,
+                    Flow::RtsCts => Flow::XonXoff,
+                    Flow::XonXoff => Flow::None,
+                };
+                output.line = true;
+            }
+            b'e' => settings.echo = !settings.echo,
+            b'n' => {
+                settings.enter = match settings.enter {
+                    Newline::Cr => Newline::CrLf,
+                    Newline::CrLf => Newline::Lf,
+                    Newline::Lf => Newline::Cr,
+                }
+            }
+            b'l' => settings.lf_as_crlf = !settings.lf_as_crlf,
+            // the rest of an escape sequence, or a key with no command
+            _ => return,
+        }
+        let _ = write!(output.show, "{}\r\n", Summary(&self.settings));
+    }
+}
+
+const MENU: &str = "b)aud d)ata bits p)arity s)top bits f)low control e)cho n) Enter l)ine feed k) break q)uit\r\n";
+
+// As "9600 8N1, no flow control, no echo, Enter sends CR, LF as is"
+struct Summary<'a>(&'a Settings);
+
+impl core::fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Settings {
+            line,
+            echo,
+            enter,
+            lf_as_crlf,
+        } = self.0;
+        let parity = match line.parity {
+            Parity::None => 'N',
+            Parity::Even => 'E',
+            Parity::Odd => 'O',
+        };
+        write!(f, "{} {}{}{}, ", line.baud, line.data_bits, parity, line.stop_bits)?;
+        f.write_str(match line.flow {
+            Flow::None => "no flow control, ",
+            Flow::RtsCts => "RTS/CTS, ",
+            Flow::XonXoff => "XON/XOFF, ",
+        })?;
+        f.write_str(if *echo { "echo, " } else { "no echo, " })?;
+        f.write_str(match enter {
+            Newline::Cr => "Enter sends CR, ",
+            Newline::Lf => "Enter sends LF, ",
+            Newline::CrLf => "Enter sends CR LF, ",
+        })?;
+        f.write_str(if *lf_as_crlf { "LF as CR LF" } else { "LF as is" })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Flow, Newline, Parity, Session, Settings, ESCAPE};
+
+    #[test]
+    fn test_translation() {
+        let mut settings = Settings::new();
+        settings.echo = true;
+        settings.enter = Newline::CrLf;
+        settings.lf_as_crlf = true;
+        let mut session = Session::new(settings);
+
+        let output = session.typed(b"ls\r");
+        assert_eq!(output.send, b"ls\r\n");
+        assert_eq!(output.show, b"ls\r\n");
+        assert!(!output.line && !output.send_break);
+        assert_eq!(session.received(b"a\nb\r\n"), b"a\r\nb\r\r\n");
+
+        let mut session = Session::new(Settings::new());
+        let output = session.typed(b"x\r");
+        assert_eq!(output.send, b"x\r");
+        assert!(output.show.is_empty());
+        assert_eq!(session.received(b"a\n"), b"a\n");
+    }
+
+    #[test]
+    fn test_setup() {
+        let mut session = Session::new(Settings::new());
+        let output = session.typed(&[b'a', ESCAPE, b'b', b'p']);
+        assert_eq!(output.send, b"a");
+        assert!(output.line);
+        assert!(output.show.starts_with(b"\r\n[serial setup] 9600 8N1, no flow control, no echo"));
+        assert!(session.in_setup());
+        assert_eq!(session.settings().line.baud, 19200);
+        assert_eq!(session.settings().line.parity, Parity::Even);
+
+        // an arrow key does nothing here
+        let output = session.typed(b"\x1b[A");
+        assert!(output.show.is_empty() && output.send.is_empty());
+
+        let output = session.typed(b"eqz");
+        assert!(!output.line);
+        assert!(session.settings().echo);
+        assert_eq!(output.send, b"z");
+        assert!(output.show.ends_with(b"[connected]\r\nz"));
+
+        session.typed(&[ESCAPE]);
+        let output = session.typed(b"ffq");
+        assert!(output.line);
+        assert_eq!(session.settings().line.flow, Flow::XonXoff);
+        assert!(output.show.ends_with(b"19200 8E1, XON/XOFF, echo, Enter sends CR, LF as is\r\n[connected]\r\n"));
+
+        let output = session.typed(&[ESCAPE, ESCAPE]);
+        assert_eq!(output.send, [ESCAPE]);
+        let output = session.typed(&[ESCAPE, b'k']);
+        assert!(output.send_break);
+        assert!(!session.in_setup());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::matrix::Scan;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Event {
+    Press { row: usize, column: usize },
+    Release { row: usize, column: usize },
+}
+
+// Which keys are held, by position in the matrix
+pub struct KeyState<const ROWS: usize, const COLUMNS: usize> {
+    pressed: Scan<ROWS, COLUMNS>,
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> KeyState<ROWS, COLUMNS> {
+    pub const fn new() -> Self {
+        Self {
+            pressed: [[false; COLUMNS]; ROWS],
+        }
+    }
+
+    pub fn is_pressed(&self, row: usize, column: usize) -> bool {
+        self.pressed[row][column]
+    }
+
+    pub fn any_pressed(&self) -> bool {
+        self.pressed.iter().flatten().any(|pressed| *pressed)
+    }
+
+    // Takes on the new scan, yielding an event for every key that changed
+    pub fn update<'a>(&'a mut self, scan: &'a Scan<ROWS, COLUMNS>) -> Changes<'a, ROWS, COLUMNS> {
+        Changes {
+            state: self,
+            scan,
+            index: 0,
+        }
+    }
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Default for KeyState<ROWS, COLUMNS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Changes<'a, const ROWS: usize, const COLUMNS: usize> {
+    state: &'a mut KeyState<ROWS, COLUMNS>,
+    scan: &'a Scan<ROWS, COLUMNS>,
+    index: usize,
+}
+
+impl<const ROWS: usize, const COLUMNS: usize> Iterator for Changes<'_, ROWS, COLUMNS> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < ROWS * COLUMNS {
+            let (row, column) = (self.index / COLUMNS, self.index % COLUMNS);
+            self.index += 1;
+
+            let pressed = self.scan[row][column];
+            if pressed != self.state.pressed[row][column] {
+                self.state.pressed[row][column] = pressed;
+                return Some(if pressed {
+                    Event::Press { row, column }
+                } else {
+                    Event::Release { row, column }
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, KeyState};
+
+    #[test]
+    fn test_changes() {
+        let mut state: KeyState<2, 2> = KeyState::new();
+        assert_eq!(state.update(&[[false, false], [false, false]]).next(), None);
+
+        let events: heapless::Vec<Event, 4> = state.update(&[[true, false], [false, true]]).collect();
+        assert_eq!(
+            events,
+            [Event::Press { row: 0, column: 0 }, Event::Press { row: 1, column: 1 }]
+        );
+        assert!(state.is_pressed(1, 1));
+
+        let events: heapless::Vec<Event, 4> = state.update(&[[true, false], [false, false]]).collect();
+        assert_eq!(events, [Event::Release { row: 1, column: 1 }]);
+        assert!(state.any_pressed());
+    }
+}
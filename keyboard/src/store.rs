@@ -0,0 +1,138 @@
+// One record kept in two flash slots, so that losing power part way through
+// a save leaves the previous one intact.  A save goes to the slot not
+// holding the newest record: erase it, write the data, then the header last.
+// Until the header is down the slot reads as erased or corrupt, and loading
+// falls back to the other.
+//
+// A slot starts with the header, all little endian: magic, sequence number,
+// data length, then a CRC-32 over the sequence number, length and data.
+
+const MAGIC: u32 = 0x4245_4554;
+
+pub const HEADER: usize = 16;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Record<'a> {
+    pub seq: u32,
+    pub data: &'a [u8],
+}
+
+// A record from the bytes at the start of a slot, if there is a whole one
+pub fn read(slot: &[u8]) -> Option<Record<'_>> {
+    let word = |i: usize| u32::from_le_bytes(slot[4 * i..4 * i + 4].try_into().unwrap());
+    if slot.len() < HEADER || word(0) != MAGIC {
+        return None;
+    }
+    let (seq, len, crc) = (word(1), word(2) as usize, word(3));
+    let data = slot.get(HEADER..HEADER + len)?;
+    (crc32(&[&seq.to_le_bytes(), &(len as u32).to_le_bytes(), data]) == crc).then_some(Record { seq, data })
+}
+
+// The slot holding the newest record, and the record
+pub fn newest<'a>(slots: [&'a [u8]; 2]) -> Option<(usize, Record<'a>)> {
+    match slots.map(read) {
+        [Some(a), Some(b)] if (b.seq.wrapping_sub(a.seq) as i32) > 0 => Some((1, b)),
+        [Some(a), _] => Some((0, a)),
+        [None, Some(b)] => Some((1, b)),
+        [None, None] => None,
+    }
+}
+
+// Where the next save goes, and its sequence number
+pub fn next(newest: Option<(usize, Record<'_>)>) -> (usize, u32) {
+    match newest {
+        Some((slot, record)) => (1 - slot, record.seq.wrapping_add(1)),
+        None => (0, 0),
+    }
+}
+
+pub fn header(seq: u32, data: &[u8]) -> [u8; HEADER] {
+    let len = data.len() as u32;
+    let crc = crc32(&[&seq.to_le_bytes(), &len.to_le_bytes(), data]);
+    let mut header = [0; HEADER];
+    for (i, word) in [MAGIC, seq, len, crc].iter().enumerate() {
+        header[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    header
+}
+
+// CRC-32 (IEEE), over the parts in turn
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for byte in parts.iter().copied().flatten() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32, header, newest, next, Record, HEADER};
+
+    const SLOT: usize = 64;
+
+    // Saves as the firmware does, stopping after `steps` of erase, data and
+    // header to simulate losing power
+    fn save(slots: &mut [[u8; SLOT]; 2], data: &[u8], steps: usize) {
+        let (slot, seq) = next(newest([&slots[0], &slots[1]]));
+        let slot = &mut slots[slot];
+        if steps > 0 {
+            slot.fill(0xff);
+        }
+        if steps > 1 {
+            slot[HEADER..HEADER + data.len()].copy_from_slice(data);
+        }
+        if steps > 2 {
+            slot[..HEADER].copy_from_slice(&header(seq, data));
+        }
+    }
+
+    fn load(slots: &[[u8; SLOT]; 2]) -> Option<&[u8]> {
+        newest([&slots[0], &slots[1]]).map(|(_, record)| record.data)
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_save() {
+        let mut slots = [[0xff; SLOT]; 2];
+        assert_eq!(load(&slots), None);
+
+        save(&mut slots, b"first", 3);
+        assert_eq!(newest([&slots[0], &slots[1]]), Some((0, Record { seq: 0, data: b"first" })));
+        save(&mut slots, b"second", 3);
+        save(&mut slots, b"third", 3);
+        assert_eq!(newest([&slots[0], &slots[1]]), Some((0, Record { seq: 2, data: b"third" })));
+    }
+
+    #[test]
+    fn test_power_loss() {
+        let mut slots = [[0xff; SLOT]; 2];
+        save(&mut slots, b"first", 3);
+        for steps in 0..3 {
+            let mut torn = slots;
+            save(&mut torn, b"second", steps);
+            assert_eq!(load(&torn), Some(&b"first"[..]));
+        }
+
+        // a bit gone bad in the newest record
+        save(&mut slots, b"second", 3);
+        slots[1][HEADER] ^= 1;
+        assert_eq!(load(&slots), Some(&b"first"[..]));
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut slots = [[0xff; SLOT]; 2];
+        slots[0][..HEADER].copy_from_slice(&header(u32::MAX, b""));
+        slots[1][..HEADER].copy_from_slice(&header(0, b"x"));
+        slots[1][HEADER] = b'x';
+        assert_eq!(load(&slots), Some(&b"x"[..]));
+    }
+}
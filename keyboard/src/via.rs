@@ -0,0 +1,298 @@
+use crate::keymap::Keymap;
+use crate::macros::{self, BUFFER};
+use crate::matrix::Scan;
+
+// The VIA protocol (version 12) over raw HID: 32 byte reports each way,
+// the command in the first byte, answered in place.  Keycodes are
+// `Action::code`s, big endian.
+pub const REPORT_LEN: usize = 32;
+
+pub const PROTOCOL_VERSION: u16 = 0x000c;
+
+// Vendor usage page 0xff60, usage 0x61, where host tools look for it
+#[rustfmt::skip]
+pub const DESCRIPTOR: &[u8] = &[
+    0x06, 0x60, 0xff, // Usage Page (Vendor 0xff60)
+    0x09, 0x61,       // Usage (0x61)
+    0xa1, 0x01,       // Collection (Application)
+    0x09, 0x62,       //   Usage (0x62)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x95, 0x20,       //   Report Count (32)
+    0x75, 0x08,       //   Report Size (8)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x09, 0x63,       //   Usage (0x63)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x95, 0x20,       //   Report Count (32)
+    0x75, 0x08,       //   Report Size (8)
+    0x91, 0x02,       //   Output (Data, Variable, Absolute)
+    0xc0,             // End Collection
+];
+
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_KEYBOARD_VALUE: u8 = 0x02;
+const SET_KEYBOARD_VALUE: u8 = 0x03;
+const GET_KEYCODE: u8 = 0x04;
+const SET_KEYCODE: u8 = 0x05;
+const RESET_KEYMAP: u8 = 0x06;
+const EEPROM_RESET: u8 = 0x0a;
+const BOOTLOADER_JUMP: u8 = 0x0b;
+const MACRO_GET_COUNT: u8 = 0x0c;
+const MACRO_GET_BUFFER_SIZE: u8 = 0x0d;
+const MACRO_GET_BUFFER: u8 = 0x0e;
+const MACRO_SET_BUFFER: u8 = 0x0f;
+const MACRO_RESET: u8 = 0x10;
+const GET_LAYER_COUNT: u8 = 0x11;
+const GET_BUFFER: u8 = 0x12;
+const SET_BUFFER: u8 = 0x13;
+const UNHANDLED: u8 = 0xff;
+
+// keyboard values
+const UPTIME: u8 = 0x01;
+const LAYOUT_OPTIONS: u8 = 0x02;
+const SWITCH_MATRIX_STATE: u8 = 0x03;
+
+// What the firmware has to do after a report
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Change {
+    None,
+    // one key changed, to be saved and passed on
+    Key { layer: u8, row: u8, column: u8 },
+    // any number changed
+    Keymap,
+    // the macro buffer, to be saved
+    Macros,
+    Bootloader,
+    // the host is watching the switches, in its key tester
+    Matrix,
+}
+
+// Answers a report from the host, in place.  `defaults` is what a reset
+// goes back to, `buffer` the macros as `macros::BUFFER` has them, `matrix`
+// the switches as last read.
+pub fn handle<const ROWS: usize, const COLUMNS: usize, const LAYERS: usize>(
+    report: &mut [u8; REPORT_LEN],
+    keymap: &mut Keymap<ROWS, COLUMNS, LAYERS>,
+    defaults: &Keymap<ROWS, COLUMNS, LAYERS>,
+    buffer: &mut [u8; BUFFER],
+    uptime_ms: u32,
+    matrix: &Scan<ROWS, COLUMNS>,
+) -> Change {
+    let index = |layer: u8, row: u8, column: u8| {
+        let (layer, row, column) = (layer as usize, row as usize, column as usize);
+        (layer < LAYERS && row < ROWS && column < COLUMNS).then_some((layer * ROWS + row) * COLUMNS + column)
+    };
+
+    match report[0] {
+        GET_PROTOCOL_VERSION => report[1..3].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes()),
+        GET_KEYBOARD_VALUE => match report[1] {
+            UPTIME => report[2..6].copy_from_slice(&uptime_ms.to_be_bytes()),
+            LAYOUT_OPTIONS => report[2..6].fill(0),
+            SWITCH_MATRIX_STATE => {
+                // from the row asked for, as many as fit, each row's columns
+                // as a big endian bitmap
+                let width = COLUMNS.div_ceil(8);
+                let rows = matrix.iter().skip(report[2] as usize);
+                report[3..].fill(0);
+                for (row, bytes) in rows.zip(report[3..].chunks_exact_mut(width)) {
+                    for (column, closed) in row.iter().enumerate() {
+                        let bit = width * 8 - 1 - column;
+                        bytes[bit / 8] |= (*closed as u8) << (7 - bit % 8);
+                    }
+                }
+                return Change::Matrix;
+            }
+            _ => report[0] = UNHANDLED,
+        },
+        SET_KEYBOARD_VALUE => match report[1] {
+            // no layout options to choose between
+            LAYOUT_OPTIONS => {}
+            _ => report[0] = UNHANDLED,
+        },
+        GET_KEYCODE => {
+            let code = index(report[1], report[2], report[3]).map_or(0, |index| keymap.code(index));
+            report[4..6].copy_from_slice(&code.to_be_bytes());
+        }
+        SET_KEYCODE => {
+            if let Some(index) = index(report[1], report[2], report[3]) {
+                keymap.set_code(index, u16::from_be_bytes([report[4], report[5]]));
+                return Change::Key {
+                    layer: report[1],
+                    row: report[2],
+                    column: report[3],
+                };
+            }
+        }
+        RESET_KEYMAP | EEPROM_RESET => {
+            *keymap = *defaults;
+            if report[0] == EEPROM_RESET {
+                buffer.fill(0);
+            }
+            return Change::Keymap;
+        }
+        BOOTLOADER_JUMP => return Change::Bootloader,
+        MACRO_GET_COUNT => report[1] = macros::COUNT,
+        MACRO_GET_BUFFER_SIZE => report[1..3].copy_from_slice(&(BUFFER as u16).to_be_bytes()),
+        MACRO_GET_BUFFER | MACRO_SET_BUFFER => {
+            let offset = (u16::from_be_bytes([report[1], report[2]]) as usize).min(BUFFER);
+            let size = (report[3] as usize).min(REPORT_LEN - 4).min(BUFFER - offset);
+            let bytes = &mut buffer[offset..offset + size];
+            if report[0] == MACRO_GET_BUFFER {
+                report[4..4 + size].copy_from_slice(bytes);
+            } else {
+                bytes.copy_from_slice(&report[4..4 + size]);
+                return Change::Macros;
+            }
+        }
+        MACRO_RESET => {
+            buffer.fill(0);
+            return Change::Macros;
+        }
+        GET_LAYER_COUNT => report[1] = LAYERS as u8,
+        GET_BUFFER | SET_BUFFER => {
+            // offset and size in bytes, always whole codes from VIA
+            let offset = u16::from_be_bytes([report[1], report[2]]) as usize / 2;
+            let size = (report[3] as usize).min(REPORT_LEN - 4) / 2;
+            let codes = offset..(offset + size).min(Keymap::<ROWS, COLUMNS, LAYERS>::POSITIONS);
+            let get = report[0] == GET_BUFFER;
+            let data = report[4..].chunks_exact_mut(2);
+            if get {
+                for (index, bytes) in codes.zip(data) {
+                    bytes.copy_from_slice(&keymap.code(index).to_be_bytes());
+                }
+            } else {
+                for (index, bytes) in codes.zip(data) {
+                    keymap.set_code(index, u16::from_be_bytes([bytes[0], bytes[1]]));
+                }
+                return Change::Keymap;
+            }
+        }
+        _ => report[0] = UNHANDLED,
+    }
+    Change::None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{handle, Change, REPORT_LEN};
+    use crate::keymap::{Action, KeyboardUsage::*, TEEB};
+    use crate::macros::BUFFER;
+
+    const OPEN: [[bool; 14]; 5] = [[false; 14]; 5];
+
+    fn report(bytes: &[u8]) -> [u8; REPORT_LEN] {
+        let mut report = [0; REPORT_LEN];
+        report[..bytes.len()].copy_from_slice(bytes);
+        report
+    }
+
+    #[test]
+    fn test_version_and_values() {
+        let mut keymap = TEEB;
+        let mut r = report(&[0x01]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN), Change::None);
+        assert_eq!(r[..3], [0x01, 0x00, 0x0c]);
+
+        let mut r = report(&[0x02, 0x01]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0x0102_0304, &OPEN);
+        assert_eq!(r[..6], [0x02, 0x01, 0x01, 0x02, 0x03, 0x04]);
+
+        let mut r = report(&[0x11]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN);
+        assert_eq!(r[1], 2);
+
+        // lighting is asked for but there is none
+        let mut r = report(&[0x08, 0x01, 0x01]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN);
+        assert_eq!(r[..3], [0xff, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn test_matrix() {
+        let mut keymap = TEEB;
+        let mut matrix = OPEN;
+        matrix[0][0] = true;
+        matrix[1][13] = true;
+        matrix[4][8] = true;
+        let mut r = report(&[0x02, 0x03, 0]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &matrix), Change::Matrix);
+        assert_eq!(r[3..13], [0, 0x01, 0x20, 0, 0, 0, 0, 0, 0x01, 0]);
+
+        let mut r = report(&[0x02, 0x03, 4]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &matrix);
+        assert_eq!(r[3..7], [0x01, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_keycode() {
+        let mut keymap = TEEB;
+        let mut r = report(&[0x04, 0, 4, 2]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN);
+        assert_eq!(r[4..6], [0x52, 0x21]);
+
+        let mut r = report(&[0x05, 1, 4, 4, 0x00, 0x28]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN), Change::Key { layer: 1, row: 4, column: 4 });
+        assert_eq!(keymap.get(1, 4, 4), Action::Key(KeyboardEnter));
+
+        // off the map
+        let mut r = report(&[0x05, 2, 0, 0, 0x00, 0x28]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN), Change::None);
+
+        let mut r = report(&[0x06]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN), Change::Keymap);
+        assert_eq!(keymap.get(1, 4, 4), Action::Trans);
+    }
+
+    #[test]
+    fn test_macros() {
+        let mut keymap = TEEB;
+        let mut buffer = [0; BUFFER];
+        let mut r = report(&[0x0c]);
+        handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN);
+        assert_eq!(r[1], 16);
+        let mut r = report(&[0x0d]);
+        handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN);
+        assert_eq!(r[1..3], [0x02, 0x00]);
+
+        let mut r = report(&[0x0f, 0x01, 0xfe, 4, b'a', b'b', b'c', b'd']);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN), Change::Macros);
+        // only what fits
+        assert_eq!(buffer[510..], *b"ab");
+        let mut r = report(&[0x0e, 0x01, 0xfd, 28]);
+        handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN);
+        assert_eq!(r[4..8], [0, b'a', b'b', 0]);
+
+        let mut r = report(&[0x10]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN), Change::Macros);
+        assert_eq!(buffer, [0; BUFFER]);
+
+        // a full reset takes the macros too
+        buffer[0] = b'x';
+        let mut r = report(&[0x06]);
+        handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN);
+        assert_eq!(buffer[0], b'x');
+        let mut r = report(&[0x0a]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut buffer, 0, &OPEN), Change::Keymap);
+        assert_eq!(buffer[0], 0);
+    }
+
+    #[test]
+    fn test_buffer() {
+        let mut keymap = TEEB;
+        // the last three codes of layer 0 and the first of layer 1
+        let mut r = report(&[0x12, 0, 2 * 67, 8]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN);
+        assert_eq!(r[4..12], [0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+        let mut r = report(&[0x13, 0, 2 * 69, 4, 0x00, 0x04, 0x00, 0x05]);
+        assert_eq!(handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN), Change::Keymap);
+        assert_eq!(keymap.get(0, 4, 13), Action::Key(KeyboardAa));
+        assert_eq!(keymap.get(1, 0, 0), Action::Key(KeyboardBb));
+
+        // reads stop at the end of the keymap
+        let mut r = report(&[0x12, 0x01, 0x16, 28]);
+        handle(&mut r, &mut keymap, &TEEB, &mut [0; BUFFER], 0, &OPEN);
+        assert_eq!(r[4..8], [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,92 @@
+use heapless::Vec;
+use usbd_hid::descriptor::KeyboardUsage::{self, *};
+
+use crate::macros::ascii;
+
+// Control, Shift and Alt, either hand, as report modifier bits
+const CONTROL: u8 = 0x11;
+const SHIFT: u8 = 0x22;
+const ALT: u8 = 0x44;
+
+// The bytes a terminal keyboard sends for a key pressed with `modifiers`
+// held, as xterm does by default: Control folds to C0 controls, Alt sends
+// Escape first, and the cursor and editing keys send their CSI sequences.
+// Nothing for modifiers alone and keys without a meaning here.
+pub fn encode(usage: KeyboardUsage, modifiers: u8) -> Vec<u8, 8> {
+    let mut bytes = Vec::new();
+    let control = modifiers & CONTROL != 0;
+    let shift = modifiers & SHIFT != 0;
+    let sequence: &[u8] = match usage {
+        KeyboardEnter | KeypadEnter => b"\r",
+        KeyboardEscape => b"\x1b",
+        KeyboardBackspace => b"\x7f",
+        KeyboardTab if shift => b"\x1b[Z",
+        KeyboardTab => b"\t",
+        KeyboardUpArrow => b"\x1b[A",
+        KeyboardDownArrow => b"\x1b[B",
+        KeyboardRightArrow => b"\x1b[C",
+        KeyboardLeftArrow => b"\x1b[D",
+        KeyboardHome => b"\x1b[H",
+        KeyboardEnd => b"\x1b[F",
+        KeyboardInsert => b"\x1b[2~",
+        KeyboardDelete => b"\x1b[3~",
+        KeyboardPageUp => b"\x1b[5~",
+        KeyboardPageDown => b"\x1b[6~",
+        KeyboardF1 => b"\x1bOP",
+        KeyboardF2 => b"\x1bOQ",
+        KeyboardF3 => b"\x1bOR",
+        KeyboardF4 => b"\x1bOS",
+        KeyboardF5 => b"\x1b[15~",
+        KeyboardF6 => b"\x1b[17~",
+        KeyboardF7 => b"\x1b[18~",
+        KeyboardF8 => b"\x1b[19~",
+        KeyboardF9 => b"\x1b[20~",
+        KeyboardF10 => b"\x1b[21~",
+        KeyboardF11 => b"\x1b[23~",
+        KeyboardF12 => b"\x1b[24~",
+        _ => {
+            let Some(byte) = character(usage, shift) else {
+                return bytes;
+            };
+            let byte = match byte {
+                b'a'..=b'z' | b'@' | b'['..=b'_' if control => byte & 0x1f,
+                b' ' | b'2' if control => 0,
+                b'?' if control => 0x7f,
+                _ => byte,
+            };
+            if modifiers & ALT != 0 {
+                let _ = bytes.push(0x1b);
+            }
+            let _ = bytes.push(byte);
+            return bytes;
+        }
+    };
+    let _ = bytes.extend_from_slice(sequence);
+    bytes
+}
+
+// The printable ASCII character a usage types on a US layout
+fn character(usage: KeyboardUsage, shift: bool) -> Option<u8> {
+    (b' '..0x7f).find(|byte| ascii(*byte) == Some((usage, shift)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::encode;
+    use crate::keymap::KeyboardUsage::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(KeyboardAa, 0), b"a");
+        assert_eq!(encode(KeyboardAa, 0x20), b"A");
+        assert_eq!(encode(Keyboard1Exclamation, 0x02), b"!");
+        assert_eq!(encode(KeyboardCc, 0x01), b"\x03");
+        assert_eq!(encode(KeyboardOpenBracketBrace, 0x10), b"\x1b");
+        assert_eq!(encode(KeyboardXx, 0x04), b"\x1bx");
+        assert_eq!(encode(KeyboardUpArrow, 0), b"\x1b[A");
+        assert_eq!(encode(KeyboardTab, 0x02), b"\x1b[Z");
+        assert_eq!(encode(KeyboardF5, 0), b"\x1b[15~");
+        assert!(encode(KeyboardLeftShift, 0x02).is_empty());
+        assert!(encode(KeyboardPrintScreen, 0).is_empty());
+    }
+}
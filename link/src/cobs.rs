@@ -0,0 +1,112 @@
+// Consistent Overhead Byte Stuffing: removes every zero from a frame so that
+// zero can delimit frames on the wire.  At most one byte is added per 254.
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Error {
+    Overflow,
+    Malformed,
+}
+
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+// Encodes `data` into `out`, without the trailing delimiter
+pub fn encode(data: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let mut code_at = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+    for byte in data {
+        if *byte == 0 {
+            *out.get_mut(code_at).ok_or(Error::Overflow)? = code;
+            code_at = len;
+            len += 1;
+            code = 1;
+        } else {
+            *out.get_mut(len).ok_or(Error::Overflow)? = *byte;
+            len += 1;
+            code += 1;
+            if code == 0xff {
+                *out.get_mut(code_at).ok_or(Error::Overflow)? = code;
+                code_at = len;
+                len += 1;
+                code = 1;
+            }
+        }
+    }
+    *out.get_mut(code_at).ok_or(Error::Overflow)? = code;
+    Ok(len)
+}
+
+// Decodes a frame, without its delimiter, into `out`
+pub fn decode(data: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let mut len = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return Err(Error::Malformed);
+        }
+        let run = &data[i + 1..i + code];
+        if run.contains(&0) {
+            return Err(Error::Malformed);
+        }
+        out.get_mut(len..len + run.len())
+            .ok_or(Error::Overflow)?
+            .copy_from_slice(run);
+        len += run.len();
+        i += code;
+        if code != 0xff && i < data.len() {
+            *out.get_mut(len).ok_or(Error::Overflow)? = 0;
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, max_encoded_len, Error};
+
+    fn round_trip(data: &[u8]) -> heapless::Vec<u8, 600> {
+        let mut encoded = [0; 600];
+        let len = encode(data, &mut encoded).unwrap();
+        assert!(len <= max_encoded_len(data.len()));
+        assert!(!encoded[..len].contains(&0));
+
+        let mut decoded = [0; 600];
+        let n = decode(&encoded[..len], &mut decoded).unwrap();
+        assert_eq!(&decoded[..n], data);
+        heapless::Vec::from_slice(&encoded[..len]).unwrap()
+    }
+
+    #[test]
+    fn test_examples() {
+        assert_eq!(round_trip(&[]), [1]);
+        assert_eq!(round_trip(&[0]), [1, 1]);
+        assert_eq!(round_trip(&[0, 0]), [1, 1, 1]);
+        assert_eq!(
+            round_trip(&[0x11, 0x22, 0x00, 0x33]),
+            [3, 0x11, 0x22, 2, 0x33]
+        );
+        assert_eq!(round_trip(&[0x11, 0x00, 0x00, 0x00]), [2, 0x11, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_long_runs() {
+        let data: [u8; 254] = core::array::from_fn(|i| i as u8 + 1);
+        let encoded = round_trip(&data);
+        assert_eq!((encoded[0], encoded.len()), (0xff, 256));
+
+        let data: [u8; 520] = core::array::from_fn(|i| (i % 255) as u8);
+        round_trip(&data);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut out = [0; 4];
+        assert_eq!(decode(&[3, 1], &mut out), Err(Error::Malformed));
+        assert_eq!(decode(&[2, 0], &mut out), Err(Error::Malformed));
+        assert_eq!(encode(&[1, 2, 3, 4], &mut out), Err(Error::Overflow));
+    }
+}
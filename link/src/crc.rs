@@ -0,0 +1,26 @@
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::crc16;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(b""), 0xffff);
+    }
+}
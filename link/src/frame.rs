@@ -0,0 +1,211 @@
+use heapless::Vec;
+
+use crate::cobs;
+use crate::crc::crc16;
+
+// Bumped on any change to the frame layout or to the meaning of a message
+pub const VERSION: u8 = 1;
+
+pub const MAX_PAYLOAD: usize = 64;
+
+// version, kind, sequence number, payload, CRC (big endian) over all of it
+const HEADER: usize = 3;
+const MAX_RAW: usize = HEADER + MAX_PAYLOAD + 2;
+
+// COBS encoded, with its zero delimiter
+pub const MAX_FRAME: usize = cobs::max_encoded_len(MAX_RAW) + 1;
+
+pub type Frame = Vec<u8, MAX_FRAME>;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Kind {
+    // acknowledges every data frame up to and including `seq`
+    Ack,
+    Data,
+    // no payload, resets the receiver to expect `seq + 1`; the first frame
+    // a sender sends, and the only one until it is acknowledged
+    Sync,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Packet {
+    pub kind: Kind,
+    pub seq: u8,
+    pub payload: Vec<u8, MAX_PAYLOAD>,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Error {
+    Cobs(cobs::Error),
+    Short,
+    Crc,
+    Version(u8),
+    Kind(u8),
+    // longer than any frame, dropped up to the next delimiter
+    Overflow,
+}
+
+pub fn encode(kind: Kind, seq: u8, payload: &[u8]) -> Frame {
+    assert!(payload.len() <= MAX_PAYLOAD);
+    let mut raw = [0; MAX_RAW];
+    raw[0] = VERSION;
+    raw[1] = match kind {
+        Kind::Ack => 0,
+        Kind::Data => 1,
+        Kind::Sync => 2,
+    };
+    raw[2] = seq;
+    raw[HEADER..HEADER + payload.len()].copy_from_slice(payload);
+    let len = HEADER + payload.len();
+    let crc = crc16(&raw[..len]);
+    raw[len..len + 2].copy_from_slice(&crc.to_be_bytes());
+
+    let mut frame = Frame::new();
+    frame.resize_default(MAX_FRAME).unwrap();
+    let n = cobs::encode(&raw[..len + 2], &mut frame).unwrap();
+    frame.truncate(n);
+    frame.push(0).unwrap();
+    frame
+}
+
+// Splits a byte stream into packets.  Garbage, truncated and corrupted
+// frames are reported and skipped, decoding picks up again at the next
+// delimiter.
+pub struct Decoder {
+    buf: Vec<u8, MAX_FRAME>,
+    overflowed: bool,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        if byte != 0 {
+            if self.buf.push(byte).is_err() {
+                self.overflowed = true;
+            }
+            return None;
+        }
+
+        let result = if core::mem::take(&mut self.overflowed) {
+            Some(Err(Error::Overflow))
+        } else if self.buf.is_empty() {
+            // back to back delimiters, e.g. one sent to resync
+            None
+        } else {
+            Some(decode(&self.buf))
+        };
+        self.buf.clear();
+        result
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn decode(encoded: &[u8]) -> Result<Packet, Error> {
+    let mut raw = [0; MAX_RAW];
+    let len = cobs::decode(encoded, &mut raw).map_err(Error::Cobs)?;
+    if len < HEADER + 2 {
+        return Err(Error::Short);
+    }
+    let (body, crc) = raw[..len].split_at(len - 2);
+    if crc16(body).to_be_bytes() != crc {
+        return Err(Error::Crc);
+    }
+    if body[0] != VERSION {
+        return Err(Error::Version(body[0]));
+    }
+    let kind = match body[1] {
+        0 => Kind::Ack,
+        1 => Kind::Data,
+        2 => Kind::Sync,
+        kind => return Err(Error::Kind(kind)),
+    };
+    Ok(Packet {
+        kind,
+        seq: body[2],
+        payload: Vec::from_slice(&body[HEADER..]).unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, Decoder, Error, Kind, Packet, MAX_FRAME};
+
+    fn decode_all(decoder: &mut Decoder, bytes: &[u8]) -> heapless::Vec<Result<Packet, Error>, 8> {
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.push(*byte))
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frame = encode(Kind::Data, 7, &[0, 1, 0, 2]);
+        assert_eq!(frame.last(), Some(&0));
+        assert_eq!(frame.iter().filter(|byte| **byte == 0).count(), 1);
+
+        let mut decoder = Decoder::new();
+        let packets = decode_all(&mut decoder, &frame);
+        assert_eq!(packets.len(), 1);
+        let packet = packets[0].as_ref().unwrap();
+        assert_eq!(
+            (packet.kind, packet.seq, packet.payload.as_slice()),
+            (Kind::Data, 7, [0, 1, 0, 2].as_slice())
+        );
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut decoder = Decoder::new();
+        let first = encode(Kind::Data, 1, b"first");
+        let second = encode(Kind::Ack, 2, &[]);
+
+        // a byte lost from the first frame only costs that frame
+        let mut stream: heapless::Vec<u8, 64> = heapless::Vec::new();
+        stream.extend_from_slice(&first[..3]).unwrap();
+        stream.extend_from_slice(&first[4..]).unwrap();
+        stream.extend_from_slice(&second).unwrap();
+        let packets = decode_all(&mut decoder, &stream);
+        assert!(packets[0].is_err());
+        assert_eq!(packets[1].as_ref().unwrap().seq, 2);
+
+        // corruption is caught by the CRC
+        let mut corrupt = encode(Kind::Data, 3, b"third");
+        corrupt[5] ^= 0x40;
+        assert_eq!(
+            decode_all(&mut decoder, &corrupt).as_slice(),
+            [Err(Error::Crc)]
+        );
+
+        // line noise longer than a frame
+        let noise = [0x55; MAX_FRAME + 10];
+        assert_eq!(decode_all(&mut decoder, &noise).as_slice(), []);
+        assert_eq!(decoder.push(0), Some(Err(Error::Overflow)));
+        assert_eq!(decode_all(&mut decoder, &second).len(), 1);
+    }
+
+    #[test]
+    fn test_version() {
+        let mut raw = [2, 0, 0, 0, 0];
+        let crc = crate::crc::crc16(&raw[..3]);
+        raw[3..].copy_from_slice(&crc.to_be_bytes());
+        let mut frame = [0; 8];
+        let len = crate::cobs::encode(&raw, &mut frame).unwrap();
+
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &frame[..len + 1]).as_slice(),
+            [Err(Error::Version(2))]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+#![no_std]
+
+pub mod cobs;
+pub mod crc;
+pub mod frame;
+pub mod message;
+pub mod session;
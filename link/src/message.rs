@@ -0,0 +1,347 @@
+// What travels over the link, inside data frames.  The first byte is a tag,
+// new messages take new tags so that older firmware can reject them.
+// Numbers are little endian.
+//
+// The bottom half sends keys and macro keys, the switches and key stats
+// while testing, and answers the top half's requests: the get messages with
+// the matching reply, the rest with nothing.
+
+// An NKRO report as built by `keyboard::report::Keys::nkro`
+pub const KEYS_LEN: usize = 29;
+
+// Consumer, system and mouse keys as built by
+// `keyboard::extrakey::Extras::to_bytes`
+pub const EXTRAS_LEN: usize = 8;
+
+// A bit per switch as built by `keyboard::diagnostics::pack`, enough for
+// 128 of them
+pub const MATRIX_LEN: usize = 16;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Message {
+    // bottom to top: every key held, after any change
+    Keys([u8; KEYS_LEN]),
+    // the host's keyboard LEDs: Num, Caps and Scroll Lock, Compose, Kana
+    Leds(u8),
+    // the top half's clock, in microseconds
+    Time(u64),
+    // one position of the keymap, the action as `keyboard::keymap::Action::code`
+    SetAction {
+        layer: u8,
+        row: u8,
+        column: u8,
+        code: u16,
+    },
+    Config(Config),
+    // reboot into the USB bootloader
+    Bootloader,
+    GetVersion,
+    Version(Version),
+    GetHealth,
+    Health(Health),
+    // bottom to top: a macro key pressed, the action as `Action::code`
+    Macro(u16),
+    // bottom to top: every consumer, system and mouse key held, after any
+    // change
+    Extras([u8; EXTRAS_LEN]),
+    // start or stop testing keys: the bottom half sends the switches on
+    // every change and a key's stats as they're worth knowing
+    Diagnostics(bool),
+    // bottom to top: the switches as read, before debouncing
+    Matrix([u8; MATRIX_LEN]),
+    KeyStats(KeyStats),
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Config {
+    pub tapping_term_ms: u16,
+    pub permissive_hold: bool,
+    pub hold_on_other_key_press: bool,
+    pub combo_term_ms: u16,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Version {
+    // `frame::VERSION` of the sender
+    pub protocol: u8,
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Health {
+    pub uptime_ms: u32,
+    pub retransmits: u32,
+    // frames dropped for a bad CRC, bad COBS and the like
+    pub bad_frames: u32,
+}
+
+// One key, as counted by `keyboard::diagnostics`
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct KeyStats {
+    pub row: u8,
+    pub column: u8,
+    pub presses: u32,
+    pub chatter: u16,
+    pub ghosts: u16,
+    // zero while it's up
+    pub held_ms: u32,
+    pub stuck: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Error {
+    Tag(u8),
+    Length,
+}
+
+const KEYS: u8 = 1;
+const LEDS: u8 = 2;
+const TIME: u8 = 3;
+const SET_ACTION: u8 = 4;
+const CONFIG: u8 = 5;
+const BOOTLOADER: u8 = 6;
+const GET_VERSION: u8 = 7;
+const VERSION: u8 = 8;
+const GET_HEALTH: u8 = 9;
+const HEALTH: u8 = 10;
+const MACRO: u8 = 11;
+const EXTRAS: u8 = 12;
+const DIAGNOSTICS: u8 = 13;
+const MATRIX: u8 = 14;
+const KEY_STATS: u8 = 15;
+
+impl Message {
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        let mut out = Writer { out, len: 0 };
+        match self {
+            Message::Keys(report) => {
+                out.put(&[KEYS]);
+                out.put(report);
+            }
+            Message::Leds(leds) => out.put(&[LEDS, *leds]),
+            Message::Time(us) => {
+                out.put(&[TIME]);
+                out.put(&us.to_le_bytes());
+            }
+            Message::SetAction {
+                layer,
+                row,
+                column,
+                code,
+            } => {
+                out.put(&[SET_ACTION, *layer, *row, *column]);
+                out.put(&code.to_le_bytes());
+            }
+            Message::Config(config) => {
+                let flags =
+                    config.permissive_hold as u8 | (config.hold_on_other_key_press as u8) << 1;
+                out.put(&[CONFIG]);
+                out.put(&config.tapping_term_ms.to_le_bytes());
+                out.put(&[flags]);
+                out.put(&config.combo_term_ms.to_le_bytes());
+            }
+            Message::Bootloader => out.put(&[BOOTLOADER]),
+            Message::GetVersion => out.put(&[GET_VERSION]),
+            Message::Version(version) => out.put(&[
+                VERSION,
+                version.protocol,
+                version.major,
+                version.minor,
+                version.patch,
+            ]),
+            Message::GetHealth => out.put(&[GET_HEALTH]),
+            Message::Health(health) => {
+                out.put(&[HEALTH]);
+                out.put(&health.uptime_ms.to_le_bytes());
+                out.put(&health.retransmits.to_le_bytes());
+                out.put(&health.bad_frames.to_le_bytes());
+            }
+            Message::Macro(code) => {
+                out.put(&[MACRO]);
+                out.put(&code.to_le_bytes());
+            }
+            Message::Extras(extras) => {
+                out.put(&[EXTRAS]);
+                out.put(extras);
+            }
+            Message::Diagnostics(on) => out.put(&[DIAGNOSTICS, *on as u8]),
+            Message::Matrix(matrix) => {
+                out.put(&[MATRIX]);
+                out.put(matrix);
+            }
+            Message::KeyStats(stats) => {
+                out.put(&[KEY_STATS, stats.row, stats.column]);
+                out.put(&stats.presses.to_le_bytes());
+                out.put(&stats.chatter.to_le_bytes());
+                out.put(&stats.ghosts.to_le_bytes());
+                out.put(&stats.held_ms.to_le_bytes());
+                out.put(&[stats.stuck as u8]);
+            }
+        }
+        out.len
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let (tag, body) = bytes.split_first().ok_or(Error::Length)?;
+        let mut body = Reader(body);
+        let message = match *tag {
+            KEYS => Message::Keys(body.take()?),
+            LEDS => Message::Leds(body.u8()?),
+            TIME => Message::Time(u64::from_le_bytes(body.take()?)),
+            SET_ACTION => Message::SetAction {
+                layer: body.u8()?,
+                row: body.u8()?,
+                column: body.u8()?,
+                code: u16::from_le_bytes(body.take()?),
+            },
+            CONFIG => {
+                let tapping_term_ms = u16::from_le_bytes(body.take()?);
+                let flags = body.u8()?;
+                Message::Config(Config {
+                    tapping_term_ms,
+                    permissive_hold: flags & 1 != 0,
+                    hold_on_other_key_press: flags & 2 != 0,
+                    combo_term_ms: u16::from_le_bytes(body.take()?),
+                })
+            }
+            BOOTLOADER => Message::Bootloader,
+            GET_VERSION => Message::GetVersion,
+            VERSION => Message::Version(Version {
+                protocol: body.u8()?,
+                major: body.u8()?,
+                minor: body.u8()?,
+                patch: body.u8()?,
+            }),
+            GET_HEALTH => Message::GetHealth,
+            HEALTH => Message::Health(Health {
+                uptime_ms: u32::from_le_bytes(body.take()?),
+                retransmits: u32::from_le_bytes(body.take()?),
+                bad_frames: u32::from_le_bytes(body.take()?),
+            }),
+            MACRO => Message::Macro(u16::from_le_bytes(body.take()?)),
+            EXTRAS => Message::Extras(body.take()?),
+            DIAGNOSTICS => Message::Diagnostics(body.u8()? != 0),
+            MATRIX => Message::Matrix(body.take()?),
+            KEY_STATS => Message::KeyStats(KeyStats {
+                row: body.u8()?,
+                column: body.u8()?,
+                presses: u32::from_le_bytes(body.take()?),
+                chatter: u16::from_le_bytes(body.take()?),
+                ghosts: u16::from_le_bytes(body.take()?),
+                held_ms: u32::from_le_bytes(body.take()?),
+                stuck: body.u8()? != 0,
+            }),
+            tag => return Err(Error::Tag(tag)),
+        };
+        if !body.0.is_empty() {
+            return Err(Error::Length);
+        }
+        Ok(message)
+    }
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.out[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.0.len() < N {
+            return Err(Error::Length);
+        }
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Error, Health, KeyStats, Message, Version, KEYS_LEN};
+
+    #[test]
+    fn test_round_trip() {
+        let mut report = [0; KEYS_LEN];
+        report[0] = 0x02;
+        report[1] = 0x10;
+        let message = Message::Keys(report);
+
+        let mut out = [0; 64];
+        let len = message.encode(&mut out);
+        assert_eq!(Message::decode(&out[..len]), Ok(message));
+        assert_eq!(Message::decode(&out[..len - 1]), Err(Error::Length));
+        assert_eq!(Message::decode(&[0x7f]), Err(Error::Tag(0x7f)));
+    }
+
+    #[test]
+    fn test_requests() {
+        let messages = [
+            Message::Leds(0x02),
+            Message::Time(0x0102_0304_0506_0708),
+            Message::SetAction {
+                layer: 1,
+                row: 2,
+                column: 13,
+                code: 0x5221,
+            },
+            Message::Config(Config {
+                tapping_term_ms: 175,
+                permissive_hold: true,
+                hold_on_other_key_press: false,
+                combo_term_ms: 40,
+            }),
+            Message::Bootloader,
+            Message::GetVersion,
+            Message::Version(Version {
+                protocol: 1,
+                major: 0,
+                minor: 1,
+                patch: 0,
+            }),
+            Message::GetHealth,
+            Message::Health(Health {
+                uptime_ms: 60_000,
+                retransmits: 3,
+                bad_frames: 1,
+            }),
+            Message::Macro(0x7c56),
+            Message::Extras([0xe9, 0, 0, 0, 0x10, 0, 0, 0]),
+            Message::Diagnostics(true),
+            Message::Matrix([0x01, 0x80, 0, 0, 0, 0, 0, 0, 0, 0x20, 0, 0, 0, 0, 0, 0]),
+            Message::KeyStats(KeyStats {
+                row: 4,
+                column: 13,
+                presses: 1234,
+                chatter: 2,
+                ghosts: 0,
+                held_ms: 12_000,
+                stuck: true,
+            }),
+        ];
+        let mut out = [0; 64];
+        for message in messages {
+            let len = message.encode(&mut out);
+            assert_eq!(Message::decode(&out[..len]), Ok(message));
+            assert_eq!(Message::decode(&out[..len + 1]), Err(Error::Length));
+        }
+
+        Message::Time(0x0102_0304_0506_0708).encode(&mut out);
+        assert_eq!(&out[..3], [3, 8, 7]);
+    }
+}
@@ -0,0 +1,374 @@
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, Vec};
+
+use crate::frame::{self, Frame, Kind, Packet};
+use crate::message::Message;
+
+pub const WINDOW: usize = 8;
+
+pub type Frames = Vec<Frame, WINDOW>;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Error {
+    // the window is full of unacknowledged messages
+    Full,
+    Message(crate::message::Error),
+}
+
+// What came of a received packet: frames to send back, a message if it was
+// new, and whether the other end has just started over, having forgotten
+// anything it was sent before
+#[derive(Debug, Eq, PartialEq)]
+pub struct Received {
+    pub reply: Frames,
+    pub message: Option<Result<Message, Error>>,
+    pub restarted: bool,
+}
+
+// One end of the link, sending and receiving.  Messages are numbered and
+// delivered in order exactly once (go-back-N): the receiver only takes the
+// next number and acknowledges cumulatively, the sender resends everything
+// unacknowledged once the oldest has waited `timeout`.
+//
+// A sender starts with a sync, and holds its messages back until that is
+// acknowledged, so a receiver that has been running all along follows a
+// restart without mistaking it for loss.
+pub struct Link {
+    timeout: Duration,
+    next: u8,
+    // none is the sync
+    unacked: Deque<(u8, Option<Message>), WINDOW>,
+    sent: Instant,
+    synced: bool,
+    // next sequence number expected, none until the first frame arrives
+    expected: Option<u8>,
+    retransmits: u32,
+}
+
+impl Link {
+    pub const fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next: 0,
+            unacked: Deque::new(),
+            sent: Instant::MIN,
+            synced: false,
+            expected: None,
+            retransmits: 0,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.unacked.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.unacked.len() + (!self.synced && self.unacked.is_empty()) as usize >= WINDOW
+    }
+
+    // Frames sent again since startup
+    pub fn retransmits(&self) -> u32 {
+        self.retransmits
+    }
+
+    // Queues a message, returning what to transmit now: the message, or the
+    // sync on the first call
+    pub fn send(&mut self, message: Message, now: Instant) -> Result<Option<Frame>, Error> {
+        if self.is_full() {
+            return Err(Error::Full);
+        }
+        let start = !self.synced && self.unacked.is_empty();
+        if start {
+            self.push(None, now);
+        }
+        let seq = self.push(Some(message), now);
+        Ok(if start {
+            Some(frame::encode(
+                Kind::Sync,
+                self.unacked.front().unwrap().0,
+                &[],
+            ))
+        } else if self.synced {
+            Some(data(seq, &message))
+        } else {
+            None
+        })
+    }
+
+    // When the oldest frame is due to be resent
+    pub fn deadline(&self) -> Option<Instant> {
+        (!self.unacked.is_empty()).then(|| self.sent + self.timeout)
+    }
+
+    pub fn retransmit(&mut self, now: Instant) -> Frames {
+        if self.deadline().is_some_and(|deadline| now >= deadline) {
+            self.sent = now;
+            let frames = self.outstanding();
+            self.retransmits = self.retransmits.wrapping_add(frames.len() as u32);
+            frames
+        } else {
+            Frames::new()
+        }
+    }
+
+    pub fn receive(&mut self, packet: &Packet, now: Instant) -> Received {
+        let mut reply = Frames::new();
+        let mut message = None;
+        let mut restarted = false;
+        match packet.kind {
+            Kind::Ack => {
+                if self.acknowledged(packet.seq, now) {
+                    reply = self.outstanding();
+                }
+            }
+            Kind::Sync => {
+                // a resend of the sync just taken changes nothing
+                restarted = self.expected != Some(packet.seq.wrapping_add(1));
+                self.expected = Some(packet.seq.wrapping_add(1));
+            }
+            Kind::Data => {
+                if self.expected.is_none_or(|expected| packet.seq == expected) {
+                    self.expected = Some(packet.seq.wrapping_add(1));
+                    message = Some(Message::decode(&packet.payload).map_err(Error::Message));
+                }
+            }
+        }
+        if packet.kind != Kind::Ack {
+            // duplicates and frames after a gap get the last one taken
+            if let Some(expected) = self.expected {
+                let _ = reply.push(frame::encode(Kind::Ack, expected.wrapping_sub(1), &[]));
+            }
+        }
+        Received {
+            reply,
+            message,
+            restarted,
+        }
+    }
+
+    fn push(&mut self, message: Option<Message>, now: Instant) -> u8 {
+        let seq = self.next;
+        if self.unacked.is_empty() {
+            self.sent = now;
+        }
+        let _ = self.unacked.push_back((seq, message));
+        self.next = seq.wrapping_add(1);
+        seq
+    }
+
+    // Returns whether this acknowledged the sync
+    fn acknowledged(&mut self, seq: u8, now: Instant) -> bool {
+        let mut synced = false;
+        let mut progress = false;
+        // everything up to `seq`, allowing for wrap around
+        while let Some((front, message)) = self.unacked.front() {
+            if seq.wrapping_sub(*front) as usize >= WINDOW {
+                break;
+            }
+            synced |= message.is_none();
+            self.unacked.pop_front();
+            progress = true;
+        }
+        if progress {
+            self.sent = now;
+        }
+        if synced {
+            self.synced = true;
+        }
+        synced
+    }
+
+    // Every frame not yet acknowledged, or just the sync until it is
+    fn outstanding(&self) -> Frames {
+        let mut frames = Frames::new();
+        for (seq, message) in self.unacked.iter() {
+            match message {
+                None => {
+                    let _ = frames.push(frame::encode(Kind::Sync, *seq, &[]));
+                    break;
+                }
+                Some(message) => {
+                    let _ = frames.push(data(*seq, message));
+                }
+            }
+        }
+        frames
+    }
+}
+
+fn data(seq: u8, message: &Message) -> Frame {
+    let mut payload = [0; frame::MAX_PAYLOAD];
+    let len = message.encode(&mut payload);
+    frame::encode(Kind::Data, seq, &payload[..len])
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use super::{Error, Frames, Link, WINDOW};
+    use crate::frame::{Decoder, Frame};
+    use crate::message::{Message, KEYS_LEN};
+
+    type Messages = heapless::Vec<Message, 16>;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn keys(n: u8) -> Message {
+        Message::Keys([n; KEYS_LEN])
+    }
+
+    fn link() -> Link {
+        Link::new(Duration::from_millis(20))
+    }
+
+    // Feeds frames as a byte stream into `to`, collecting what it delivers
+    // and what it sends back
+    fn deliver(to: &mut Link, frames: &[Frame], now: Instant) -> (Messages, Frames) {
+        let mut decoder = Decoder::new();
+        let mut messages = Messages::new();
+        let mut replies = Frames::new();
+        for byte in frames.iter().flatten() {
+            if let Some(Ok(packet)) = decoder.push(*byte) {
+                let received = to.receive(&packet, now);
+                for frame in received.reply {
+                    replies.push(frame).unwrap();
+                }
+                if let Some(message) = received.message {
+                    messages.push(message.unwrap()).unwrap();
+                }
+            }
+        }
+        (messages, replies)
+    }
+
+    // Sends the first message and completes the sync
+    fn connect(bottom: &mut Link, top: &mut Link, message: Message) -> Messages {
+        let sync = bottom.send(message, at(0)).unwrap().unwrap();
+        let (messages, acks) = deliver(top, &[sync], at(0));
+        assert!(messages.is_empty());
+        let (_, frames) = deliver(bottom, &acks, at(0));
+        let (messages, acks) = deliver(top, &frames, at(0));
+        deliver(bottom, &acks, at(0));
+        assert!(bottom.is_idle());
+        messages
+    }
+
+    #[test]
+    fn test_loopback() {
+        let (mut bottom, mut top) = (link(), link());
+        assert_eq!(connect(&mut bottom, &mut top, keys(1)), [keys(1)]);
+
+        let frames = [
+            bottom.send(keys(2), at(1)).unwrap().unwrap(),
+            bottom.send(keys(3), at(1)).unwrap().unwrap(),
+        ];
+        let (messages, acks) = deliver(&mut top, &frames, at(2));
+        assert_eq!(messages, [keys(2), keys(3)]);
+        assert!(!bottom.is_idle());
+        deliver(&mut bottom, &acks, at(3));
+        assert!(bottom.is_idle());
+        assert_eq!(bottom.deadline(), None);
+    }
+
+    #[test]
+    fn test_sync() {
+        let (mut bottom, mut top) = (link(), link());
+        // held back until the sync, lost here, is acknowledged
+        let _lost = bottom.send(keys(1), at(0)).unwrap().unwrap();
+        assert_eq!(bottom.send(keys(2), at(1)).unwrap(), None);
+        assert_eq!(bottom.deadline(), Some(at(20)));
+
+        let resent = bottom.retransmit(at(20));
+        assert_eq!(resent.len(), 1);
+        let (_, acks) = deliver(&mut top, &resent, at(21));
+        let (_, frames) = deliver(&mut bottom, &acks, at(22));
+        assert_eq!(deliver(&mut top, &frames, at(23)).0, [keys(1), keys(2)]);
+    }
+
+    #[test]
+    fn test_lost_frame() {
+        let (mut bottom, mut top) = (link(), link());
+        connect(&mut bottom, &mut top, keys(1));
+
+        // the second frame is lost, the third comes after a gap and is dropped
+        let _lost = bottom.send(keys(2), at(10)).unwrap().unwrap();
+        let third = bottom.send(keys(3), at(10)).unwrap().unwrap();
+        let (messages, acks) = deliver(&mut top, &[third], at(11));
+        assert!(messages.is_empty());
+        deliver(&mut bottom, &acks, at(12));
+
+        assert!(bottom.retransmit(at(29)).is_empty());
+        let resent = bottom.retransmit(at(30));
+        assert_eq!(resent.len(), 2);
+        assert_eq!(bottom.retransmits(), 2);
+        let (messages, acks) = deliver(&mut top, &resent, at(31));
+        assert_eq!(messages, [keys(2), keys(3)]);
+        deliver(&mut bottom, &acks, at(32));
+        assert!(bottom.is_idle());
+    }
+
+    #[test]
+    fn test_lost_ack() {
+        let (mut bottom, mut top) = (link(), link());
+        connect(&mut bottom, &mut top, keys(1));
+        let frame = bottom.send(keys(2), at(0)).unwrap().unwrap();
+        let (messages, _lost) = deliver(&mut top, &[frame], at(1));
+        assert_eq!(messages, [keys(2)]);
+
+        // the resend is acknowledged again but not delivered twice
+        let resent = bottom.retransmit(at(20));
+        let (messages, acks) = deliver(&mut top, &resent, at(21));
+        assert!(messages.is_empty());
+        deliver(&mut bottom, &acks, at(22));
+        assert!(bottom.is_idle());
+    }
+
+    #[test]
+    fn test_restart() {
+        let (mut bottom, mut top) = (link(), link());
+        connect(&mut bottom, &mut top, keys(1));
+        for n in 2..5 {
+            let frame = bottom.send(keys(n), at(0)).unwrap().unwrap();
+            let (_, acks) = deliver(&mut top, &[frame], at(0));
+            deliver(&mut bottom, &acks, at(0));
+        }
+
+        // bottom restarts and numbers from zero again
+        let mut bottom = link();
+        assert_eq!(connect(&mut bottom, &mut top, keys(9)), [keys(9)]);
+
+        // top restarts and takes whatever comes first
+        let mut top = link();
+        let frame = bottom.send(keys(10), at(0)).unwrap().unwrap();
+        assert_eq!(deliver(&mut top, &[frame], at(0)).0, [keys(10)]);
+    }
+
+    #[test]
+    fn test_restarted() {
+        let (mut bottom, mut top) = (link(), link());
+        let sync = bottom.send(keys(1), at(0)).unwrap().unwrap();
+        let mut decoder = Decoder::new();
+        let packet = sync
+            .iter()
+            .find_map(|byte| decoder.push(*byte))
+            .unwrap()
+            .unwrap();
+        assert!(top.receive(&packet, at(0)).restarted);
+        // the same sync again, its acknowledgement lost
+        assert!(!top.receive(&packet, at(20)).restarted);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut bottom = link();
+        for n in 0..WINDOW as u8 - 1 {
+            assert!(!bottom.is_full());
+            bottom.send(keys(n), at(0)).unwrap();
+        }
+        assert!(bottom.is_full());
+        assert_eq!(bottom.send(keys(0), at(0)), Err(Error::Full));
+    }
+}
@@ -1,160 +1,143 @@
 #![feature(ascii_char)]
 #![feature(ascii_char_variants)]
 use embedded_graphics::{pixelcolor::Rgb888, prelude::Size};
-use embedded_graphics_simulator::{sdl2::Keycode, SimulatorEvent, OutputSettingsBuilder, SimulatorDisplay, Window};
+use embedded_graphics_simulator::{SimulatorEvent, OutputSettingsBuilder, SimulatorDisplay, Window};
+use nix::{
+    pty::{openpty, Winsize},
+    sys::signal::{kill, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    unistd::{setsid, Pid},
+};
 use std::{
-    io::{Read, Write, BufReader, BufRead},
-    process::{ChildStderr, ChildStdout, Command, Stdio},
-    sync::mpsc::{Receiver, TryRecvError, channel},
+    fs::File,
+    io::{Read, Write},
+    os::{fd::{AsRawFd, OwnedFd}, unix::process::CommandExt},
+    process::{Command, Stdio},
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, TryRecvError}},
     thread,
-    time
 };
+use terminal::grid::{COLUMNS, ROWS};
 use terminal::Terminal;
-use terminal::c1::Parser;
-use terminal::csi::Parser as CsiParser;
-use terminal::osc::Parser as OscParser;
 
-#[tokio::main]
-async fn main() -> Result<(), core::convert::Infallible> {
-    let mut bash = Command::new("bash")
+static CHILD_EXITED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigchld(_: nix::libc::c_int) {
+    CHILD_EXITED.store(true, Ordering::Relaxed);
+}
+
+nix::ioctl_write_ptr_bad!(set_window_size, nix::libc::TIOCSWINSZ, Winsize);
+
+fn main() {
+    let winsize = Winsize {
+        ws_row: ROWS as u16,
+        ws_col: COLUMNS as u16,
+        ws_xpixel: 800,
+        ws_ypixel: 300,
+    };
+    let pty = openpty(None, None).unwrap();
+    unsafe { set_window_size(pty.master.as_raw_fd(), &winsize) }.unwrap();
+
+    let handler = SigAction::new(SigHandler::Handler(on_sigchld), SaFlags::SA_NOCLDSTOP, SigSet::empty());
+    unsafe { sigaction(Signal::SIGCHLD, &handler) }.unwrap();
+
+    let slave: OwnedFd = pty.slave;
+    let mut command = Command::new("bash");
+    command
         .arg("--login")
         .arg("-i")
-        .arg("-m")
-        .stderr(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stdin(Stdio::piped())
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
         .env("TERM", "xterm")
-        .spawn()
-        .unwrap();
+        .env("COLUMNS", COLUMNS.to_string())
+        .env("LINES", ROWS.to_string());
+    // new session with the pty as its controlling terminal, so that job
+    // control and SIGWINCH work as they would on the device
+    unsafe {
+        command.pre_exec(|| {
+            setsid()?;
+            if nix::libc::ioctl(0, nix::libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut bash = command.spawn().unwrap();
+    // the parent's copies of the slave fd have to go for EOF to be seen
+    drop(command);
+
+    let mut master = File::from(pty.master);
+    let mut reader = master.try_clone().unwrap();
+    let (tx, rx) = channel::<Vec<u8>>();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        // reading fails with EIO once the last slave fd is closed
+        while let Ok(n @ 1..) = reader.read(&mut buffer) {
+            if tx.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
 
     let mut display = SimulatorDisplay::<Rgb888>::new(Size::new(800, 300));
     let output_settings = OutputSettingsBuilder::new().scale(1).build();
-    let mut window = Window::new("Hello World", &output_settings);
+    let mut window = Window::new("Teeb", &output_settings);
     let mut terminal: Terminal<'_> = Terminal::new();
+    let mut parser = terminal::stream::Parser::new();
     terminal.clear(&mut display);
 
-    terminal.push(
-        terminal::Character::ControlSequenceIntroducer(
-            terminal::csi::ControlSequenceIntroducer::SelectGraphicRendition(
-                terminal::csi::select_graphic_rendition::SelectGraphicRendition::SetForegroundColor4
-            )
-        )
-    );
-
-    let mut stdout = bash.stdout.unwrap();
-    let (tx_o, stdout_reader) = channel::<u8>();
-    thread::spawn(move || loop {
-        let mut buffer = [0];
-        stdout.read(&mut buffer).unwrap();
-        tx_o.send(buffer[0]);
-    });
-
-    let mut stderr = bash.stderr.unwrap();
-    let (tx_e, stderr_reader) = channel::<u8>();
-    thread::spawn(move || loop {
-        let mut buffer = [0];
-        stderr.read(&mut buffer).unwrap();
-        tx_e.send(buffer[0]);
-    });
-
     'running: loop {
-        window.update(&display);
-
-        let mut c1_parser_buffer: Vec<u8> = Vec::new();
-        let mut csi_parser_buffer: Vec<terminal::Character> = Vec::new();
-        let mut osc_parser_buffer: Vec<terminal::Character> = Vec::new();
-
-        let mut err_c1_parser_buffer: Vec<u8> = Vec::new();
-        let mut err_csi_parser_buffer: Vec<terminal::Character> = Vec::new();
-        let mut err_osc_parser_buffer: Vec<terminal::Character> = Vec::new();
-
-        'readerr: loop {
-            match stderr_reader.try_recv() {
-                Ok(key) => {
-                    c1_parser_buffer.push(key);
-                    let mut c1_parser = Parser::new(&c1_parser_buffer);
-                    if let Some(c) = c1_parser.next() {
-                        csi_parser_buffer.push(c);
-                        let mut csi_parser = CsiParser::new(&csi_parser_buffer);
-                        if let Some(d) = csi_parser.next() {
-                            osc_parser_buffer.push(d);
-                            let mut osc_parser = OscParser::new(&osc_parser_buffer);
-                            if let Some(e) = osc_parser.next() {
-                                println!("{:?}", &e);
-                                terminal.push(e);
-                                osc_parser_buffer.clear();
-                            }
-                            csi_parser_buffer.clear();
+        'read: loop {
+            match rx.try_recv() {
+                Ok(bytes) => {
+                    for byte in bytes {
+                        if let Some(c) = parser.push(byte) {
+                            terminal.push(c);
                         }
-                        c1_parser_buffer.clear();
                     }
-                },
-                Err(TryRecvError::Empty) => {
-                    break 'readerr;
-                },
-                Err(TryRecvError::Disconnected) => {
-                    break 'readerr;
-                },
+                }
+                Err(TryRecvError::Empty) => break 'read,
+                Err(TryRecvError::Disconnected) => break 'running,
             }
         }
-        'readout: loop {
-            match stdout_reader.try_recv() {
-                Ok(key) => {
-                    err_c1_parser_buffer.push(key);
-                    let mut c1_parser = Parser::new(&err_c1_parser_buffer);
-                    if let Some(c) = c1_parser.next() {
-                        err_csi_parser_buffer.push(c);
-                        let mut csi_parser = CsiParser::new(&err_csi_parser_buffer);
-                        if let Some(d) = csi_parser.next() {
-                            err_osc_parser_buffer.push(d);
-                            let mut err_osc_parser = OscParser::new(&err_osc_parser_buffer);
-                            if let Some(e) = err_osc_parser.next() {
-                                println!("{:?}", &e);
-                                terminal.push(e);
-                                err_osc_parser_buffer.clear();
-                            }
-                            err_csi_parser_buffer.clear();
-                        }
-                        err_c1_parser_buffer.clear();
-                    }
-                },
-                Err(TryRecvError::Empty) => {
-                    break 'readout;
-                },
-                Err(TryRecvError::Disconnected) => {
-                    break 'readout;
-                },
-            }
+
+        if CHILD_EXITED.load(Ordering::Relaxed) && bash.try_wait().unwrap().is_some() {
+            break 'running;
         }
 
         terminal.draw(&mut display);
+        window.update(&display);
 
         for event in window.events() {
             match event {
-                SimulatorEvent::Quit => { nix::sys::signal::raise(nix::sys::signal::Signal::SIGKILL); },
+                SimulatorEvent::Quit => {
+                    let _ = kill(Pid::from_raw(bash.id() as i32), Signal::SIGHUP);
+                    break 'running;
+                }
                 SimulatorEvent::KeyDown { keycode, keymod, .. } => {
-                   let b = (keycode.into_i32() & 0xff) as u8;
-                   let k = match (b, keymod) {
-                       (39, embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => 34,
-                       (92, embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => 124,
-                       (b'4', embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => 36,
-                       (b'a'..b'z', embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => b - 32,
-                       _ => b
-                   };
+                    let b = (keycode.into_i32() & 0xff) as u8;
+                    let k = match (b, keymod) {
+                        (39, embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => 34,
+                        (92, embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => 124,
+                        (b'4', embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => 36,
+                        (b'a'..=b'z', embedded_graphics_simulator::sdl2::Mod::LSHIFTMOD) => b - 32,
+                        _ => b,
+                    };
 
                     match core::ascii::Char::from_u8(k) {
-                        Some(ascii_char) => {
-                            bash.stdin.as_mut().unwrap().write(&[k]);
-                        },
+                        Some(_) => {
+                            if master.write_all(&[k]).is_err() {
+                                break 'running;
+                            }
+                        }
                         None => {
                             println!("{:?}", b);
                         }
                     }
-                },
+                }
                 _ => {}
             }
         }
     }
 
-    Ok(())
+    let _ = bash.wait();
 }
@@ -8,6 +8,8 @@ pub struct Parser<'a> {
     c1: Iter<'a, crate::Character>,
     csi: bool,
     argument: Option<usize>,
+    private: bool,
+    intermediate: Option<Char>,
 }
 
 impl<'a> Parser<'a> {
@@ -16,6 +18,8 @@ impl<'a> Parser<'a> {
             c1: c1.iter(),
             csi: false,
             argument: None,
+            private: false,
+            intermediate: None,
         }
     }
 }
@@ -49,6 +53,11 @@ pub enum ControlSequenceIntroducer {
     DisableAlternativeScreen,
     EnableBracketedPaste,
     DisableBracketedPaste,
+    SetCursorStyle(crate::cursor::CursorStyle),
+    EnableMouseTracking(crate::input::MouseTracking),
+    DisableMouseTracking,
+    EnableSgrMouse,
+    DisableSgrMouse,
 }
 
 impl Iterator for Parser<'_> {
@@ -248,6 +257,52 @@ impl Iterator for Parser<'_> {
                                         ));
                                     }
                                 }
+                                Char::QuestionMark => {
+                                    self.private = true;
+                                }
+                                Char::Space => {
+                                    self.intermediate = Some(Char::Space);
+                                }
+                                Char::SmallQ => {
+                                    let argument = self.argument;
+                                    let intermediate = self.intermediate;
+                                    self.csi = false;
+                                    self.argument = None;
+                                    if intermediate == Some(Char::Space) {
+                                        if let Ok(style) = crate::cursor::CursorStyle::try_from(argument.unwrap_or(0)) {
+                                            return Some(Character::ControlSequenceIntroducer(
+                                                ControlSequenceIntroducer::SetCursorStyle(style),
+                                            ));
+                                        }
+                                    }
+                                }
+                                h @ (Char::SmallH | Char::SmallL) => {
+                                    let argument = self.argument;
+                                    let private = self.private;
+                                    self.csi = false;
+                                    self.argument = None;
+                                    if private {
+                                        let set = h == Char::SmallH;
+                                        let mode = match argument {
+                                            Some(25) if set => ControlSequenceIntroducer::ShowCursor,
+                                            Some(25) => ControlSequenceIntroducer::HideCursor,
+                                            Some(1000) if set => ControlSequenceIntroducer::EnableMouseTracking(crate::input::MouseTracking::Normal),
+                                            Some(1002) if set => ControlSequenceIntroducer::EnableMouseTracking(crate::input::MouseTracking::ButtonEvent),
+                                            Some(1003) if set => ControlSequenceIntroducer::EnableMouseTracking(crate::input::MouseTracking::AnyEvent),
+                                            Some(1000 | 1002 | 1003) => ControlSequenceIntroducer::DisableMouseTracking,
+                                            Some(1004) if set => ControlSequenceIntroducer::EnableFocusReporting,
+                                            Some(1004) => ControlSequenceIntroducer::DisableFocusReporting,
+                                            Some(1006) if set => ControlSequenceIntroducer::EnableSgrMouse,
+                                            Some(1006) => ControlSequenceIntroducer::DisableSgrMouse,
+                                            Some(1049) if set => ControlSequenceIntroducer::EnableAlternativeScreen,
+                                            Some(1049) => ControlSequenceIntroducer::DisableAlternativeScreen,
+                                            Some(2004) if set => ControlSequenceIntroducer::EnableBracketedPaste,
+                                            Some(2004) => ControlSequenceIntroducer::DisableBracketedPaste,
+                                            _ => continue,
+                                        };
+                                        return Some(Character::ControlSequenceIntroducer(mode));
+                                    }
+                                }
                                 Char::SmallS => {
                                     self.csi = false;
                                     self.argument = None;
@@ -289,6 +344,8 @@ impl Iterator for Parser<'_> {
                         crate::Character::C1Escape(c1) => {
                             if c1 == C1Escape::ControlSequenceIntroducer {
                                 self.csi = true;
+                                self.private = false;
+                                self.intermediate = None;
                             } else {
                                 return Some(Character::C1Escape(c1));
                             }
@@ -366,4 +423,58 @@ mod test {
         ));
         assert_eq!(parser.next(), None);
     }
+
+    #[test]
+    fn test_cursor_style() {
+        let c1 = [
+            Character::C1Escape(
+                C1Escape::ControlSequenceIntroducer
+            ),
+            Character::Char(
+                core::ascii::Char::Digit4
+            ),
+            Character::Char(
+                core::ascii::Char::Space
+            ),
+            Character::Char(
+                core::ascii::Char::SmallQ
+            ),
+            Character::C1Escape(
+                C1Escape::ControlSequenceIntroducer
+            ),
+            Character::Char(
+                core::ascii::Char::QuestionMark
+            ),
+            Character::Char(
+                core::ascii::Char::Digit2
+            ),
+            Character::Char(
+                core::ascii::Char::Digit5
+            ),
+            Character::Char(
+                core::ascii::Char::SmallL
+            ),
+            Character::C1Escape(
+                C1Escape::ControlSequenceIntroducer
+            ),
+            Character::Char(
+                core::ascii::Char::Space
+            ),
+            Character::Char(
+                core::ascii::Char::SmallQ
+            ),
+        ];
+
+        let mut parser = Parser::new(&c1);
+        assert_eq!(parser.next().unwrap(), crate::csi::Character::ControlSequenceIntroducer(
+            super::ControlSequenceIntroducer::SetCursorStyle(crate::cursor::CursorStyle::SteadyUnderline)
+        ));
+        assert_eq!(parser.next().unwrap(), crate::csi::Character::ControlSequenceIntroducer(
+            super::ControlSequenceIntroducer::HideCursor
+        ));
+        assert_eq!(parser.next().unwrap(), crate::csi::Character::ControlSequenceIntroducer(
+            super::ControlSequenceIntroducer::SetCursorStyle(crate::cursor::CursorStyle::BlinkingBlock)
+        ));
+        assert_eq!(parser.next(), None);
+    }
 }
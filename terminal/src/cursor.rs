@@ -0,0 +1,135 @@
+use embedded_graphics::{geometry::{Point, Size}, mono_font::MonoFont, primitives::Rectangle};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum CursorStyle {
+    #[default]
+    BlinkingBlock,
+    SteadyBlock,
+    BlinkingUnderline,
+    SteadyUnderline,
+    BlinkingBar,
+    SteadyBar,
+}
+
+// DECSCUSR: CSI Ps SP q
+impl TryFrom<usize> for CursorStyle {
+    type Error = usize;
+
+    fn try_from(argument: usize) -> Result<Self, Self::Error> {
+        match argument {
+            0 | 1 => Ok(CursorStyle::BlinkingBlock),
+            2 => Ok(CursorStyle::SteadyBlock),
+            3 => Ok(CursorStyle::BlinkingUnderline),
+            4 => Ok(CursorStyle::SteadyUnderline),
+            5 => Ok(CursorStyle::BlinkingBar),
+            6 => Ok(CursorStyle::SteadyBar),
+            a => Err(a),
+        }
+    }
+}
+
+impl CursorStyle {
+    pub fn blinking(&self) -> bool {
+        matches!(
+            self,
+            CursorStyle::BlinkingBlock | CursorStyle::BlinkingUnderline | CursorStyle::BlinkingBar
+        )
+    }
+
+    // area of the cell at `top_left` that gets inverted
+    pub fn area(&self, top_left: Point, font: &MonoFont) -> Rectangle {
+        let size = font.character_size;
+        match self {
+            CursorStyle::BlinkingBlock | CursorStyle::SteadyBlock => Rectangle::new(top_left, size),
+            CursorStyle::BlinkingUnderline | CursorStyle::SteadyUnderline => {
+                let height = font.underline.height.max(2);
+                Rectangle::new(
+                    top_left + Point::new(0, size.height.saturating_sub(height) as i32),
+                    Size::new(size.width, height),
+                )
+            }
+            CursorStyle::BlinkingBar | CursorStyle::SteadyBar => {
+                Rectangle::new(top_left, Size::new(2, size.height))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub enum RefreshMode {
+    #[default]
+    Immediate,
+    // e-paper: cursor is left out of `draw` and only painted by `idle`, never blinks
+    Epd,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Cursor {
+    pub style: CursorStyle,
+    pub visible: bool,
+    pub mode: RefreshMode,
+    blink_on: bool,
+    drawn: bool,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            style: CursorStyle::default(),
+            visible: true,
+            mode: RefreshMode::default(),
+            blink_on: true,
+            drawn: false,
+        }
+    }
+}
+
+impl Cursor {
+    pub fn blink(&mut self) {
+        self.blink_on = !self.blink_on;
+    }
+
+    pub fn shown(&self) -> bool {
+        self.visible && (self.mode == RefreshMode::Epd || !self.style.blinking() || self.blink_on)
+    }
+
+    pub fn drawn(&self) -> bool {
+        self.drawn
+    }
+
+    pub fn set_drawn(&mut self, drawn: bool) {
+        self.drawn = drawn;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cursor, CursorStyle, RefreshMode};
+
+    #[test]
+    fn test_decscusr() {
+        assert_eq!(CursorStyle::try_from(0), Ok(CursorStyle::BlinkingBlock));
+        assert_eq!(CursorStyle::try_from(1), Ok(CursorStyle::BlinkingBlock));
+        assert_eq!(CursorStyle::try_from(4), Ok(CursorStyle::SteadyUnderline));
+        assert_eq!(CursorStyle::try_from(6), Ok(CursorStyle::SteadyBar));
+        assert_eq!(CursorStyle::try_from(7), Err(7));
+    }
+
+    #[test]
+    fn test_blink() {
+        let mut cursor = Cursor::default();
+        assert!(cursor.shown());
+        cursor.blink();
+        assert!(!cursor.shown());
+
+        cursor.mode = RefreshMode::Epd;
+        assert!(cursor.shown());
+
+        cursor.mode = RefreshMode::Immediate;
+        cursor.style = CursorStyle::SteadyBar;
+        assert!(cursor.shown());
+
+        cursor.visible = false;
+        assert!(!cursor.shown());
+    }
+}
@@ -0,0 +1,109 @@
+use core::convert::Infallible;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+pub const WIDTH: usize = 800;
+pub const HEIGHT: usize = 300;
+
+// The display in one bit a pixel, for e-paper: rows top to bottom, each
+// from the top bit of its first byte, as the panel's controller takes them.
+// Light colours are On, set bits, and dark ones Off.
+pub struct Framebuffer {
+    bytes: [u8; WIDTH * HEIGHT / 8],
+}
+
+impl Framebuffer {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; WIDTH * HEIGHT / 8],
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                continue;
+            };
+            if x >= WIDTH || y >= HEIGHT {
+                continue;
+            }
+            let bit = y * WIDTH + x;
+            let mask = 0x80 >> (bit % 8);
+            match color {
+                BinaryColor::On => self.bytes[bit / 8] |= mask,
+                BinaryColor::Off => self.bytes[bit / 8] &= !mask,
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.bytes.fill(if color.is_on() { 0xff } else { 0 });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Framebuffer, WIDTH};
+    use crate::cursor::RefreshMode;
+    use crate::stream::Parser;
+    use crate::Terminal;
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+    #[test]
+    fn test_pixels() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.clear(BinaryColor::On).unwrap();
+        let pixels = [(0, 0), (9, 0), (799, 1), (-1, 0), (800, 0), (0, 300)];
+        for (x, y) in pixels {
+            Pixel(Point::new(x, y), BinaryColor::Off).draw(&mut framebuffer).unwrap();
+        }
+        let bytes = framebuffer.bytes();
+        assert_eq!(bytes[..2], [0x7f, 0xbf]);
+        assert_eq!(bytes[2 * WIDTH / 8 - 1], 0xfe);
+        assert!(bytes[2..2 * WIDTH / 8 - 1].iter().chain(&bytes[2 * WIDTH / 8..]).all(|byte| *byte == 0xff));
+    }
+
+    #[test]
+    fn test_terminal() {
+        let mut terminal = Terminal::new();
+        terminal.set_refresh_mode(RefreshMode::Epd);
+        let mut parser = Parser::new();
+        for byte in b"Hi" {
+            if let Some(c) = parser.push(*byte) {
+                terminal.push(c);
+            }
+        }
+        let mut framebuffer = Framebuffer::new();
+        terminal.draw(&mut framebuffer);
+        // text in the first two cells only
+        let inked = |x: usize, y: usize| framebuffer.bytes()[(y * WIDTH + x) / 8] & (0x80 >> (x % 8)) != 0;
+        assert!((0..15).any(|y| (0..18).any(|x| inked(x, y))));
+        assert!(!(0..300).any(|y| (18..WIDTH).any(|x| inked(x, y))));
+    }
+}
@@ -0,0 +1,49 @@
+use core::ascii::Char;
+use embedded_graphics::pixelcolor::Rgb888;
+
+// 800x300 display with 9px columns and 15px lines
+pub const COLUMNS: usize = 89;
+pub const ROWS: usize = 20;
+// lines past the bottom of the display are kept for copy mode
+pub const SCROLLBACK: usize = 80;
+pub const LINES: usize = ROWS + SCROLLBACK;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Cell {
+    pub character: Char,
+    pub foreground: Rgb888,
+    pub background: Rgb888,
+    pub bold: bool,
+}
+
+pub struct Grid {
+    cells: [[Option<Cell>; COLUMNS]; LINES],
+}
+
+impl Grid {
+    pub fn new() -> Self {
+        Self {
+            cells: [[None; COLUMNS]; LINES],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = [[None; COLUMNS]; LINES];
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> Option<Cell> {
+        *self.cells.get(row)?.get(column)?
+    }
+
+    pub fn set(&mut self, column: usize, row: usize, cell: Cell) {
+        if let Some(c) = self.cells.get_mut(row).and_then(|r| r.get_mut(column)) {
+            *c = Some(cell);
+        }
+    }
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
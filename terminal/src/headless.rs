@@ -0,0 +1,166 @@
+use core::fmt::Write;
+use std::{path::Path, string::String};
+
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+use embedded_graphics_simulator::{OutputSettings, SimulatorDisplay};
+
+use crate::grid::{Cell, COLUMNS, ROWS};
+use crate::Terminal;
+
+// Renders a terminal without opening a window, for regression tests and for
+// machines without SDL2
+pub struct Headless {
+    display: SimulatorDisplay<Rgb888>,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Self {
+            display: SimulatorDisplay::with_default_color(Size::new(800, 300), Rgb888::BLACK),
+        }
+    }
+
+    pub fn render(&mut self, terminal: &mut Terminal) {
+        terminal.draw(&mut self.display);
+    }
+
+    pub fn display(&self) -> &SimulatorDisplay<Rgb888> {
+        &self.display
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.display
+            .to_rgb_output_image(&OutputSettings::default())
+            .save_png(path)
+            .map_err(std::io::Error::other)
+    }
+
+    // Compares against a PNG saved earlier with `save_png`
+    pub fn matches_png<P: AsRef<Path>>(&self, path: P) -> std::io::Result<bool> {
+        let expected = SimulatorDisplay::<Rgb888>::load_png(path).map_err(std::io::Error::other)?;
+        Ok(self.display.diff(&expected).is_none())
+    }
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Text of each visible row, each followed by runs of cells sharing colours
+// and weight, e.g.
+//
+//  0|hello
+//   |0-4 fg=#cdcd00 bg=#000000
+pub fn dump(terminal: &Terminal) -> String {
+    let grid = terminal.grid();
+    let mut out = String::new();
+    let last_row = (0..ROWS)
+        .rev()
+        .find(|row| (0..COLUMNS).any(|column| grid.get(column, *row).is_some()));
+
+    for row in 0..last_row.map_or(0, |row| row + 1) {
+        let cells: std::vec::Vec<Option<Cell>> = (0..COLUMNS).map(|column| grid.get(column, row)).collect();
+        let width = cells.iter().rposition(Option::is_some).map_or(0, |column| column + 1);
+
+        let _ = write!(out, "{:2}|", row);
+        for cell in &cells[..width] {
+            out.push(cell.map_or(' ', |cell| cell.character.to_char()));
+        }
+        out.push('\n');
+
+        let mut start = 0;
+        while start < width {
+            let attributes = cells[start].map(|cell| (cell.foreground, cell.background, cell.bold));
+            let end = (start..width)
+                .take_while(|column| cells[*column].map(|cell| (cell.foreground, cell.background, cell.bold)) == attributes)
+                .last()
+                .unwrap_or(start);
+            if let Some((foreground, background, bold)) = attributes {
+                let _ = writeln!(
+                    out,
+                    "  |{}-{} fg=#{:02x}{:02x}{:02x} bg=#{:02x}{:02x}{:02x}{}",
+                    start,
+                    end,
+                    foreground.r(),
+                    foreground.g(),
+                    foreground.b(),
+                    background.r(),
+                    background.g(),
+                    background.b(),
+                    if bold { " bold" } else { "" }
+                );
+            }
+            start = end + 1;
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dump, Headless};
+    use crate::Terminal;
+    use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+
+    fn terminal(bytes: &[u8]) -> Terminal<'static> {
+        let mut terminal = Terminal::new();
+        let mut parser = crate::stream::Parser::new();
+        for byte in bytes {
+            if let Some(c) = parser.push(*byte) {
+                terminal.push(c);
+            }
+        }
+        terminal
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut terminal = terminal(b"ab\x1b[31mcd\x1b[1me\x1b[0m\nf");
+        let mut headless = Headless::new();
+        headless.render(&mut terminal);
+
+        assert_eq!(
+            dump(&terminal),
+            " 0|abcde\n  \
+             |0-1 fg=#ffffff bg=#000000\n  \
+             |2-3 fg=#cd0000 bg=#000000\n  \
+             |4-4 fg=#cd0000 bg=#000000 bold\n \
+             1|f\n  \
+             |0-0 fg=#ffffff bg=#000000\n"
+        );
+    }
+
+    #[test]
+    fn test_palette() {
+        let mut terminal = terminal(b"\x1b[44m \x1b[49m\x1b[102m ");
+        terminal.set_refresh_mode(crate::cursor::RefreshMode::Epd);
+        let mut headless = Headless::new();
+        headless.render(&mut terminal);
+
+        assert_eq!(headless.display().get_pixel(Point::new(4, 8)), Rgb888::new(0, 0, 238));
+        assert_eq!(headless.display().get_pixel(Point::new(13, 8)), Rgb888::new(0, 252, 0));
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut terminal = terminal(b"hello \x1b[7mworld");
+        let mut headless = Headless::new();
+        headless.render(&mut terminal);
+
+        // one file per test process, so runs in other checkouts can't clash
+        let name = std::format!("terminal-headless-round-trip-{}.png", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        headless.save_png(&path).unwrap();
+        let matches = headless.matches_png(&path).unwrap();
+
+        let mut other = Headless::new();
+        other.render(&mut Terminal::new());
+        let other_matches = other.matches_png(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(matches);
+        assert!(!other_matches);
+    }
+}
@@ -0,0 +1,307 @@
+use core::fmt::Write;
+use heapless::Vec;
+
+use crate::grid::{COLUMNS, ROWS};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum MouseTracking {
+    // 1000: presses, releases and wheel
+    Normal,
+    // 1002: as above, plus motion while a button is held
+    ButtonEvent,
+    // 1003: as above, plus all motion
+    AnyEvent,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    Motion(Option<MouseButton>),
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub meta: bool,
+    pub control: bool,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    // zero-based cell
+    pub column: usize,
+    pub row: usize,
+    pub modifiers: Modifiers,
+}
+
+impl MouseEvent {
+    pub fn new(kind: MouseEventKind, column: usize, row: usize) -> Self {
+        Self {
+            kind,
+            column,
+            row,
+            modifiers: Modifiers::default(),
+        }
+    }
+
+    fn button_code(&self) -> usize {
+        let code = match self.kind {
+            MouseEventKind::Press(b) | MouseEventKind::Release(b) | MouseEventKind::Motion(Some(b)) => b as usize,
+            MouseEventKind::Motion(None) => 3,
+            MouseEventKind::WheelUp => 64,
+            MouseEventKind::WheelDown => 65,
+        };
+        let motion = if matches!(self.kind, MouseEventKind::Motion(_)) { 32 } else { 0 };
+
+        code + motion
+            + if self.modifiers.shift { 4 } else { 0 }
+            + if self.modifiers.meta { 8 } else { 0 }
+            + if self.modifiers.control { 16 } else { 0 }
+    }
+}
+
+// Turns local input into the bytes a host application expects, following
+// whatever modes the host has requested
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct Encoder {
+    mouse_tracking: Option<MouseTracking>,
+    sgr_mouse: bool,
+    bracketed_paste: bool,
+}
+
+impl Encoder {
+    pub fn update(&mut self, csi: crate::csi::ControlSequenceIntroducer) {
+        match csi {
+            crate::csi::ControlSequenceIntroducer::EnableMouseTracking(tracking) => {
+                self.mouse_tracking = Some(tracking);
+            }
+            crate::csi::ControlSequenceIntroducer::DisableMouseTracking => {
+                self.mouse_tracking = None;
+            }
+            crate::csi::ControlSequenceIntroducer::EnableSgrMouse => {
+                self.sgr_mouse = true;
+            }
+            crate::csi::ControlSequenceIntroducer::DisableSgrMouse => {
+                self.sgr_mouse = false;
+            }
+            crate::csi::ControlSequenceIntroducer::EnableBracketedPaste => {
+                self.bracketed_paste = true;
+            }
+            crate::csi::ControlSequenceIntroducer::DisableBracketedPaste => {
+                self.bracketed_paste = false;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn mouse_tracking(&self) -> Option<MouseTracking> {
+        self.mouse_tracking
+    }
+
+    pub fn mouse(&self, event: MouseEvent) -> Option<Vec<u8, 32>> {
+        let tracking = self.mouse_tracking?;
+        match (event.kind, tracking) {
+            (MouseEventKind::Motion(None), MouseTracking::Normal | MouseTracking::ButtonEvent) => return None,
+            (MouseEventKind::Motion(Some(_)), MouseTracking::Normal) => return None,
+            _ => {}
+        }
+
+        let mut bytes: Vec<u8, 32> = Vec::new();
+        let column = event.column + 1;
+        let row = event.row + 1;
+        if self.sgr_mouse {
+            let final_byte = if matches!(event.kind, MouseEventKind::Release(_)) { 'm' } else { 'M' };
+            write!(
+                Writer(&mut bytes),
+                "\x1b[<{};{};{}{}",
+                event.button_code(),
+                column,
+                row,
+                final_byte
+            )
+            .ok()?;
+        } else {
+            // X10 encoding: releases do not say which button, and coordinates
+            // past 223 do not fit in a byte
+            let code = match event.kind {
+                MouseEventKind::Release(_) => 3 + event.button_code() - event.button_code() % 4,
+                _ => event.button_code(),
+            };
+            if column > 223 || row > 223 {
+                return None;
+            }
+            bytes.extend_from_slice(&[0x1b, b'[', b'M']).ok()?;
+            bytes.push((32 + code) as u8).ok()?;
+            bytes.push((32 + column) as u8).ok()?;
+            bytes.push((32 + row) as u8).ok()?;
+        }
+
+        Some(bytes)
+    }
+
+    // escapes are dropped from the pasted text so that it cannot end the
+    // bracketed paste early
+    pub fn paste<W: Write>(&self, text: &str, out: &mut W) -> core::fmt::Result {
+        if self.bracketed_paste {
+            out.write_str("\x1b[200~")?;
+        }
+        for c in text.chars().filter(|c| *c != '\x1b') {
+            out.write_char(c)?;
+        }
+        if self.bracketed_paste {
+            out.write_str("\x1b[201~")?;
+        }
+        Ok(())
+    }
+}
+
+struct Writer<'a, const N: usize>(&'a mut Vec<u8, N>);
+
+impl<const N: usize> Write for Writer<'_, N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0.extend_from_slice(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+// Accumulates relative movement from a trackpoint or similar into a cell on
+// the grid, so that only movement across cell boundaries is reported
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default)]
+pub struct Pointer {
+    x: i32,
+    y: i32,
+    held: Option<MouseButton>,
+}
+
+impl Pointer {
+    pub fn cell(&self) -> (usize, usize) {
+        ((self.x / 9) as usize, (self.y / 15) as usize)
+    }
+
+    pub fn move_by(&mut self, dx: i32, dy: i32) -> Option<MouseEvent> {
+        let before = self.cell();
+        self.x = (self.x + dx).clamp(0, COLUMNS as i32 * 9 - 1);
+        self.y = (self.y + dy).clamp(0, ROWS as i32 * 15 - 1);
+        let (column, row) = self.cell();
+        if (column, row) == before {
+            None
+        } else {
+            Some(MouseEvent::new(MouseEventKind::Motion(self.held), column, row))
+        }
+    }
+
+    pub fn press(&mut self, button: MouseButton) -> MouseEvent {
+        self.held = Some(button);
+        let (column, row) = self.cell();
+        MouseEvent::new(MouseEventKind::Press(button), column, row)
+    }
+
+    pub fn release(&mut self, button: MouseButton) -> MouseEvent {
+        self.held = None;
+        let (column, row) = self.cell();
+        MouseEvent::new(MouseEventKind::Release(button), column, row)
+    }
+
+    // One event per step, positive steps scrolling up as with a clockwise
+    // rotary encoder, and none for no steps
+    pub fn wheel(&self, steps: i32) -> impl Iterator<Item = MouseEvent> {
+        let (column, row) = self.cell();
+        let kind = if steps > 0 { MouseEventKind::WheelUp } else { MouseEventKind::WheelDown };
+        core::iter::repeat_n(MouseEvent::new(kind, column, row), steps.unsigned_abs() as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Encoder, MouseButton, MouseEvent, MouseEventKind, MouseTracking, Pointer};
+    use crate::csi::ControlSequenceIntroducer;
+
+    #[test]
+    fn test_untracked() {
+        let encoder = Encoder::default();
+        assert_eq!(encoder.mouse(MouseEvent::new(MouseEventKind::WheelUp, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_x10() {
+        let mut encoder = Encoder::default();
+        encoder.update(ControlSequenceIntroducer::EnableMouseTracking(MouseTracking::Normal));
+
+        let press = encoder.mouse(MouseEvent::new(MouseEventKind::Press(MouseButton::Left), 2, 4)).unwrap();
+        assert_eq!(&press[..], b"\x1b[M #%");
+        let release = encoder.mouse(MouseEvent::new(MouseEventKind::Release(MouseButton::Right), 2, 4)).unwrap();
+        assert_eq!(&release[..], b"\x1b[M##%");
+        let wheel = encoder.mouse(MouseEvent::new(MouseEventKind::WheelDown, 0, 0)).unwrap();
+        assert_eq!(&wheel[..], b"\x1b[Ma!!");
+        assert_eq!(encoder.mouse(MouseEvent::new(MouseEventKind::Motion(Some(MouseButton::Left)), 0, 0)), None);
+        assert_eq!(encoder.mouse(MouseEvent::new(MouseEventKind::Press(MouseButton::Left), 300, 0)), None);
+    }
+
+    #[test]
+    fn test_sgr() {
+        let mut encoder = Encoder::default();
+        encoder.update(ControlSequenceIntroducer::EnableMouseTracking(MouseTracking::ButtonEvent));
+        encoder.update(ControlSequenceIntroducer::EnableSgrMouse);
+
+        let press = encoder.mouse(MouseEvent::new(MouseEventKind::Press(MouseButton::Left), 2, 4)).unwrap();
+        assert_eq!(&press[..], b"\x1b[<0;3;5M");
+        let release = encoder.mouse(MouseEvent::new(MouseEventKind::Release(MouseButton::Right), 299, 4)).unwrap();
+        assert_eq!(&release[..], b"\x1b[<2;300;5m");
+        let drag = encoder.mouse(MouseEvent::new(MouseEventKind::Motion(Some(MouseButton::Left)), 0, 0)).unwrap();
+        assert_eq!(&drag[..], b"\x1b[<32;1;1M");
+        assert_eq!(encoder.mouse(MouseEvent::new(MouseEventKind::Motion(None), 0, 0)), None);
+
+        encoder.update(ControlSequenceIntroducer::EnableMouseTracking(MouseTracking::AnyEvent));
+        let motion = encoder.mouse(MouseEvent::new(MouseEventKind::Motion(None), 0, 0)).unwrap();
+        assert_eq!(&motion[..], b"\x1b[<35;1;1M");
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut encoder = Encoder::default();
+        let mut text: heapless::String<32> = heapless::String::new();
+        encoder.paste("ls\x1b", &mut text).unwrap();
+        assert_eq!(text, "ls");
+
+        encoder.update(ControlSequenceIntroducer::EnableBracketedPaste);
+        text.clear();
+        encoder.paste("ls\x1b[201~", &mut text).unwrap();
+        assert_eq!(text, "\x1b[200~ls[201~\x1b[201~");
+    }
+
+    #[test]
+    fn test_pointer() {
+        let mut pointer = Pointer::default();
+        assert_eq!(pointer.move_by(4, 0), None);
+        assert_eq!(
+            pointer.move_by(5, 0),
+            Some(MouseEvent::new(MouseEventKind::Motion(None), 1, 0))
+        );
+        pointer.press(MouseButton::Left);
+        assert_eq!(
+            pointer.move_by(0, 15),
+            Some(MouseEvent::new(MouseEventKind::Motion(Some(MouseButton::Left)), 1, 1))
+        );
+        assert_eq!(pointer.move_by(-1000, -1000), Some(MouseEvent::new(MouseEventKind::Motion(Some(MouseButton::Left)), 0, 0)));
+    }
+
+    #[test]
+    fn test_wheel() {
+        let pointer = Pointer::default();
+        assert_eq!(pointer.wheel(0).count(), 0);
+        let mut up = pointer.wheel(1);
+        assert_eq!(up.next().map(|event| event.kind), Some(MouseEventKind::WheelUp));
+        assert_eq!(up.next(), None);
+        assert_eq!(pointer.wheel(-3).filter(|event| event.kind == MouseEventKind::WheelDown).count(), 3);
+    }
+}
@@ -2,12 +2,13 @@
 #![feature(ascii_char)]
 #![feature(ascii_char_variants)]
 
+#[cfg(feature = "std")]
 extern crate std;
 use core::fmt::Debug;
 
 use embedded_graphics::pixelcolor::Rgb888;
 use embedded_graphics::{
-    draw_target::DrawTarget, mono_font::MonoTextStyle, prelude::*, text::Text,
+    draw_target::DrawTarget, mono_font::{MonoTextStyle, MonoTextStyleBuilder}, prelude::*, text::Text,
 };
 use heapless::HistoryBuffer;
 use embedded_graphics::pixelcolor::PixelColor;
@@ -16,7 +17,15 @@ use crate::csi::ControlSequenceIntroducer::EraseInDisplay;
 
 pub mod c1;
 pub mod csi;
+pub mod cursor;
+pub mod framebuffer;
+pub mod grid;
+#[cfg(feature = "std")]
+pub mod headless;
+pub mod input;
+pub mod selection;
 pub mod state;
+pub mod stream;
 pub mod wrap;
 pub mod osc;
 
@@ -32,7 +41,11 @@ pub enum Character {
 pub struct Terminal<'a> {
     characters: HistoryBuffer<Character, 5000>,
     state: state::State<'a>,
-    osc: Option<osc::OperatingSystemCommand>
+    osc: Option<osc::OperatingSystemCommand>,
+    grid: grid::Grid,
+    cursor: cursor::Cursor,
+    input: input::Encoder,
+    selection: Option<selection::Selection>,
 }
 
 impl <'a>Terminal<'a> {
@@ -40,10 +53,22 @@ impl <'a>Terminal<'a> {
         Self {
             characters: HistoryBuffer::new(),
             state: state::State::default(),
-            osc: None
+            osc: None,
+            grid: grid::Grid::new(),
+            cursor: cursor::Cursor::default(),
+            input: input::Encoder::default(),
+            selection: None,
         }
     }
 
+    pub fn set_refresh_mode(&mut self, mode: cursor::RefreshMode) {
+        self.cursor.mode = mode;
+    }
+
+    pub fn blink(&mut self) {
+        self.cursor.blink();
+    }
+
     pub fn clear<D, C>(&self, display: &mut D) where C: PixelColor + From<Rgb888>, D: DrawTarget<Color = C>, <D as DrawTarget>::Error: Debug {
         let style: MonoTextStyle::<C> = (&self.state).into();
         display.clear(style.background_color.unwrap()).unwrap();
@@ -52,6 +77,7 @@ impl <'a>Terminal<'a> {
     pub fn draw<D, C>(&mut self, display: &mut D) where C: PixelColor + From<Rgb888>, D: DrawTarget<Color = C>, <D as DrawTarget>::Error: Debug {
         self.state.reset_position();
         self.clear(display);
+        self.grid.clear();
 
         for c in self.characters.oldest_ordered() {
             match c {
@@ -77,10 +103,12 @@ impl <'a>Terminal<'a> {
                 },
                 Character::Char(c) => {
                     if self.osc.is_none() {
-                        let s = std::format!("{}", c);
+                        if let Some((column, row)) = self.state.cell() {
+                            self.grid.set(column, row, self.state.to_cell(*c));
+                        }
 
                         let style: MonoTextStyle::<C> = (&self.state).into();
-                        let next = Text::new(&s, self.state.position(), style)
+                        let next = Text::new(c.as_str(), self.state.position(), style)
                             .draw(display)
                             .unwrap();
 
@@ -95,6 +123,7 @@ impl <'a>Terminal<'a> {
                     csi::ControlSequenceIntroducer::EraseInDisplay(_)
                 ) => {
                     self.clear(display);
+                    self.grid.clear();
                 }
                 Character::ControlSequenceIntroducer(
                     csi::ControlSequenceIntroducer::CursorPosition(_, _)
@@ -106,6 +135,21 @@ impl <'a>Terminal<'a> {
                 ) => {
                     self.state.update(*sgr);
                 },
+                Character::ControlSequenceIntroducer(
+                    csi::ControlSequenceIntroducer::SetCursorStyle(style),
+                ) => {
+                    self.cursor.style = *style;
+                },
+                Character::ControlSequenceIntroducer(
+                    csi::ControlSequenceIntroducer::ShowCursor,
+                ) => {
+                    self.cursor.visible = true;
+                },
+                Character::ControlSequenceIntroducer(
+                    csi::ControlSequenceIntroducer::HideCursor,
+                ) => {
+                    self.cursor.visible = false;
+                },
                 Character::OperatingSystemCommand(
                     osc::OperatingSystemCommand::SetWorkingDirectory
                 ) => {
@@ -115,11 +159,112 @@ impl <'a>Terminal<'a> {
             }
 
         }
+
+        self.cursor.set_drawn(false);
+        if self.selection.is_some() {
+            self.draw_selection(display);
+        } else if self.cursor.mode == cursor::RefreshMode::Immediate && self.cursor.shown() {
+            self.draw_cursor(display);
+        }
+    }
+
+    // only paints the cursor while in EPD mode, so that a panel refresh is not
+    // triggered for every cursor movement
+    pub fn idle<D, C>(&mut self, display: &mut D) where C: PixelColor + From<Rgb888>, D: DrawTarget<Color = C>, <D as DrawTarget>::Error: Debug {
+        if self.cursor.mode == cursor::RefreshMode::Epd && !self.cursor.drawn() && self.cursor.shown() && self.selection.is_none() {
+            self.draw_cursor(display);
+        }
+    }
+
+    // inverts the cell under the cursor
+    pub fn draw_cursor<D, C>(&mut self, display: &mut D) where C: PixelColor + From<Rgb888>, D: DrawTarget<Color = C>, <D as DrawTarget>::Error: Debug {
+        if let Some((column, row)) = self.state.cell() {
+            self.invert(display, column, row, self.cursor.style);
+        }
+        self.cursor.set_drawn(true);
+    }
+
+    // inverts the part of a cell covered by `style`
+    fn invert<D, C>(&self, display: &mut D, column: usize, row: usize, style: cursor::CursorStyle) where C: PixelColor + From<Rgb888>, D: DrawTarget<Color = C>, <D as DrawTarget>::Error: Debug {
+        let cell = self.grid.get(column, row);
+        let (foreground, background, bold) = match cell {
+            Some(cell) => (cell.foreground, cell.background, cell.bold),
+            None => {
+                let style: MonoTextStyle::<Rgb888> = (&self.state).into();
+                (style.text_color.unwrap(), style.background_color.unwrap(), self.state.bold())
+            }
+        };
+        let font = self.state.font(bold);
+        let position = Point::new(column as i32 * 9, 14 + row as i32 * 15);
+        let area = style.area(position - Point::new(0, font.baseline as i32), font);
+
+        let mut clipped = display.clipped(&area);
+        clipped.fill_solid(&area, foreground.into()).unwrap();
+        if let Some(cell) = cell {
+            let style = MonoTextStyleBuilder::new()
+                .font(font)
+                .text_color(background.into())
+                .build();
+            Text::new(cell.character.as_str(), position, style)
+                .draw(&mut clipped)
+                .unwrap();
+        }
+    }
+
+    pub fn enter_copy_mode(&mut self) {
+        let (column, row) = self.state.cell().unwrap_or((0, 0));
+        self.selection = Some(selection::Selection::new(column, row));
+    }
+
+    pub fn exit_copy_mode(&mut self) {
+        self.selection = None;
+    }
+
+    pub fn selection_mut(&mut self) -> Option<&mut selection::Selection> {
+        self.selection.as_mut()
+    }
+
+    pub fn copy<W: core::fmt::Write>(&self, out: &mut W) -> core::fmt::Result {
+        match &self.selection {
+            Some(selection) => selection.copy(&self.grid, out),
+            None => Ok(()),
+        }
+    }
+
+    // highlights the selection and shows the copy mode cursor in place of
+    // the terminal cursor
+    fn draw_selection<D, C>(&mut self, display: &mut D) where C: PixelColor + From<Rgb888>, D: DrawTarget<Color = C>, <D as DrawTarget>::Error: Debug {
+        let Some(selection) = self.selection else {
+            return;
+        };
+        if let Some((start, end)) = selection.range() {
+            for row in start.1..=end.1.min(grid::ROWS - 1) {
+                let first = if row == start.1 { start.0 } else { 0 };
+                let last = if row == end.1 { end.0 } else { grid::COLUMNS - 1 };
+                for column in first..=last {
+                    self.invert(display, column, row, cursor::CursorStyle::SteadyBlock);
+                }
+            }
+        }
+
+        let (column, row) = selection.cursor();
+        self.invert(display, column, row, cursor::CursorStyle::SteadyUnderline);
     }
 
     pub fn push(&mut self, c: Character) {
+        if let Character::ControlSequenceIntroducer(csi) = c {
+            self.input.update(csi);
+        }
         self.characters.write(c);
     }
+
+    pub fn grid(&self) -> &grid::Grid {
+        &self.grid
+    }
+
+    pub fn input(&self) -> &input::Encoder {
+        &self.input
+    }
 }
 
 #[cfg(test)]
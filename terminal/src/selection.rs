@@ -0,0 +1,148 @@
+use core::fmt::Write;
+
+use crate::grid::{Grid, COLUMNS, LINES};
+
+// Copy mode: a cursor that is moved around the grid independently of the
+// terminal cursor, with an optional mark.  Everything between the mark and
+// the cursor (in reading order) is selected.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Selection {
+    cursor: (usize, usize),
+    mark: Option<(usize, usize)>,
+}
+
+impl Selection {
+    pub fn new(column: usize, row: usize) -> Self {
+        Self {
+            cursor: (column.min(COLUMNS - 1), row.min(LINES - 1)),
+            mark: None,
+        }
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn move_by(&mut self, columns: isize, rows: isize) {
+        let (column, row) = self.cursor;
+        self.cursor = (
+            column.saturating_add_signed(columns).min(COLUMNS - 1),
+            row.saturating_add_signed(rows).min(LINES - 1),
+        );
+    }
+
+    pub fn mark(&mut self) {
+        self.mark = Some(self.cursor);
+    }
+
+    pub fn clear_mark(&mut self) {
+        self.mark = None;
+    }
+
+    // start and end of the selection, in reading order, as (column, row)
+    pub fn range(&self) -> Option<((usize, usize), (usize, usize))> {
+        let mark = self.mark?;
+        let (a, b) = ((mark.1, mark.0), (self.cursor.1, self.cursor.0));
+        let (start, end) = if a <= b { (a, b) } else { (b, a) };
+        Some(((start.1, start.0), (end.1, end.0)))
+    }
+
+    pub fn contains(&self, column: usize, row: usize) -> bool {
+        match self.range() {
+            Some((start, end)) => (start.1, start.0) <= (row, column) && (row, column) <= (end.1, end.0),
+            None => false,
+        }
+    }
+
+    // writes the selected text as UTF-8, one line per row with trailing
+    // blanks removed
+    pub fn copy<W: Write>(&self, grid: &Grid, out: &mut W) -> core::fmt::Result {
+        let Some((start, end)) = self.range() else {
+            return Ok(());
+        };
+
+        for row in start.1..=end.1 {
+            if row != start.1 {
+                out.write_char('\n')?;
+            }
+            let first = if row == start.1 { start.0 } else { 0 };
+            let last = if row == end.1 { end.0 } else { COLUMNS - 1 };
+            let width = (first..=last)
+                .rev()
+                .find(|column| grid.get(*column, row).is_some_and(|c| c.character != core::ascii::Char::Space))
+                .map_or(0, |column| column + 1 - first);
+
+            for column in first..first + width {
+                match grid.get(column, row) {
+                    Some(cell) => out.write_char(cell.character.to_char())?,
+                    None => out.write_char(' ')?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Selection;
+    use crate::grid::{Cell, Grid};
+    use embedded_graphics::pixelcolor::Rgb888;
+    use heapless::String;
+
+    fn grid(lines: &[&str]) -> Grid {
+        let mut grid = Grid::new();
+        for (row, line) in lines.iter().enumerate() {
+            for (column, byte) in line.bytes().enumerate() {
+                grid.set(column, row, Cell {
+                    character: core::ascii::Char::from_u8(byte).unwrap(),
+                    foreground: Rgb888::new(255, 255, 255),
+                    background: Rgb888::new(0, 0, 0),
+                    bold: false,
+                });
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn test_copy() {
+        let grid = grid(&["hello world   ", "second", "third line"]);
+        let mut selection = Selection::new(6, 0);
+        selection.mark();
+        selection.move_by(-2, 2);
+
+        let mut text: String<64> = String::new();
+        selection.copy(&grid, &mut text).unwrap();
+        assert_eq!(text, "world\nsecond\nthird");
+        assert!(selection.contains(0, 1));
+        assert!(!selection.contains(5, 0));
+        assert!(!selection.contains(5, 2));
+        assert!(selection.contains(4, 2));
+    }
+
+    #[test]
+    fn test_copy_backwards() {
+        let grid = grid(&["hello world"]);
+        let mut selection = Selection::new(4, 0);
+        selection.mark();
+        selection.move_by(-4, 0);
+
+        let mut text: String<64> = String::new();
+        selection.copy(&grid, &mut text).unwrap();
+        assert_eq!(text, "hello");
+    }
+
+    #[test]
+    fn test_no_mark() {
+        let grid = grid(&["hello world"]);
+        let mut selection = Selection::new(0, 0);
+        selection.move_by(-1, -1);
+        assert_eq!(selection.cursor(), (0, 0));
+
+        let mut text: String<64> = String::new();
+        selection.copy(&grid, &mut text).unwrap();
+        assert_eq!(text, "");
+    }
+}
@@ -23,7 +23,7 @@ pub struct State<'a> {
     position: Point,
 }
 
-impl State<'_> {
+impl<'a> State<'a> {
     pub fn update(&mut self, sgr: SelectGraphicRendition) {
         match sgr {
             SelectGraphicRendition::Reset => *self = Self::default(),
@@ -199,6 +199,29 @@ impl State<'_> {
     pub fn backspace(&mut self) {
         self.position = Point::new(self.position.x - 9, self.position.y);
     }
+
+    // column and row of the cell at the current position
+    pub fn cell(&self) -> Option<(usize, usize)> {
+        let column = usize::try_from(self.position.x / 9).ok()?;
+        let row = usize::try_from((self.position.y - 14) / 15).ok()?;
+        Some((column, row))
+    }
+
+    pub fn top_left(&self) -> Point {
+        Point::new(self.position.x, self.position.y - self.normal_font.baseline as i32)
+    }
+
+    pub fn bold(&self) -> bool {
+        self.bold
+    }
+
+    pub fn font(&self, bold: bool) -> &'a MonoFont<'a> {
+        if bold {
+            self.bold_font
+        } else {
+            self.normal_font
+        }
+    }
 }
 
 impl Default for State<'_> {
@@ -1,8 +1,21 @@
 use embedded_graphics::{mono_font::MonoTextStyle, pixelcolor::{Rgb888, PixelColor}, text::DecorationColor};
 
 use super::State;
+use crate::grid::Cell;
 use crate::state::SelectGraphicRendition;
 
+impl State<'_> {
+    pub fn to_cell(&self, character: core::ascii::Char) -> Cell {
+        let style: MonoTextStyle<Rgb888> = self.into();
+        Cell {
+            character,
+            foreground: style.text_color.unwrap(),
+            background: style.background_color.unwrap(),
+            bold: self.bold,
+        }
+    }
+}
+
 impl<'a, C> From<&'a State<'a>> for MonoTextStyle<'a, C> where C: PixelColor + From<Rgb888> {
     fn from(terminal_state: &'a State<'a>) -> Self {
         let mut foreground_color = match terminal_state.foreground {
@@ -0,0 +1,81 @@
+use heapless::Vec;
+
+use crate::Character;
+
+// Runs bytes through the c1, csi and osc parsers one at a time, holding on to
+// partial sequences between calls, so that input can be fed as it arrives
+pub struct Parser {
+    c1: Vec<u8, 8>,
+    csi: Vec<Character, 32>,
+    osc: Vec<Character, 256>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            c1: Vec::new(),
+            csi: Vec::new(),
+            osc: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Character> {
+        // sequences that outgrow a buffer are dropped rather than wedging the parser
+        if self.c1.push(byte).is_err() {
+            self.c1.clear();
+            return None;
+        }
+        let c = crate::c1::Parser::new(&self.c1).next()?;
+        self.c1.clear();
+
+        if self.csi.push(c).is_err() {
+            self.csi.clear();
+            return None;
+        }
+        let d = crate::csi::Parser::new(&self.csi).next()?;
+        self.csi.clear();
+
+        if self.osc.push(d).is_err() {
+            self.osc.clear();
+            return None;
+        }
+        let e = crate::osc::Parser::new(&self.osc).next()?;
+        self.osc.clear();
+
+        Some(e)
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Parser;
+    use crate::csi::ControlSequenceIntroducer;
+    use crate::Character;
+
+    #[test]
+    fn test_split_sequences() {
+        let mut parser = Parser::new();
+        let mut characters: heapless::Vec<Character, 10> = heapless::Vec::new();
+        for byte in b"\x1b[3J\x1b[H\x1b[2JA" {
+            if let Some(c) = parser.push(*byte) {
+                characters.push(c).unwrap();
+            }
+        }
+
+        assert_eq!(
+            characters,
+            [
+                Character::ControlSequenceIntroducer(ControlSequenceIntroducer::EraseInDisplay(3)),
+                Character::ControlSequenceIntroducer(ControlSequenceIntroducer::CursorPosition(1, 1)),
+                Character::ControlSequenceIntroducer(ControlSequenceIntroducer::EraseInDisplay(2)),
+                Character::Char(core::ascii::Char::CapitalA),
+            ]
+        );
+    }
+}